@@ -0,0 +1,27 @@
+//! Lexes a multi-megabyte source and reports the throughput. Run with
+//! `cargo bench --bench lexer`.
+
+use std::time::Instant;
+
+use uvl::Lexer;
+
+fn main() {
+    let chunk = "let mut total = 0;\n\
+                 // a comment with ünïcödé and 🦀\n\
+                 total = (total + 12.5) * 3 / 2 - 1;\n\
+                 println \"total: \" + \"🎉 done\";\n";
+    let source = chunk.repeat(4 * 1024 * 1024 / chunk.len());
+
+    let start = Instant::now();
+    let mut lex = Lexer::new(&source);
+    let token_count = lex.scan().unwrap().len();
+    let elapsed = start.elapsed();
+
+    println!(
+        "lexed {} bytes into {} tokens in {:?} ({:.1} MB/s)",
+        source.len(),
+        token_count,
+        elapsed,
+        source.len() as f64 / 1_000_000.0 / elapsed.as_secs_f64()
+    );
+}
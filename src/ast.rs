@@ -1,20 +1,46 @@
-use crate::token::Token;
+use std::rc::Rc;
 
+use crate::token::{Span, Token, TokenType};
+use crate::value::UvlValue;
+
+/// Where a node comes from. `line` and `column` are 1-based and locate the
+/// start of the node, `span` covers all of its source text.
 #[derive(Clone, Debug, Default)]
 pub struct Ctx {
     pub file: String,
     pub line: usize,
+    pub column: usize,
+    pub span: Span,
     pub module: String,
 }
 
 impl Ctx {
-    pub fn from_token(token: &Token) -> Self {
+    pub fn from_token(file: &str, token: &Token) -> Self {
         Ctx {
-            file: "main.uvl".to_string(),
+            file: file.to_string(),
             line: token.line,
+            column: token.column,
+            span: token.span,
             module: "root".to_string(),
         }
     }
+
+    /// A context starting where `self` starts and ending where `end` ends.
+    pub fn to(&self, end: &Ctx) -> Self {
+        Ctx {
+            span: self.span.to(end.span),
+            ..self.clone()
+        }
+    }
+}
+
+/// Where a variable lives, filled in by the resolver. Locals are found
+/// `depth` scopes out from the use, at index `slot` of that scope.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Binding {
+    #[default]
+    Global,
+    Local(usize, usize),
 }
 
 #[derive(Debug, Clone)]
@@ -23,19 +49,188 @@ pub enum Expr<'a> {
     Grouping(Ctx, Box<Expr<'a>>),
     Literal(Ctx, Token<'a>),
     Unary(Ctx, Token<'a>, Box<Expr<'a>>),
-    Variable(Ctx, Token<'a>),
-    Assign(Ctx, Token<'a>, Box<Expr<'a>>),
+    Variable(Ctx, Token<'a>, Binding),
+    Assign(Ctx, Token<'a>, Binding, Box<Expr<'a>>),
+    Call(Ctx, Box<Expr<'a>>, Vec<Expr<'a>>),
+    /// `receiver.name(args)`, dispatched through the impls of the
+    /// receiver's type.
+    MethodCall(Ctx, Box<Expr<'a>>, Token<'a>, Vec<Expr<'a>>),
+    /// `module.name`, an export of a module.
+    Get(Ctx, Box<Expr<'a>>, Token<'a>),
+    /// `target[index]`, dispatched to the `index` method of the target's type.
+    Index(Ctx, Box<Expr<'a>>, Box<Expr<'a>>),
+    /// A value computed at compile time, such as a folded constant.
+    Constant(Ctx, UvlValue),
 }
 
 #[derive(Debug, Clone)]
 pub struct Mutable(pub bool);
 
+/// A type annotation as written, e.g. `Number` or `fun(Number) -> Bool`.
+#[derive(Debug, Clone)]
+pub enum TypeExpr<'a> {
+    Named(Ctx, Token<'a>),
+    Function(Ctx, Vec<TypeExpr<'a>>, Box<TypeExpr<'a>>),
+}
+
+impl TypeExpr<'_> {
+    pub fn ctx(&self) -> &Ctx {
+        match self {
+            TypeExpr::Named(ctx, _) | TypeExpr::Function(ctx, ..) => ctx,
+        }
+    }
+}
+
+/// A function parameter or struct field and its optional annotation.
+#[derive(Debug, Clone)]
+pub struct Param<'a> {
+    pub name: Token<'a>,
+    pub annotation: Option<TypeExpr<'a>>,
+}
+
+/// A method declared by a trait. Methods without a body must be provided
+/// by every impl, the others are defaults.
+#[derive(Debug, Clone)]
+pub struct TraitMethod<'a> {
+    pub ctx: Ctx,
+    pub name: Token<'a>,
+    pub params: Vec<Param<'a>>,
+    pub return_type: Option<TypeExpr<'a>>,
+    pub body: Option<Rc<Vec<Stmt<'a>>>>,
+}
+
+/// Doc comment lines attached to a declaration.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Doc<'a>(pub Vec<&'a str>);
+
 #[derive(Debug, Clone)]
 pub enum Stmt<'a> {
     Expression(Ctx, Box<Expr<'a>>),
     PrintLn(Ctx, Box<Expr<'a>>),
-    Let(Ctx, Token<'a>, Mutable, Box<Expr<'a>>),
-    Block(Ctx, Vec<Box<Stmt<'a>>>),
+    Let(
+        Ctx,
+        Token<'a>,
+        Mutable,
+        Option<TypeExpr<'a>>,
+        Box<Expr<'a>>,
+        Doc<'a>,
+    ),
+    Const(Ctx, Token<'a>, Box<Expr<'a>>, Doc<'a>),
+    Block(Ctx, Vec<Stmt<'a>>),
+    If(Ctx, Box<Expr<'a>>, Box<Stmt<'a>>, Option<Box<Stmt<'a>>>),
+    Function(
+        Ctx,
+        Token<'a>,
+        Vec<Param<'a>>,
+        Option<TypeExpr<'a>>,
+        Rc<Vec<Stmt<'a>>>,
+        Doc<'a>,
+    ),
+    Return(Ctx, Option<Box<Expr<'a>>>),
+    /// `struct Name { fields }`, declaring the type and its constructor.
+    Struct(Ctx, Token<'a>, Vec<Param<'a>>, Doc<'a>),
+    Trait(Ctx, Token<'a>, Vec<TraitMethod<'a>>, Doc<'a>),
+    /// `impl Trait for Type { .. }`, the methods are `Stmt::Function`s.
+    Impl(Ctx, Token<'a>, Token<'a>, Vec<Stmt<'a>>),
+    /// `import "path" as name;`, the token is the path string.
+    Import(Ctx, Token<'a>, Token<'a>),
+    /// `use module::{a, b};`
+    Use(Ctx, Token<'a>, Vec<Token<'a>>),
+    /// `pub` before a `let`, `const`, `fun` or `struct` exports it from the
+    /// module.
+    Pub(Ctx, Box<Stmt<'a>>),
+}
+
+impl Expr<'_> {
+    pub fn ctx(&self) -> &Ctx {
+        match self {
+            Expr::Binary(ctx, ..)
+            | Expr::Grouping(ctx, _)
+            | Expr::Literal(ctx, _)
+            | Expr::Unary(ctx, ..)
+            | Expr::Variable(ctx, ..)
+            | Expr::Assign(ctx, ..)
+            | Expr::Call(ctx, ..)
+            | Expr::MethodCall(ctx, ..)
+            | Expr::Get(ctx, ..)
+            | Expr::Index(ctx, ..)
+            | Expr::Constant(ctx, _) => ctx,
+        }
+    }
+}
+
+impl Stmt<'_> {
+    pub fn ctx(&self) -> &Ctx {
+        match self {
+            Stmt::Expression(ctx, _)
+            | Stmt::PrintLn(ctx, _)
+            | Stmt::Let(ctx, ..)
+            | Stmt::Const(ctx, ..)
+            | Stmt::Block(ctx, _)
+            | Stmt::If(ctx, ..)
+            | Stmt::Function(ctx, ..)
+            | Stmt::Return(ctx, _)
+            | Stmt::Struct(ctx, ..)
+            | Stmt::Trait(ctx, ..)
+            | Stmt::Impl(ctx, ..)
+            | Stmt::Import(ctx, ..)
+            | Stmt::Use(ctx, ..)
+            | Stmt::Pub(ctx, _) => ctx,
+        }
+    }
+
+    /// The declaration a `pub` statement exports, otherwise the statement.
+    pub fn declaration(&self) -> &Self {
+        match self {
+            Stmt::Pub(_, stmt) => stmt,
+            stmt => stmt,
+        }
+    }
+
+    /// Whether control never reaches the statement after this one.
+    pub fn diverges(&self) -> bool {
+        match self {
+            Stmt::Return(..) => true,
+            Stmt::Block(_, stmts) => stmts.iter().any(Stmt::diverges),
+            Stmt::If(_, _, then_branch, Some(else_branch)) => {
+                then_branch.diverges() && else_branch.diverges()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Renders an expression in a parenthesized prefix form, e.g. `(+ 1 4)`.
+pub fn to_string(expr: &Expr) -> String {
+    match expr {
+        Expr::Binary(_, left, op, right) => {
+            format!("({} {} {})", op.lexeme, to_string(left), to_string(right))
+        }
+        Expr::Grouping(_, expr) => format!("(group {})", to_string(expr)),
+        Expr::Literal(_, token) => match token.ttype {
+            TokenType::String(s) => format!("\"{}\"", s),
+            TokenType::Number(n) => format!("{}", n),
+            _ => token.lexeme.to_string(),
+        },
+        Expr::Unary(_, op, expr) => format!("({} {})", op.lexeme, to_string(expr)),
+        Expr::Variable(_, token, _) => token.lexeme.to_string(),
+        Expr::Assign(_, token, _, expr) => format!("(= {} {})", token.lexeme, to_string(expr)),
+        Expr::Call(_, callee, args) => {
+            let mut parts = vec![to_string(callee)];
+            parts.extend(args.iter().map(to_string));
+            format!("(call {})", parts.join(" "))
+        }
+        Expr::MethodCall(_, receiver, name, args) => {
+            let mut parts = vec![to_string(receiver)];
+            parts.extend(args.iter().map(to_string));
+            format!("(call .{} {})", name.lexeme, parts.join(" "))
+        }
+        Expr::Get(_, object, name) => format!("(. {} {})", to_string(object), name.lexeme),
+        Expr::Index(_, target, index) => {
+            format!("(index {} {})", to_string(target), to_string(index))
+        }
+        Expr::Constant(_, value) => value.to_string(),
+    }
 }
 
 #[cfg(test)]
@@ -44,10 +239,11 @@ mod tests {
 
     #[test]
     fn binary_to_string() {
-        let val_1 = token::Token::new(token::TokenType::Number(1.0), "1.0", 0);
-        let val_2 = token::Token::new(token::TokenType::Number(4.0), "4.0", 0);
+        let span = token::Span::default();
+        let val_1 = token::Token::new(token::TokenType::Number(1.0), "1.0", 1, 1, span);
+        let val_2 = token::Token::new(token::TokenType::Number(4.0), "4.0", 1, 1, span);
         let tt_plus_op = token::TokenType::Plus;
-        let op = token::Token::new(tt_plus_op, "+", 0);
+        let op = token::Token::new(tt_plus_op, "+", 1, 1, span);
 
         let b_expr = Expr::Binary(
             Ctx::default(),
@@ -0,0 +1,647 @@
+use std::collections::HashMap;
+
+use crate::ast::{Ctx, Expr, Param, Stmt, TypeExpr};
+use crate::common::Depth;
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
+use crate::parser::DEFAULT_MAX_DEPTH;
+use crate::token::{Token, TokenType};
+use crate::types::{unsupported_operator, MethodLookup, MethodTable, Type};
+use crate::value::UvlValue;
+
+/// The return type a function body is checked against, and the annotation
+/// it comes from.
+struct Returns {
+    expected: Type,
+    annotation: Option<Ctx>,
+}
+
+/// Gradual type checker. Literals, constants, operators and annotated
+/// bindings, parameters and return values have a static type, everything
+/// else is `Any`. Operations whose operand types are known to be wrong are
+/// reported before the program runs, `Any` operands are left to the
+/// runtime checks. Operators the built-in types do not support are
+/// accepted when an impl gives the left operand's type the operator method.
+///
+/// The outermost scope holds the globals and outlives a single `check`, so
+/// the prompt remembers the types of earlier lines.
+pub struct TypeChecker {
+    scopes: Vec<HashMap<String, Type>>,
+    returns: Vec<Returns>,
+    errors: Vec<UvlError>,
+    methods: MethodTable,
+    depth: Depth,
+}
+
+impl TypeChecker {
+    pub fn new() -> Self {
+        TypeChecker {
+            scopes: vec![HashMap::new()],
+            returns: vec![],
+            errors: vec![],
+            methods: MethodTable::default(),
+            depth: Depth::new(DEFAULT_MAX_DEPTH),
+        }
+    }
+
+    /// Sets how deeply statements and expressions may nest before checking
+    /// fails with a RecursionError.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.depth = Depth::new(max_depth);
+    }
+
+    pub fn check(&mut self, stmts: &[Stmt], methods: &MethodTable) -> Result<(), Vec<UvlError>> {
+        self.methods = methods.clone();
+        self.depth.reset();
+        self.check_stmts(stmts);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn check_stmts(&mut self, stmts: &[Stmt]) {
+        // Functions may call the ones declared after them, and construct
+        // the structs declared after them
+        for stmt in stmts {
+            match stmt.declaration() {
+                Stmt::Function(_, name, params, return_type, ..) => {
+                    let signature = self.signature(params, return_type.as_ref());
+                    self.declare(name.lexeme, signature);
+                }
+                Stmt::Struct(_, name, fields, _) => {
+                    let constructor = self.constructor(name, fields);
+                    self.declare(name.lexeme, constructor);
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in stmts {
+            self.check_stmt(stmt);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        if self.depth.enter(stmt.ctx(), &mut self.errors) {
+            self.check_nested_stmt(stmt);
+        }
+        self.depth.leave();
+    }
+
+    fn check_nested_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(_, expr) | Stmt::PrintLn(_, expr) => {
+                self.check_expr(expr);
+            }
+            Stmt::Let(_, name, _, annotation, expr, _) => {
+                let actual = self.check_expr(expr);
+                let declared = match annotation {
+                    Some(annotation) => {
+                        let declared = Type::annotated(annotation, &self.methods, &mut self.errors);
+                        self.expect(&declared, &actual, expr.ctx(), Some(annotation.ctx()));
+                        declared
+                    }
+                    None => Type::Any,
+                };
+                self.declare(name.lexeme, declared);
+            }
+            Stmt::Const(_, name, expr, _) => {
+                // The resolver folds the initializer into a constant, and has
+                // reported why when it could not
+                let actual = match expr.as_ref() {
+                    Expr::Constant(..) => self.check_expr(expr),
+                    _ => Type::Any,
+                };
+                self.declare(name.lexeme, actual);
+            }
+            Stmt::Block(_, stmts) => {
+                self.scopes.push(HashMap::new());
+                self.check_stmts(stmts);
+                self.scopes.pop();
+            }
+            Stmt::If(_, condition, then_branch, else_branch) => {
+                let actual = self.check_expr(condition);
+                if !Type::Bool.accepts(&actual) {
+                    self.errors.push(UvlError::new(
+                        ErrorKind::Type,
+                        Diagnostic::error(
+                            condition.ctx(),
+                            format!("Expected a Bool condition, found {}", actual),
+                        )
+                        .with_label("expected Bool".to_string()),
+                    ));
+                }
+                self.check_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.check_stmt(else_branch);
+                }
+            }
+            Stmt::Function(ctx, name, params, return_type, body, _) => {
+                self.check_function(&ctx.file, name, params, return_type.as_ref(), body);
+            }
+            Stmt::Struct(_, name, fields, _) => {
+                let constructor = self.constructor(name, fields);
+                self.declare(name.lexeme, constructor);
+            }
+            Stmt::Trait(ctx, _, methods, _) => {
+                for method in methods {
+                    if let Some(body) = &method.body {
+                        self.check_function(
+                            &ctx.file,
+                            &method.name,
+                            &method.params,
+                            method.return_type.as_ref(),
+                            body,
+                        );
+                    }
+                }
+            }
+            Stmt::Impl(ctx, trait_name, type_name, methods) => {
+                if let Err(e) = self.methods.impl_target(ctx, trait_name, type_name) {
+                    self.errors.push(e);
+                }
+                for method in methods {
+                    if let Stmt::Function(ctx, name, params, return_type, body, _) = method {
+                        self.check_function(&ctx.file, name, params, return_type.as_ref(), body);
+                    }
+                }
+            }
+            Stmt::Return(ctx, expr) => {
+                let actual = match expr {
+                    Some(expr) => self.check_expr(expr),
+                    None => Type::Nil,
+                };
+                if let Some(returns) = self.returns.pop() {
+                    let at = expr.as_ref().map_or(ctx, |expr| expr.ctx());
+                    self.expect(&returns.expected, &actual, at, returns.annotation.as_ref());
+                    self.returns.push(returns);
+                }
+            }
+            Stmt::Import(_, _, name) => self.declare(name.lexeme, Type::Module),
+            // Exports are checked when they run
+            Stmt::Use(_, _, names) => {
+                for name in names {
+                    self.declare(name.lexeme, Type::Any);
+                }
+            }
+            Stmt::Pub(_, stmt) => self.check_stmt(stmt),
+        }
+    }
+
+    fn check_function(
+        &mut self,
+        file: &str,
+        name: &Token,
+        params: &[Param],
+        return_type: Option<&TypeExpr>,
+        body: &[Stmt],
+    ) {
+        let mut scope = HashMap::new();
+        for param in params {
+            let declared = match &param.annotation {
+                Some(annotation) => Type::annotated(annotation, &self.methods, &mut self.errors),
+                None => Type::Any,
+            };
+            scope.insert(param.name.lexeme.to_string(), declared);
+        }
+        let expected = match return_type {
+            Some(annotation) => Type::annotated(annotation, &self.methods, &mut self.errors),
+            None => Type::Any,
+        };
+
+        let returns_value = !expected.accepts(&Type::Nil);
+        if returns_value && !body.iter().any(Stmt::diverges) {
+            let mut diagnostic = Diagnostic::error(
+                &Ctx::from_token(file, name),
+                format!(
+                    "Function '{}' may finish without returning a {}",
+                    name.lexeme, expected
+                ),
+            )
+            .with_label("not every path returns a value".to_string());
+            if let Some(annotation) = return_type {
+                diagnostic = diagnostic.with_secondary_label(
+                    annotation.ctx().span,
+                    format!("declared to return {}", expected),
+                );
+            }
+            self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+        }
+
+        self.scopes.push(scope);
+        self.returns.push(Returns {
+            expected,
+            annotation: return_type
+                .as_ref()
+                .map(|annotation| annotation.ctx().clone()),
+        });
+        self.check_stmts(body);
+        self.returns.pop();
+        self.scopes.pop();
+    }
+
+    fn check_expr(&mut self, expr: &Expr) -> Type {
+        let ty = if self.depth.enter(expr.ctx(), &mut self.errors) {
+            self.check_nested_expr(expr)
+        } else {
+            Type::Any
+        };
+        self.depth.leave();
+        ty
+    }
+
+    fn check_nested_expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Binary(ctx, left, op, right) => {
+                let left_type = self.check_expr(left);
+                let right_type = self.check_expr(right);
+                self.binary(ctx, op, (left, &left_type), (right, &right_type))
+            }
+            Expr::Grouping(_, expr) => self.check_expr(expr),
+            Expr::Literal(_, token) => Type::of(&UvlValue::from_literal(token)),
+            Expr::Constant(_, value) => Type::of(value),
+            Expr::Unary(ctx, op, expr) => {
+                let actual = self.check_expr(expr);
+                let expected = match op.ttype {
+                    TokenType::Bang => Type::Bool,
+                    _ => Type::Number,
+                };
+                if expected.accepts(&actual) {
+                    expected
+                } else if self.methods.overloads(op, actual.name(), false) {
+                    Type::Any
+                } else {
+                    let operand = (expr.ctx().span, actual.to_string());
+                    self.errors.push(unsupported_operator(ctx, op, &[operand]));
+                    Type::Any
+                }
+            }
+            Expr::Variable(_, name, _) => self.lookup(name.lexeme),
+            Expr::Assign(_, name, _, expr) => {
+                let actual = self.check_expr(expr);
+                let declared = self.lookup(name.lexeme);
+                if !declared.accepts(&actual) {
+                    self.errors.push(UvlError::new(
+                        ErrorKind::Type,
+                        Diagnostic::error(
+                            expr.ctx(),
+                            format!(
+                                "Cannot assign {} to '{}' of type {}",
+                                actual, name.lexeme, declared
+                            ),
+                        )
+                        .with_label(format!("expected {}", declared)),
+                    ));
+                }
+                declared
+            }
+            Expr::MethodCall(ctx, receiver, name, args) => {
+                let receiver_type = self.check_expr(receiver);
+                let arg_types = args
+                    .iter()
+                    .map(|arg| self.check_expr(arg))
+                    .collect::<Vec<Type>>();
+
+                let receiver = receiver.ctx().span;
+                let type_name = receiver_type.name();
+                match self
+                    .methods
+                    .method(ctx, receiver, type_name, name, args.len())
+                {
+                    Ok(MethodLookup::Native(params, returns)) => {
+                        for ((param, arg), actual) in params.iter().zip(args).zip(&arg_types) {
+                            self.expect(param, actual, arg.ctx(), None);
+                        }
+                        returns
+                    }
+                    Ok(MethodLookup::Dynamic) => Type::Any,
+                    Err(e) => {
+                        self.errors.push(e);
+                        Type::Any
+                    }
+                }
+            }
+            Expr::Get(ctx, object, name) => {
+                let object_type = self.check_expr(object);
+                let has_member = match &object_type {
+                    Type::Any | Type::Module => true,
+                    Type::Struct(struct_name) => self
+                        .methods
+                        .fields(struct_name)
+                        .is_some_and(|fields| fields.iter().any(|field| field == name.lexeme)),
+                    _ => false,
+                };
+                if !has_member {
+                    self.errors.push(UvlError::new(
+                        ErrorKind::Type,
+                        Diagnostic::error(
+                            ctx,
+                            format!("{} has no member '{}'", object_type, name.lexeme),
+                        )
+                        .with_secondary_label(object.ctx().span, object_type.to_string()),
+                    ));
+                }
+                Type::Any
+            }
+            Expr::Index(ctx, target, index) => {
+                let target_type = self.check_expr(target);
+                self.check_expr(index);
+                let indexable = target_type.name().is_none_or(|name| {
+                    matches!(name, "List" | "Map") || self.methods.has(name, "index")
+                });
+                if !indexable {
+                    self.errors.push(UvlError::new(
+                        ErrorKind::Type,
+                        Diagnostic::error(ctx, format!("{} cannot be indexed", target_type))
+                            .with_secondary_label(target.ctx().span, target_type.to_string())
+                            .with_help(format!("consider `impl Index for {}`", target_type)),
+                    ));
+                }
+                Type::Any
+            }
+            Expr::Call(ctx, callee, args) => {
+                let callee_type = self.check_expr(callee);
+                let arg_types = args
+                    .iter()
+                    .map(|arg| self.check_expr(arg))
+                    .collect::<Vec<Type>>();
+
+                match callee_type {
+                    Type::Any => Type::Any,
+                    Type::Function(params, return_type) => {
+                        if params.len() != args.len() {
+                            let name = match callee.as_ref() {
+                                Expr::Variable(_, name, _) => format!("Function '{}'", name.lexeme),
+                                _ => "Function".to_string(),
+                            };
+                            self.errors.push(UvlError::new(
+                                ErrorKind::Type,
+                                Diagnostic::error(
+                                    ctx,
+                                    format!(
+                                        "{} expects {} arguments, got {}",
+                                        name,
+                                        params.len(),
+                                        args.len()
+                                    ),
+                                ),
+                            ));
+                        } else {
+                            for ((param, arg), actual) in params.iter().zip(args).zip(&arg_types) {
+                                self.expect(param, actual, arg.ctx(), None);
+                            }
+                        }
+                        *return_type
+                    }
+                    actual => {
+                        self.errors.push(UvlError::new(
+                            ErrorKind::Type,
+                            Diagnostic::error(ctx, format!("{} is not callable", actual))
+                                .with_secondary_label(callee.ctx().span, actual.to_string()),
+                        ));
+                        Type::Any
+                    }
+                }
+            }
+        }
+    }
+
+    /// The type of `left op right`, reporting operands it does not support.
+    fn binary(
+        &mut self,
+        ctx: &Ctx,
+        op: &Token,
+        (left, left_type): (&Expr, &Type),
+        (right, right_type): (&Expr, &Type),
+    ) -> Type {
+        let result = match op.ttype {
+            TokenType::EqualEqual | TokenType::BangEqual => Some(Type::Bool),
+            TokenType::Plus => match (left_type, right_type) {
+                (Type::Any, Type::Any) => Some(Type::Any),
+                (Type::Number | Type::Any, Type::Number | Type::Any) => Some(Type::Number),
+                (Type::String | Type::Any, Type::String | Type::Any) => Some(Type::String),
+                _ => None,
+            },
+            TokenType::Minus | TokenType::Star | TokenType::Slash => {
+                TypeChecker::numeric(left_type, right_type).then_some(Type::Number)
+            }
+            _ => TypeChecker::numeric(left_type, right_type).then_some(Type::Bool),
+        };
+
+        let builtin =
+            result.is_some() && !matches!(left_type, Type::Function(..) | Type::Struct(_));
+        if !builtin && self.methods.overloads(op, left_type.name(), true) {
+            return Type::Any;
+        }
+        result.unwrap_or_else(|| {
+            let operands = [
+                (left.ctx().span, left_type.to_string()),
+                (right.ctx().span, right_type.to_string()),
+            ];
+            self.errors.push(unsupported_operator(ctx, op, &operands));
+            Type::Any
+        })
+    }
+
+    fn numeric(left: &Type, right: &Type) -> bool {
+        Type::Number.accepts(left) && Type::Number.accepts(right)
+    }
+
+    /// Reports `actual` at `ctx` unless it may be used as `expected`.
+    fn expect(&mut self, expected: &Type, actual: &Type, ctx: &Ctx, annotation: Option<&Ctx>) {
+        if expected.accepts(actual) {
+            return;
+        }
+
+        let mut diagnostic =
+            Diagnostic::error(ctx, format!("Expected {}, found {}", expected, actual))
+                .with_label(format!("expected {}", expected));
+        if let Some(annotation) = annotation {
+            diagnostic = diagnostic
+                .with_secondary_label(annotation.span, "expected because of this".to_string());
+        }
+        self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+    }
+
+    /// The type of a function declared with `params` and `return_type`.
+    fn signature(&self, params: &[Param], return_type: Option<&TypeExpr>) -> Type {
+        Type::Function(
+            params
+                .iter()
+                .map(|param| Type::from_annotation(param.annotation.as_ref(), &self.methods))
+                .collect(),
+            Box::new(Type::from_annotation(return_type, &self.methods)),
+        )
+    }
+
+    /// The type of the constructor of the struct `name` with `fields`.
+    fn constructor(&self, name: &Token, fields: &[Param]) -> Type {
+        Type::Function(
+            fields
+                .iter()
+                .map(|field| Type::from_annotation(field.annotation.as_ref(), &self.methods))
+                .collect(),
+            Box::new(Type::Struct(name.lexeme.to_string())),
+        )
+    }
+
+    fn declare(&mut self, name: &str, declared: Type) {
+        self.scopes
+            .last_mut()
+            .expect("the global scope is never popped")
+            .insert(name.to_string(), declared);
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .unwrap_or(Type::Any)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+
+    fn check(source: &str) -> Vec<String> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.scan().unwrap().clone();
+        let mut stmts = Parser::new(&tokens, "main.uvl", false).parse().unwrap();
+        Resolver::new().resolve(&mut stmts).unwrap();
+        let mut methods = MethodTable::default();
+        methods.scan(&stmts);
+        match TypeChecker::new().check(&stmts, &methods) {
+            Ok(()) => vec![],
+            Err(errors) => errors
+                .iter()
+                .map(|e| format!("{}:{} {}", e.ctx().line, e.ctx().column, e.message()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn unannotated_code_stays_dynamic() {
+        let source = "fun twice(x) { return x + x; }\nlet a = twice(1);\nlet b = a + \"x\";";
+        assert!(check(source).is_empty());
+    }
+
+    #[test]
+    fn reports_operator_type_errors() {
+        assert_eq!(
+            check("let a = \"a string\" + 123;\nlet b = -true;\nif 1 < 2 == !false {}"),
+            vec![
+                "1:9 Operator '+' is not supported for String and Number",
+                "2:9 Operator '-' is not supported for Bool",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_annotations() {
+        let source = "let mut n: Number = \"five\";\n\
+                      fun add(a: Number, b: Number) -> Number { return a + b; }\n\
+                      let s: String = add(1, 2);\n\
+                      add(1, true);\n\
+                      n = add;\n\
+                      let x: Numbr = 1;";
+        assert_eq!(
+            check(source),
+            vec![
+                "1:21 Expected Number, found String",
+                "3:17 Expected String, found Number",
+                "4:8 Expected Number, found Bool",
+                "5:5 Cannot assign fun(Number, Number) -> Number to 'n' of type Number",
+                "6:8 Unknown type 'Numbr'",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_function_returns() {
+        let source = "fun f(flag: Bool) -> Number {\n  if flag { return 1; }\n}\n\
+                      fun g() -> String { return 1; }\n\
+                      fun h(apply: fun(Number) -> Number) -> Number { return apply(2); }\n\
+                      h(g);";
+        assert_eq!(
+            check(source),
+            vec![
+                "1:5 Function 'f' may finish without returning a Number",
+                "4:28 Expected String, found Number",
+                "6:3 Expected fun(Number) -> Number, found fun() -> String",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_structs() {
+        let source = "struct Point { x: Number, y }\n\
+                      let p: Point = Point(1, \"2\");\n\
+                      let q: Point = Point(\"1\", 2);\n\
+                      let n: Number = p;\n\
+                      let z = p.x + p.z;\n\
+                      impl Add for Shape {}";
+        assert_eq!(
+            check(source),
+            vec![
+                "3:22 Expected Number, found String",
+                "4:17 Expected Number, found Point",
+                "5:15 Point has no member 'z'",
+                "6:14 Unknown type 'Shape'",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_comparison_impls() {
+        let source = "struct Money { cents }\n\
+                      impl Ord for Money { fun lt(self, other) { return self.cents < other.cents; } }\n\
+                      impl Eq for Function { fun eq(self, other) { return true; } }\n\
+                      impl Ord for Number { fun lt(self, other) { return false; } }\n\
+                      let cheap = Money(1) < Money(2);";
+        assert_eq!(
+            check(source),
+            vec!["4:6 Trait 'Ord' cannot be implemented for Number"]
+        );
+    }
+
+    #[test]
+    fn accepts_overloaded_operators() {
+        let source = "impl Mul for String { fun mul(self, n) { return self; } }\n\
+                      let a = \"ab\" * 3;\nlet b = true * 3;\nlet c = \"ab\"[0];";
+        assert_eq!(
+            check(source),
+            vec![
+                "3:9 Operator '*' is not supported for Bool and Number",
+                "4:9 String cannot be indexed",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_native_methods() {
+        let source = "let n: Number = \"abc\".len();\nlet s: String = \"abc\".find(\"b\");\n\
+                      \"abc\".repeat(\"2\");\n\"abc\".slice(1);\n\"abc\".reverse();";
+        assert_eq!(
+            check(source),
+            vec![
+                "3:14 Expected Number, found String",
+                "4:1 Method 'slice' expects 2 arguments, got 1",
+                "5:7 String has no method 'reverse'",
+            ]
+        );
+
+        let source = "trait Sized { fun len(self); }\n\
+                      impl Sized for String { fun len(self) { return \"long\"; } }\n\
+                      let s: String = \"abc\".len();";
+        assert!(check(source).is_empty());
+    }
+}
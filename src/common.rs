@@ -1,8 +1,71 @@
 use crate::ast::Ctx;
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
 
-pub fn make_error_msg(ctx: &Ctx, error_reason: String) -> String {
+pub fn make_location(file: &str, line: usize, column: usize) -> String {
+    format!("{}:{}:{}", file, line, column)
+}
+
+/// The header of an error at `ctx` raised in `scope`, the function running
+/// or `<module>` at the top level.
+pub fn make_error_msg(ctx: &Ctx, scope: &str, error_reason: String) -> String {
     format!(
-        "File \"<{}>\", line {}, in <{}>\n    {}",
-        ctx.file, ctx.line, ctx.module, error_reason
+        "File \"<{}>\", in {}\n    {}",
+        make_location(&ctx.file, ctx.line, ctx.column),
+        scope,
+        error_reason
+    )
+}
+
+/// The RecursionError for nesting deeper than `limit` levels at `ctx`.
+pub fn nesting_error(ctx: &Ctx, limit: usize) -> UvlError {
+    UvlError::new(
+        ErrorKind::Recursion,
+        Diagnostic::error(ctx, "Too many nested statements or expressions".to_string())
+            .with_label("nesting limit reached here".to_string())
+            .with_note(format!("the limit is {} levels", limit)),
     )
 }
+
+/// How deeply a pass over the syntax tree has recursed, so it stops with a
+/// RecursionError instead of overflowing the native stack.
+#[derive(Debug)]
+pub struct Depth {
+    level: usize,
+    limit: usize,
+    reported: bool,
+}
+
+impl Depth {
+    pub fn new(limit: usize) -> Self {
+        Depth {
+            level: 0,
+            limit,
+            reported: false,
+        }
+    }
+
+    /// Goes one level deeper, which must be undone with `leave`. Returns
+    /// false past the limit, reporting that to `errors` the first time.
+    pub fn enter(&mut self, ctx: &Ctx, errors: &mut Vec<UvlError>) -> bool {
+        self.level += 1;
+        if self.level <= self.limit {
+            return true;
+        }
+        if !self.reported {
+            self.reported = true;
+            errors.push(nesting_error(ctx, self.limit));
+        }
+        false
+    }
+
+    pub fn leave(&mut self) {
+        self.level -= 1;
+    }
+
+    /// Starts a new pass, reporting the limit again.
+    pub fn reset(&mut self) {
+        self.level = 0;
+        self.reported = false;
+    }
+}
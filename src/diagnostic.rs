@@ -0,0 +1,330 @@
+use std::fmt::Write;
+
+use crate::ast::Ctx;
+use crate::common::{make_error_msg, make_location};
+use crate::token::Span;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl Level {
+    fn color(&self) -> &'static str {
+        match self {
+            Level::Error => RED,
+            Level::Warning => YELLOW,
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A span of source to underline. The primary label marks where the
+/// problem is (`^^^`), secondary labels add related locations (`---`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    pub primary: bool,
+}
+
+/// A message about the source, rendered rustc-style with the offending
+/// lines underlined.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub ctx: Ctx,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    /// A diagnostic whose primary label underlines `ctx.span`.
+    pub fn new(level: Level, ctx: &Ctx, message: String) -> Self {
+        Diagnostic {
+            level,
+            ctx: ctx.clone(),
+            message,
+            labels: vec![Label {
+                span: ctx.span,
+                message: String::new(),
+                primary: true,
+            }],
+            notes: vec![],
+            help: vec![],
+        }
+    }
+
+    pub fn error(ctx: &Ctx, message: String) -> Self {
+        Diagnostic::new(Level::Error, ctx, message)
+    }
+
+    pub fn warning(ctx: &Ctx, message: String) -> Self {
+        Diagnostic::new(Level::Warning, ctx, message)
+    }
+
+    /// Sets the text shown under the primary span.
+    pub fn with_label(mut self, message: String) -> Self {
+        if let Some(label) = self.labels.iter_mut().find(|l| l.primary) {
+            label.message = message;
+        }
+        self
+    }
+
+    pub fn with_secondary_label(mut self, span: Span, message: String) -> Self {
+        self.labels.push(Label {
+            span,
+            message,
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help.push(help);
+        self
+    }
+
+    /// Renders the diagnostic against `source`, the text `ctx.file` was
+    /// read from. With `color` the output uses ANSI escapes.
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{}{}{}", style, text, RESET)
+            } else {
+                text.to_string()
+            }
+        };
+
+        let lines = SourceLines::new(source);
+        let mut labels = self
+            .labels
+            .iter()
+            .map(|label| (lines.position(label.span.start), label))
+            .collect::<Vec<((usize, usize), &Label)>>();
+        labels.sort_by_key(|(position, label)| (*position, !label.primary));
+
+        let last_line = labels.iter().map(|((line, _), _)| *line).max();
+        let width = last_line.unwrap_or(self.ctx.line).to_string().len();
+        let gutter = paint(BLUE, &format!("{} |", " ".repeat(width)));
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}{}",
+            paint(self.level.color(), &self.level.to_string()),
+            paint(BOLD, &format!(": {}", self.message))
+        );
+        let _ = writeln!(
+            out,
+            "{}{} {}",
+            " ".repeat(width),
+            paint(BLUE, "-->"),
+            make_location(&self.ctx.file, self.ctx.line, self.ctx.column)
+        );
+        let _ = writeln!(out, "{}", gutter);
+
+        let mut previous_line = None;
+        for ((line, column), label) in labels {
+            if previous_line != Some(line) {
+                if previous_line.is_some_and(|previous| line > previous + 1) {
+                    let _ = writeln!(out, "{}", paint(BLUE, "..."));
+                }
+
+                let _ = writeln!(
+                    out,
+                    "{} {}",
+                    paint(BLUE, &format!("{:>width$} |", line, width = width)),
+                    lines.text(line)
+                );
+                previous_line = Some(line);
+            }
+
+            // Reuse tabs from the source line so the markers stay aligned
+            let indent = lines
+                .text(line)
+                .chars()
+                .take(column - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            let length = lines.underline_len(label.span).max(1);
+            let (marker, style) = if label.primary {
+                ("^", self.level.color())
+            } else {
+                ("-", BLUE)
+            };
+            let underline = format!("{} {}", marker.repeat(length), label.message);
+            let _ = writeln!(
+                out,
+                "{} {}{}",
+                gutter,
+                indent,
+                paint(style, underline.trim_end())
+            );
+        }
+
+        if !self.notes.is_empty() || !self.help.is_empty() {
+            let _ = writeln!(out, "{}", gutter);
+        }
+        for note in &self.notes {
+            let _ = writeln!(
+                out,
+                "{} {} {}",
+                " ".repeat(width),
+                paint(BOLD, "= note:"),
+                note
+            );
+        }
+        for help in &self.help {
+            let _ = writeln!(
+                out,
+                "{} {} {}",
+                " ".repeat(width),
+                paint(BOLD, "= help:"),
+                help
+            );
+        }
+
+        out.trim_end().to_string()
+    }
+}
+
+impl Diagnostic {
+    /// Writes the diagnostic as raised in `scope`, see [`make_error_msg`].
+    pub(crate) fn write_in(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        scope: &str,
+    ) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            make_error_msg(&self.ctx, scope, self.message.clone())
+        )?;
+        for note in &self.notes {
+            write!(f, "\n    note: {}", note)?;
+        }
+        for help in &self.help {
+            write!(f, "\n    help: {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_in(f, &format!("<{}>", self.ctx.module))
+    }
+}
+
+/// Maps byte offsets in a source to lines and columns.
+struct SourceLines<'a> {
+    source: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> SourceLines<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        SourceLines { source, starts }
+    }
+
+    /// 1-based line and column (in chars) of `offset`.
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.source.len());
+        let line = self.starts.partition_point(|start| *start <= offset);
+        let start = self.starts[line - 1];
+        let column = self.source[start..offset].chars().count() + 1;
+        (line, column)
+    }
+
+    /// Text of the 1-based `line`, without its line terminator.
+    fn text(&self, line: usize) -> &'a str {
+        let start = self.starts[line - 1];
+        let end = self
+            .starts
+            .get(line)
+            .map_or(self.source.len(), |next| next - 1);
+        self.source[start..end].trim_end_matches('\r')
+    }
+
+    /// Number of chars of `span` on its first line.
+    fn underline_len(&self, span: Span) -> usize {
+        let (line, _) = self.position(span.start);
+        let start = span.start.min(self.source.len());
+        let line_end = self.starts[line - 1] + self.text(line).len();
+        let end = span.end.clamp(start, line_end.max(start));
+        self.source[start..end].chars().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(line: usize, column: usize, start: usize, end: usize) -> Ctx {
+        Ctx {
+            file: "main.uvl".to_string(),
+            line,
+            column,
+            span: Span::new(start, end),
+            module: "root".to_string(),
+        }
+    }
+
+    #[test]
+    fn render_primary_label_with_help() {
+        let source = "let n = 1;\nn = 2;\n";
+        let diagnostic = Diagnostic::error(&ctx(2, 1, 11, 16), "Name 'n' is immutable".into())
+            .with_label("cannot assign twice".into())
+            .with_secondary_label(Span::new(4, 5), "first assignment".into())
+            .with_help("consider `let mut n`".into());
+
+        assert_eq!(
+            diagnostic.render(source, false),
+            "error: Name 'n' is immutable\n \
+             --> main.uvl:2:1\n  \
+              |\n\
+             1 | let n = 1;\n  \
+              |     - first assignment\n\
+             2 | n = 2;\n  \
+              | ^^^^^ cannot assign twice\n  \
+              |\n  \
+              = help: consider `let mut n`"
+        );
+    }
+
+    #[test]
+    fn render_counts_columns_in_chars() {
+        let source = "let s = \"🦀\" + 1;";
+        let diagnostic = Diagnostic::error(&ctx(1, 9, 8, 18), "bad".into());
+
+        assert_eq!(
+            diagnostic.render(source, false),
+            "error: bad\n --> main.uvl:1:9\n  |\n1 | let s = \"🦀\" + 1;\n  |         ^^^^^^^"
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::native::{function, string};
+use crate::value::UvlValue;
+
+/// The members of the `env` module. `args` are the arguments the script was
+/// given, shared with the interpreter so they can be set later.
+pub fn members(args: Rc<RefCell<Vec<String>>>) -> Vec<(&'static str, UvlValue)> {
+    vec![
+        (
+            "args",
+            function("args", Some(0), move |_| {
+                Ok(UvlValue::from(args.borrow().clone()))
+            }),
+        ),
+        // nil when the variable is not set
+        (
+            "var",
+            function("var", Some(1), |args| {
+                Ok(UvlValue::from(std::env::var(string("var", args, 0)?).ok()))
+            }),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::call_member;
+
+    #[test]
+    fn reads_arguments_and_variables() {
+        let args = Rc::new(RefCell::new(vec![]));
+        let members = members(args.clone());
+        let call = |name: &str, args: &[UvlValue]| call_member(&members, name, args);
+
+        *args.borrow_mut() = vec!["in.txt".to_string()];
+        assert_eq!(call("args", &[]), Ok(UvlValue::from(vec!["in.txt"])));
+        assert_eq!(
+            call("var", &[UvlValue::from("UVL_SURELY_NOT_SET")]),
+            Ok(UvlValue::Nil(()))
+        );
+        assert_eq!(
+            call("var", &[UvlValue::Number(1.0)]),
+            Err("Argument 1 of 'var' must be a String, found 1 of type Number".to_string())
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::value::UvlValue;
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub is_mutable: bool,
+    pub value: UvlValue,
+}
+
+/// A local scope. Bindings are stored in declaration order, so the
+/// resolver can address them by slot. Cloning an `Environment` yields
+/// another handle to the same bindings, so nested scopes observe
+/// assignments made through any handle.
+#[derive(Debug, Clone)]
+pub struct Environment {
+    values: Rc<RefCell<Vec<Entry>>>,
+    enclosing: Option<Box<Environment>>,
+}
+
+impl Environment {
+    pub fn new(enclosing: Option<Box<Environment>>) -> Self {
+        Environment {
+            values: Rc::new(RefCell::new(Vec::new())),
+            enclosing,
+        }
+    }
+
+    /// Binds the next slot.
+    pub fn define(&mut self, is_mutable: bool, value: UvlValue) {
+        self.values.borrow_mut().push(Entry { is_mutable, value });
+    }
+
+    /// The binding in `slot` of the scope `depth` levels out, `None` if
+    /// it was not declared yet.
+    pub fn get(&self, depth: usize, slot: usize) -> Option<Entry> {
+        self.ancestor(depth)?.values.borrow().get(slot).cloned()
+    }
+
+    pub fn assign(&mut self, depth: usize, slot: usize, value: UvlValue) -> bool {
+        match self.ancestor(depth) {
+            Some(environment) => match environment.values.borrow_mut().get_mut(slot) {
+                Some(entry) => {
+                    entry.value = value;
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    fn ancestor(&self, depth: usize) -> Option<&Environment> {
+        let mut environment = self;
+        for _ in 0..depth {
+            environment = environment.enclosing.as_deref()?;
+        }
+        Some(environment)
+    }
+}
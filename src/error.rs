@@ -0,0 +1,266 @@
+use crate::ast::Ctx;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::LexErrorKind;
+use crate::token::Span;
+
+/// What went wrong, coarse enough to filter on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Lexer(LexErrorKind),
+    Parser,
+    Name,
+    Const,
+    Lint,
+    Type,
+    Import,
+    UnsupportedOperator,
+    Runtime,
+    Recursion,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lexer(_) => write!(f, "LexerError"),
+            Self::Parser => write!(f, "ParserError"),
+            Self::Name => write!(f, "NameError"),
+            Self::Const => write!(f, "ConstError"),
+            Self::Lint => write!(f, "LintError"),
+            Self::Type => write!(f, "TypeError"),
+            Self::Import => write!(f, "ImportError"),
+            Self::UnsupportedOperator => write!(f, "UnsupportedOperator"),
+            Self::Runtime => write!(f, "RuntimeError"),
+            Self::Recursion => write!(f, "RecursionError"),
+        }
+    }
+}
+
+/// Number of identical consecutive frames shown before they are collapsed.
+const TRACEBACK_REPEATS: usize = 3;
+/// Number of traceback lines kept at each end of a very deep traceback.
+const TRACEBACK_EDGE: usize = 10;
+
+/// A call in a traceback: `function` was running at `ctx`.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    pub ctx: Ctx,
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "  File \"{}\", line {}, in {}",
+            self.ctx.file, self.ctx.line, self.function
+        )
+    }
+}
+
+/// An error raised while lexing, parsing or running a program. The
+/// diagnostic holds the location, message, labels and notes, so tools can
+/// inspect the error or render it however they like. Runtime errors raised
+/// inside a function also carry the call stack, outermost frame first.
+#[derive(Debug, Clone)]
+pub struct UvlError {
+    kind: ErrorKind,
+    diagnostic: Box<Diagnostic>,
+    traceback: Vec<Frame>,
+}
+
+impl UvlError {
+    pub fn new(kind: ErrorKind, diagnostic: Diagnostic) -> Self {
+        UvlError {
+            kind,
+            diagnostic: Box::new(diagnostic),
+            traceback: vec![],
+        }
+    }
+
+    pub fn with_traceback(mut self, traceback: Vec<Frame>) -> Self {
+        self.traceback = traceback;
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.diagnostic.help.push(help);
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn ctx(&self) -> &Ctx {
+        &self.diagnostic.ctx
+    }
+
+    pub fn span(&self) -> Span {
+        self.diagnostic.ctx.span
+    }
+
+    pub fn message(&self) -> &str {
+        &self.diagnostic.message
+    }
+
+    pub fn notes(&self) -> &[String] {
+        &self.diagnostic.notes
+    }
+
+    pub fn diagnostic(&self) -> &Diagnostic {
+        &self.diagnostic
+    }
+
+    pub fn traceback(&self) -> &[Frame] {
+        &self.traceback
+    }
+
+    /// Renders the error against `source`, see [`Diagnostic::render`]. The
+    /// traceback, if any, comes first.
+    pub fn render(&self, source: &str, color: bool) -> String {
+        match self.render_traceback() {
+            Some(traceback) => format!("{}\n{}", traceback, self.diagnostic.render(source, color)),
+            None => self.diagnostic.render(source, color),
+        }
+    }
+
+    /// Python-style traceback. Runs of the same frame are collapsed and
+    /// the middle of a very deep stack is elided.
+    fn render_traceback(&self) -> Option<String> {
+        if self.traceback.is_empty() {
+            return None;
+        }
+
+        let mut lines = Vec::<String>::new();
+        let mut frames = self.traceback.iter().map(Frame::to_string).peekable();
+        while let Some(frame) = frames.next() {
+            let mut repeats = 1;
+            while frames.next_if_eq(&frame).is_some() {
+                repeats += 1;
+            }
+
+            lines.extend(std::iter::repeat_n(frame, repeats.min(TRACEBACK_REPEATS)));
+            if repeats > TRACEBACK_REPEATS {
+                lines.push(format!(
+                    "  [Previous line repeated {} more times]",
+                    repeats - TRACEBACK_REPEATS
+                ));
+            }
+        }
+
+        if lines.len() > 2 * TRACEBACK_EDGE {
+            let elided = lines.len() - 2 * TRACEBACK_EDGE;
+            lines.splice(
+                TRACEBACK_EDGE..lines.len() - TRACEBACK_EDGE,
+                [format!("  [{} more lines elided]", elided)],
+            );
+        }
+
+        Some(format!(
+            "Traceback (most recent call last):\n{}",
+            lines.join("\n")
+        ))
+    }
+}
+
+impl std::fmt::Display for UvlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(traceback) = self.render_traceback() {
+            writeln!(f, "{}", traceback)?;
+        }
+        // Raised in the innermost function of the traceback, if any
+        match self.traceback.last() {
+            Some(frame) => self.diagnostic.write_in(f, &frame.function),
+            None => write!(f, "{}", self.diagnostic),
+        }
+    }
+}
+
+impl std::error::Error for UvlError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp::UvlInterpreter;
+
+    #[test]
+    fn errors_are_inspectable() {
+        let mut interp = UvlInterpreter::new(false);
+        let errors = interp
+            .run("main.uvl", "let a = 1;\nlet b = a + \"x\";")
+            .unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        assert_eq!(error.kind(), ErrorKind::UnsupportedOperator);
+        assert_eq!((error.ctx().line, error.ctx().column), (2, 9));
+        assert_eq!(error.span(), Span::new(19, 26));
+        assert_eq!(error.notes().len(), 1);
+    }
+
+    #[test]
+    fn errors_are_std_errors() {
+        let mut interp = UvlInterpreter::new(false);
+        let errors = interp.run("main.uvl", "1 $ 2; let = 3;").unwrap_err();
+
+        assert_eq!(
+            errors[0].kind(),
+            ErrorKind::Lexer(LexErrorKind::UnexpectedCharacter('$'))
+        );
+        let error: Box<dyn std::error::Error> = Box::new(errors[0].clone());
+        assert!(error.to_string().ends_with("Unexpected character '$'"));
+    }
+
+    #[test]
+    fn runtime_errors_carry_the_call_stack() {
+        let source =
+            "fun inner(n) {\n  return n / 0;\n}\nfun outer() {\n  return inner(1);\n}\nouter();";
+        let mut interp = UvlInterpreter::new(false);
+        let errors = interp.run("main.uvl", source).unwrap_err();
+
+        let frames = errors[0]
+            .traceback()
+            .iter()
+            .map(|frame| format!("{}:{}", frame.function, frame.ctx.line))
+            .collect::<Vec<String>>();
+        assert_eq!(frames, vec!["<root>:7", "outer:5", "inner:2"]);
+        assert!(errors[0].render(source, false).starts_with(
+            "Traceback (most recent call last):\n  \
+             File \"main.uvl\", line 7, in <root>\n  \
+             File \"main.uvl\", line 5, in outer\n  \
+             File \"main.uvl\", line 2, in inner\n\
+             error: Division by zero: 1/0"
+        ));
+    }
+
+    #[test]
+    fn deep_tracebacks_are_collapsed() {
+        let source =
+            "fun down(n) {\n  if n == 0 { return 1 / n; }\n  return down(n - 1);\n}\ndown(50);";
+        let mut interp = UvlInterpreter::new(false);
+        let errors = interp.run("main.uvl", source).unwrap_err();
+        assert_eq!(errors[0].traceback().len(), 52);
+
+        let rendered = errors[0].render(source, false);
+        assert!(rendered.contains(
+            "  File \"main.uvl\", line 3, in down\n  \
+             [Previous line repeated 47 more times]\n  \
+             File \"main.uvl\", line 2, in down"
+        ));
+    }
+
+    #[test]
+    fn very_deep_tracebacks_are_elided() {
+        let source = "fun ping(n) {\n  if n == 0 { return 1 / n; }\n  return pong(n - 1);\n}\n\
+                      fun pong(n) {\n  return ping(n);\n}\nping(30);";
+        let mut interp = UvlInterpreter::new(false);
+        let errors = interp.run("main.uvl", source).unwrap_err();
+
+        let traceback = errors[0].to_string();
+        let lines = traceback.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "Traceback (most recent call last):");
+        assert_eq!(lines[11], "  [42 more lines elided]");
+        assert_eq!(lines[21], "  File \"main.uvl\", line 2, in ping");
+        assert_eq!(lines[22], "File \"<main.uvl:2:22>\", in ping");
+    }
+}
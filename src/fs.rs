@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::native::{function, string};
+use crate::value::UvlValue;
+
+/// Where scripts may use the file system.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsAccess {
+    Denied,
+    Anywhere,
+    /// Only inside a directory, given as a canonical path.
+    Under(PathBuf),
+}
+
+/// The members of the `fs` module. Each checks `access` when it is called,
+/// so access granted later applies to them too.
+pub fn members(access: Rc<RefCell<FsAccess>>) -> Vec<(&'static str, UvlValue)> {
+    let read_to_string = operation(&access, "read_to_string", 1, |path, _| {
+        std::fs::read_to_string(path).map(UvlValue::String)
+    });
+    let write = operation(&access, "write", 2, |path, args| {
+        std::fs::write(path, args[0]).map(|_| nil())
+    });
+    let append = operation(&access, "append", 2, |path, args| {
+        std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(args[0].as_bytes()))
+            .map(|_| nil())
+    });
+    let list_dir = operation(&access, "list_dir", 1, |path, _| {
+        let mut names = vec![];
+        for entry in std::fs::read_dir(path)? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(UvlValue::from(names))
+    });
+    let lines = operation(&access, "lines", 1, |path, _| {
+        let text = std::fs::read_to_string(path)?;
+        Ok(UvlValue::from(text.lines().collect::<Vec<&str>>()))
+    });
+    let exists = function("exists", Some(1), move |args| {
+        let path = allowed(&access.borrow(), Path::new(string("exists", args, 0)?))?;
+        Ok(UvlValue::Bool(path.exists()))
+    });
+
+    vec![
+        ("read_to_string", read_to_string),
+        ("write", write),
+        ("append", append),
+        ("exists", exists),
+        ("list_dir", list_dir),
+        ("lines", lines),
+    ]
+}
+
+/// A function taking a path and `arity - 1` more Strings, whose I/O errors
+/// are returned as an Err Result holding the message.
+fn operation(
+    access: &Rc<RefCell<FsAccess>>,
+    name: &'static str,
+    arity: usize,
+    apply: impl Fn(&Path, &[&str]) -> std::io::Result<UvlValue> + 'static,
+) -> UvlValue {
+    let access = access.clone();
+    function(name, Some(arity), move |args| {
+        let texts = (0..arity)
+            .map(|position| string(name, args, position))
+            .collect::<Result<Vec<&str>, String>>()?;
+        let path = allowed(&access.borrow(), Path::new(texts[0]))?;
+        let result =
+            apply(&path, &texts[1..]).map_err(|e| UvlValue::String(format!("{}: {}", texts[0], e)));
+        Ok(UvlValue::Result(Rc::new(result)))
+    })
+}
+
+/// The path to use for `path`, an error when access does not reach it.
+fn allowed(access: &FsAccess, path: &Path) -> Result<PathBuf, String> {
+    match access {
+        FsAccess::Denied => {
+            Err("File system access is disabled, run uvl with --allow-fs".to_string())
+        }
+        FsAccess::Anywhere => Ok(path.to_path_buf()),
+        FsAccess::Under(root) => {
+            let resolved = resolve(path)
+                .map_err(|e| format!("Path '{}' cannot be resolved: {}", path.display(), e))?;
+            if resolved.starts_with(root) {
+                Ok(resolved)
+            } else {
+                Err(format!(
+                    "Path '{}' is outside {}, the directory file system access is allowed in",
+                    path.display(),
+                    root.display()
+                ))
+            }
+        }
+    }
+}
+
+/// The canonical form of `path`, which may not exist yet: its longest
+/// existing ancestor is canonicalized and the rest appended. Symlinks are
+/// existing entries too, so a dangling one fails to canonicalize instead of
+/// being written through.
+fn resolve(path: &Path) -> std::io::Result<PathBuf> {
+    let absolute = std::env::current_dir()?.join(path);
+    let mut existing = absolute.as_path();
+    let mut rest = vec![];
+    while existing.symlink_metadata().is_err() {
+        // `..` past a missing directory cannot be resolved
+        let (Some(name), Some(parent)) = (existing.file_name(), existing.parent()) else {
+            return Err(std::io::ErrorKind::NotFound.into());
+        };
+        rest.push(name);
+        existing = parent;
+    }
+    let mut resolved = existing.canonicalize()?;
+    resolved.extend(rest.iter().rev());
+    Ok(resolved)
+}
+
+fn nil() -> UvlValue {
+    UvlValue::Nil(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::call_member;
+
+    fn call(access: FsAccess, name: &str, args: &[&str]) -> Result<UvlValue, String> {
+        let members = members(Rc::new(RefCell::new(access)));
+        let args = args
+            .iter()
+            .map(|arg| UvlValue::from(*arg))
+            .collect::<Vec<UvlValue>>();
+        call_member(&members, name, &args)
+    }
+
+    #[test]
+    fn access_is_checked_before_every_operation() {
+        let dir = std::env::temp_dir().join(format!("uvl-fs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let root = dir.canonicalize().unwrap();
+        let file = dir.join("notes.txt");
+        let file = file.to_str().unwrap();
+
+        assert_eq!(
+            call(FsAccess::Denied, "exists", &[file]),
+            Err("File system access is disabled, run uvl with --allow-fs".to_string())
+        );
+        let under = FsAccess::Under(root.clone());
+        assert_eq!(
+            call(under.clone(), "write", &[file, "a\nb"]),
+            Ok(UvlValue::from(Ok::<(), UvlValue>(())))
+        );
+        call(under.clone(), "append", &[file, "\nc"]).unwrap();
+        assert_eq!(
+            call(under.clone(), "lines", &[file]),
+            Ok(UvlValue::from(Ok::<_, UvlValue>(vec!["a", "b", "c"])))
+        );
+        let missing = dir.join("missing").join("..").join("x");
+        assert!(call(
+            under.clone(),
+            "read_to_string",
+            &[missing.to_str().unwrap()]
+        )
+        .is_err());
+        assert!(call(under, "exists", &["/"])
+            .unwrap_err()
+            .starts_with("Path '/' is outside"));
+        let listed = call(FsAccess::Anywhere, "list_dir", &[dir.to_str().unwrap()]);
+        assert_eq!(
+            listed,
+            Ok(UvlValue::from(Ok::<_, UvlValue>(vec!["notes.txt"])))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_symlinks_do_not_escape_the_directory() {
+        let dir = std::env::temp_dir().join(format!("uvl-fs-link-{}", std::process::id()));
+        let sandbox = dir.join("sandbox");
+        std::fs::create_dir_all(&sandbox).unwrap();
+        let outside = dir.join("outside.txt");
+        let link = sandbox.join("evil");
+        std::os::unix::fs::symlink(&outside, &link).unwrap();
+
+        let under = FsAccess::Under(sandbox.canonicalize().unwrap());
+        let written = call(under, "write", &[link.to_str().unwrap(), "pwned"]);
+        assert!(written.unwrap_err().contains("cannot be resolved"));
+        assert!(!outside.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1059 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Ctx, Expr, Param, Stmt, TypeExpr};
+use crate::common::Depth;
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
+use crate::parser::DEFAULT_MAX_DEPTH;
+use crate::token::{Token, TokenType};
+use crate::types::{unsupported_operator, MethodLookup, MethodTable, Type};
+use crate::value::UvlValue;
+
+/// A type during inference. Variables stand for types that are not known
+/// yet and are solved by unification.
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Var(usize),
+    Number,
+    String,
+    Bool,
+    Nil,
+    List,
+    Map,
+    Result,
+    Module,
+    Function(Vec<Ty>, Box<Ty>),
+    Struct(String),
+}
+
+/// A possibly polymorphic type, `vars` are instantiated afresh at every use.
+#[derive(Debug, Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Ty,
+}
+
+impl Scheme {
+    fn mono(ty: Ty) -> Self {
+        Scheme { vars: vec![], ty }
+    }
+}
+
+/// Why two types failed to unify.
+enum Mismatch {
+    Types,
+    Infinite(usize),
+}
+
+/// Hindley–Milner type inference for whole programs, annotated or not.
+/// Functions and immutable bindings are generalized, so a function like
+/// `fun id(x) { return x; }` can be used at several types. `+` works on
+/// Numbers and Strings, so its operands get a variable that can only be
+/// solved to one of those. Operators the built-in types do not support get
+/// a fresh type when an impl gives the left operand's type the operator
+/// method.
+///
+/// The outermost scope holds the globals and outlives a single `infer`, so
+/// the prompt remembers the types of earlier lines. A global that neither
+/// the program declares nor is defined when it runs is reported.
+pub struct Inferencer {
+    solutions: Vec<Option<Ty>>,
+    addable: Vec<bool>,
+    scopes: Vec<HashMap<String, Scheme>>,
+    /// The globals the program declares and the ones defined outside it.
+    globals: HashSet<String>,
+    returns: Vec<Ty>,
+    declared: Vec<String>,
+    errors: Vec<UvlError>,
+    methods: MethodTable,
+    depth: Depth,
+}
+
+impl Inferencer {
+    pub fn new() -> Self {
+        Inferencer {
+            solutions: vec![],
+            addable: vec![],
+            scopes: vec![HashMap::new()],
+            globals: HashSet::new(),
+            returns: vec![],
+            declared: vec![],
+            errors: vec![],
+            methods: MethodTable::default(),
+            depth: Depth::new(DEFAULT_MAX_DEPTH),
+        }
+    }
+
+    /// Sets how deeply statements and expressions may nest before inference
+    /// fails with a RecursionError.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.depth = Depth::new(max_depth);
+    }
+
+    /// Infers the types of `stmts`, which may use the globals in `defined`
+    /// besides their own.
+    pub fn infer(
+        &mut self,
+        stmts: &[Stmt],
+        methods: &MethodTable,
+        defined: HashSet<String>,
+    ) -> Result<(), Vec<UvlError>> {
+        self.methods = methods.clone();
+        self.globals = defined;
+        for stmt in stmts {
+            let names = match stmt.declaration() {
+                Stmt::Let(_, name, ..)
+                | Stmt::Const(_, name, ..)
+                | Stmt::Function(_, name, ..)
+                | Stmt::Struct(_, name, ..)
+                | Stmt::Import(_, _, name) => vec![name],
+                Stmt::Use(_, _, names) => names.iter().collect(),
+                _ => vec![],
+            };
+            self.globals
+                .extend(names.into_iter().map(|name| name.lexeme.to_string()));
+        }
+        self.depth.reset();
+        self.declared.clear();
+        self.infer_stmts(stmts);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// The globals declared by the last `infer`, as `name: type`.
+    pub fn declarations(&self) -> Vec<String> {
+        self.declared
+            .iter()
+            .filter_map(|name| {
+                Some(format!(
+                    "{}: {}",
+                    name,
+                    self.show_scheme(self.scopes[0].get(name)?)
+                ))
+            })
+            .collect()
+    }
+
+    fn infer_stmts(&mut self, stmts: &[Stmt]) {
+        // Functions may call the ones declared after them, within the
+        // block they are monomorphic until their own declaration. They may
+        // construct the structs declared after them too.
+        for stmt in stmts {
+            match stmt.declaration() {
+                Stmt::Function(_, name, ..) => {
+                    let ty = self.fresh();
+                    self.bind(name.lexeme, Scheme::mono(ty));
+                }
+                Stmt::Struct(_, name, fields, _) => {
+                    let constructor = self.constructor(name, fields);
+                    self.bind(name.lexeme, constructor);
+                }
+                _ => {}
+            }
+        }
+
+        for stmt in stmts {
+            self.infer_stmt(stmt);
+        }
+    }
+
+    fn infer_stmt(&mut self, stmt: &Stmt) {
+        if self.depth.enter(stmt.ctx(), &mut self.errors) {
+            self.infer_nested_stmt(stmt);
+        }
+        self.depth.leave();
+    }
+
+    fn infer_nested_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Expression(_, expr) | Stmt::PrintLn(_, expr) => {
+                self.infer_expr(expr);
+            }
+            Stmt::Let(_, name, is_mutable, annotation, expr, _) => {
+                let actual = self.infer_expr(expr);
+                if let Some(annotation) = annotation {
+                    let declared = self.annotated(annotation);
+                    if self.unify(&declared, &actual).is_err() {
+                        let diagnostic = self
+                            .mismatch(expr.ctx(), &declared, &actual)
+                            .with_secondary_label(
+                                annotation.ctx().span,
+                                "expected because of this".to_string(),
+                            );
+                        self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                    }
+                }
+
+                // A mutable binding may be assigned other values later
+                let scheme = if is_mutable.0 {
+                    Scheme::mono(actual)
+                } else {
+                    self.generalize(&actual)
+                };
+                self.declare(name.lexeme, scheme);
+            }
+            Stmt::Const(_, name, expr, _) => {
+                // The resolver folds the initializer into a constant, and has
+                // reported why when it could not
+                let actual = match expr.as_ref() {
+                    Expr::Constant(..) => self.infer_expr(expr),
+                    _ => self.fresh(),
+                };
+                let scheme = self.generalize(&actual);
+                self.declare(name.lexeme, scheme);
+            }
+            Stmt::Block(_, stmts) => {
+                self.scopes.push(HashMap::new());
+                self.infer_stmts(stmts);
+                self.scopes.pop();
+            }
+            Stmt::If(_, condition, then_branch, else_branch) => {
+                let actual = self.infer_expr(condition);
+                if self.unify(&Ty::Bool, &actual).is_err() {
+                    let diagnostic = Diagnostic::error(
+                        condition.ctx(),
+                        format!(
+                            "Expected a Bool condition, found {}",
+                            self.show(&[&actual])[0]
+                        ),
+                    )
+                    .with_label("expected Bool".to_string());
+                    self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                }
+                self.infer_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.infer_stmt(else_branch);
+                }
+            }
+            Stmt::Function(ctx, name, params, return_type, body, _) => {
+                let name_ctx = Ctx::from_token(&ctx.file, name);
+                let function =
+                    self.infer_function(&ctx.file, name, None, params, return_type.as_ref(), body);
+                let placeholder = self.remove(name.lexeme);
+                if let Some(placeholder) = placeholder {
+                    if self.unify(&placeholder.ty, &function).is_err() {
+                        let diagnostic = self
+                            .mismatch(&name_ctx, &placeholder.ty, &function)
+                            .with_note(format!(
+                                "'{}' is used as {} before its declaration",
+                                name.lexeme,
+                                self.show(&[&placeholder.ty])[0]
+                            ));
+                        self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                    }
+                }
+                let scheme = self.generalize(&function);
+                self.declare(name.lexeme, scheme);
+            }
+            Stmt::Struct(_, name, fields, _) => {
+                let constructor = self.constructor(name, fields);
+                self.declare(name.lexeme, constructor);
+            }
+            // Methods are dispatched at runtime, only their bodies are checked
+            Stmt::Trait(ctx, _, methods, _) => {
+                for method in methods {
+                    if let Some(body) = &method.body {
+                        self.infer_function(
+                            &ctx.file,
+                            &method.name,
+                            None,
+                            &method.params,
+                            method.return_type.as_ref(),
+                            body,
+                        );
+                    }
+                }
+            }
+            Stmt::Impl(ctx, trait_name, type_name, methods) => {
+                if let Err(e) = self.methods.impl_target(ctx, trait_name, type_name) {
+                    self.errors.push(e);
+                }
+                for method in methods {
+                    if let Stmt::Function(ctx, name, params, return_type, body, _) = method {
+                        let receiver = match type_name.lexeme {
+                            "Number" => Ty::Number,
+                            "String" => Ty::String,
+                            "Bool" => Ty::Bool,
+                            "Nil" => Ty::Nil,
+                            "List" => Ty::List,
+                            "Map" => Ty::Map,
+                            "Result" => Ty::Result,
+                            "Module" => Ty::Module,
+                            name if self.methods.fields(name).is_some() => {
+                                Ty::Struct(name.to_string())
+                            }
+                            // Functions of any signature share the impl
+                            _ => self.fresh(),
+                        };
+                        let return_type = return_type.as_ref();
+                        self.infer_function(
+                            &ctx.file,
+                            name,
+                            Some(receiver),
+                            params,
+                            return_type,
+                            body,
+                        );
+                    }
+                }
+            }
+            Stmt::Return(ctx, expr) => {
+                let actual = match expr {
+                    Some(expr) => self.infer_expr(expr),
+                    None => Ty::Nil,
+                };
+                if let Some(expected) = self.returns.last().cloned() {
+                    if self.unify(&expected, &actual).is_err() {
+                        let at = expr.as_ref().map_or(ctx, |expr| expr.ctx());
+                        let diagnostic = self.mismatch(at, &expected, &actual).with_note(format!(
+                            "the function was inferred to return {}",
+                            self.show(&[&expected])[0]
+                        ));
+                        self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                    }
+                }
+            }
+            Stmt::Import(_, _, name) => self.declare(name.lexeme, Scheme::mono(Ty::Module)),
+            // Exports are checked when they run, so each use may have any type
+            Stmt::Use(_, _, names) => {
+                for name in names {
+                    let ty = self.fresh();
+                    let scheme = self.generalize(&ty);
+                    self.declare(name.lexeme, scheme);
+                }
+            }
+            Stmt::Pub(_, stmt) => self.infer_stmt(stmt),
+        }
+    }
+
+    /// The type of a function, before it is generalized. The first
+    /// parameter of a method has the `receiver` type.
+    fn infer_function(
+        &mut self,
+        file: &str,
+        name: &Token,
+        receiver: Option<Ty>,
+        params: &[Param],
+        return_type: Option<&TypeExpr>,
+        body: &[Stmt],
+    ) -> Ty {
+        let name_ctx = Ctx::from_token(file, name);
+        let mut scope = HashMap::new();
+        let mut param_types = vec![];
+        let mut receiver = receiver;
+        for param in params {
+            let ty = match (&param.annotation, receiver.take()) {
+                (Some(annotation), _) => self.annotated(annotation),
+                (None, Some(receiver)) => receiver,
+                (None, None) => self.fresh(),
+            };
+            scope.insert(param.name.lexeme.to_string(), Scheme::mono(ty.clone()));
+            param_types.push(ty);
+        }
+        let returns = match return_type {
+            Some(annotation) => self.annotated(annotation),
+            None => self.fresh(),
+        };
+
+        self.scopes.push(scope);
+        self.returns.push(returns.clone());
+        self.infer_stmts(body);
+        self.returns.pop();
+        self.scopes.pop();
+
+        // Falling off the end returns nil
+        if !body.iter().any(Stmt::diverges) && self.unify(&returns, &Ty::Nil).is_err() {
+            let diagnostic = Diagnostic::error(
+                &name_ctx,
+                format!(
+                    "Function '{}' may finish without returning a {}",
+                    name.lexeme,
+                    self.show(&[&returns])[0]
+                ),
+            )
+            .with_label("not every path returns a value".to_string());
+            self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+        }
+
+        Ty::Function(param_types, Box::new(returns))
+    }
+
+    fn infer_expr(&mut self, expr: &Expr) -> Ty {
+        let ty = if self.depth.enter(expr.ctx(), &mut self.errors) {
+            self.infer_nested_expr(expr)
+        } else {
+            self.fresh()
+        };
+        self.depth.leave();
+        ty
+    }
+
+    fn infer_nested_expr(&mut self, expr: &Expr) -> Ty {
+        match expr {
+            Expr::Binary(ctx, left, op, right) => {
+                let left_type = self.infer_expr(left);
+                let right_type = self.infer_expr(right);
+                let overloaded = self.overloads(op, &left_type, true);
+                if overloaded
+                    && matches!(self.resolve(&left_type), Ty::Function(..) | Ty::Struct(_))
+                {
+                    return self.fresh();
+                }
+                // Failed unifications are undone when the method applies
+                let snapshot = overloaded.then(|| (self.solutions.clone(), self.addable.clone()));
+                let (operands, result) = match op.ttype {
+                    TokenType::EqualEqual | TokenType::BangEqual => (None, Ty::Bool),
+                    TokenType::Plus => (None, left_type.clone()),
+                    TokenType::Minus | TokenType::Star | TokenType::Slash => {
+                        (Some(Ty::Number), Ty::Number)
+                    }
+                    _ => (Some(Ty::Number), Ty::Bool),
+                };
+
+                let supported = match operands {
+                    Some(operand) => {
+                        self.unify(&operand, &left_type).is_ok()
+                            && self.unify(&operand, &right_type).is_ok()
+                    }
+                    None => {
+                        self.unify(&left_type, &right_type).is_ok()
+                            && (op.ttype != TokenType::Plus || self.make_addable(&left_type))
+                    }
+                };
+                if let (false, Some((solutions, addable))) = (supported, snapshot) {
+                    self.solutions = solutions;
+                    self.addable = addable;
+                    return self.fresh();
+                }
+                if !supported {
+                    let mut types = self.show(&[&left_type, &right_type]).into_iter();
+                    let operands = [
+                        (left.ctx().span, types.next().expect("two types were shown")),
+                        (
+                            right.ctx().span,
+                            types.next().expect("two types were shown"),
+                        ),
+                    ];
+                    self.errors.push(unsupported_operator(ctx, op, &operands));
+                }
+                result
+            }
+            Expr::Grouping(_, expr) => self.infer_expr(expr),
+            Expr::Literal(_, token) => self.lower(&Type::of(&UvlValue::from_literal(token))),
+            Expr::Constant(_, value) => self.lower(&Type::of(value)),
+            Expr::Unary(ctx, op, expr) => {
+                let actual = self.infer_expr(expr);
+                let expected = match op.ttype {
+                    TokenType::Bang => Ty::Bool,
+                    _ => Ty::Number,
+                };
+                let overloaded = self.overloads(op, &actual, false);
+                if overloaded && matches!(self.resolve(&actual), Ty::Function(..)) {
+                    return self.fresh();
+                }
+                if self.unify(&expected, &actual).is_err() {
+                    if overloaded {
+                        return self.fresh();
+                    }
+                    let operand = (expr.ctx().span, self.show(&[&actual]).remove(0));
+                    self.errors.push(unsupported_operator(ctx, op, &[operand]));
+                }
+                expected
+            }
+            Expr::Variable(ctx, name, _) => self.lookup(ctx, name.lexeme),
+            Expr::Assign(ctx, name, _, expr) => {
+                let actual = self.infer_expr(expr);
+                let declared = self.lookup(ctx, name.lexeme);
+                if self.unify(&declared, &actual).is_err() {
+                    let types = self.show(&[&actual, &declared]);
+                    let diagnostic = Diagnostic::error(
+                        expr.ctx(),
+                        format!(
+                            "Cannot assign {} to '{}' of type {}",
+                            types[0], name.lexeme, types[1]
+                        ),
+                    )
+                    .with_label(format!("expected {}", types[1]));
+                    self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                }
+                declared
+            }
+            Expr::Call(ctx, callee, args) => self.infer_call(ctx, callee, args),
+            Expr::MethodCall(ctx, receiver, name, args) => {
+                let receiver_type = self.infer_expr(receiver);
+                let arg_types = args
+                    .iter()
+                    .map(|arg| self.infer_expr(arg))
+                    .collect::<Vec<Ty>>();
+
+                let receiver = receiver.ctx().span;
+                let resolved = self.resolve(&receiver_type);
+                let type_name = Inferencer::type_name(&resolved);
+                match self
+                    .methods
+                    .method(ctx, receiver, type_name, name, args.len())
+                {
+                    Ok(MethodLookup::Native(params, returns)) => {
+                        for ((param, arg), actual) in params.iter().zip(args).zip(&arg_types) {
+                            let expected = self.lower(param);
+                            if self.unify(&expected, actual).is_err() {
+                                let diagnostic = self.mismatch(arg.ctx(), &expected, actual);
+                                self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                            }
+                        }
+                        self.lower(&returns)
+                    }
+                    Ok(MethodLookup::Dynamic) => self.fresh(),
+                    Err(e) => {
+                        self.errors.push(e);
+                        self.fresh()
+                    }
+                }
+            }
+            Expr::Get(ctx, object, name) => {
+                let object_type = self.infer_expr(object);
+                let resolved = self.resolve(&object_type);
+                let has_member = |found: &str| match self.methods.fields(found) {
+                    Some(fields) => fields.iter().any(|field| field == name.lexeme),
+                    None => found == "Module",
+                };
+                let object_name = Inferencer::type_name(&resolved);
+                if let Some(object_name) = object_name.filter(|found| !has_member(found)) {
+                    let diagnostic = Diagnostic::error(
+                        ctx,
+                        format!("{} has no member '{}'", object_name, name.lexeme),
+                    )
+                    .with_secondary_label(object.ctx().span, object_name.to_string());
+                    self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                }
+                self.fresh()
+            }
+            Expr::Index(ctx, target, index) => {
+                let target_type = self.infer_expr(target);
+                self.infer_expr(index);
+                let resolved = self.resolve(&target_type);
+                let name = Inferencer::type_name(&resolved);
+                let indexable = |name: &&str| {
+                    matches!(*name, "List" | "Map") || self.methods.has(name, "index")
+                };
+                if let Some(name) = name.filter(|name| !indexable(name)) {
+                    let diagnostic = Diagnostic::error(ctx, format!("{} cannot be indexed", name))
+                        .with_secondary_label(target.ctx().span, name.to_string())
+                        .with_help(format!("consider `impl Index for {}`", name));
+                    self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                }
+                self.fresh()
+            }
+        }
+    }
+
+    /// Whether `op` calls an operator method of the operand's type instead
+    /// of the built-in operator.
+    fn overloads(&self, op: &Token, operand: &Ty, binary: bool) -> bool {
+        let resolved = self.resolve(operand);
+        self.methods
+            .overloads(op, Inferencer::type_name(&resolved), binary)
+    }
+
+    /// The name impls use for a type, `None` for a variable.
+    fn type_name(ty: &Ty) -> Option<&str> {
+        match ty {
+            Ty::Var(_) => None,
+            Ty::Number => Some("Number"),
+            Ty::String => Some("String"),
+            Ty::Bool => Some("Bool"),
+            Ty::Nil => Some("Nil"),
+            Ty::List => Some("List"),
+            Ty::Map => Some("Map"),
+            Ty::Result => Some("Result"),
+            Ty::Module => Some("Module"),
+            Ty::Function(..) => Some("Function"),
+            Ty::Struct(name) => Some(name.as_str()),
+        }
+    }
+
+    fn infer_call(&mut self, ctx: &Ctx, callee: &Expr, args: &[Expr]) -> Ty {
+        let callee_type = self.infer_expr(callee);
+        let arg_types = args
+            .iter()
+            .map(|arg| self.infer_expr(arg))
+            .collect::<Vec<Ty>>();
+        let name = match callee {
+            Expr::Variable(_, name, _) => format!("'{}'", name.lexeme),
+            _ => "the function".to_string(),
+        };
+
+        match self.resolve(&callee_type) {
+            Ty::Function(params, returns) => {
+                if params.len() != args.len() {
+                    let diagnostic = Diagnostic::error(
+                        ctx,
+                        format!(
+                            "Function {} expects {} arguments, got {}",
+                            name,
+                            params.len(),
+                            args.len()
+                        ),
+                    )
+                    .with_note(format!(
+                        "{} has type {}",
+                        name,
+                        self.show(&[&callee_type])[0]
+                    ));
+                    self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                    return self.fresh();
+                }
+
+                for ((param, arg), actual) in params.iter().zip(args).zip(&arg_types) {
+                    if self.unify(param, actual).is_err() {
+                        let diagnostic = self.mismatch(arg.ctx(), param, actual).with_note(
+                            format!("{} has type {}", name, self.show(&[&callee_type])[0]),
+                        );
+                        self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                    }
+                }
+                *returns
+            }
+            Ty::Var(_) => {
+                let returns = self.fresh();
+                let expected = Ty::Function(arg_types, Box::new(returns.clone()));
+                if self.unify(&callee_type, &expected).is_err() {
+                    let diagnostic = self.mismatch(callee.ctx(), &expected, &callee_type);
+                    self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                }
+                returns
+            }
+            actual => {
+                let shown = self.show(&[&actual]).remove(0);
+                let diagnostic = Diagnostic::error(ctx, format!("{} is not callable", shown))
+                    .with_secondary_label(callee.ctx().span, shown);
+                self.errors.push(UvlError::new(ErrorKind::Type, diagnostic));
+                self.fresh()
+            }
+        }
+    }
+
+    /// "Expected .., found .." at `ctx`. An infinite type found while
+    /// unifying gets its own note.
+    fn mismatch(&self, ctx: &Ctx, expected: &Ty, actual: &Ty) -> Diagnostic {
+        let types = self.show(&[expected, actual]);
+        let diagnostic =
+            Diagnostic::error(ctx, format!("Expected {}, found {}", types[0], types[1]))
+                .with_label(format!("expected {}", types[0]));
+        match self.unify_probe(expected, actual) {
+            Some(Mismatch::Infinite(var)) => diagnostic.with_note(format!(
+                "this would need the infinite type {} = {}",
+                self.show(&[&Ty::Var(var)])[0],
+                self.show(&[&Ty::Var(var), actual])[1]
+            )),
+            _ => diagnostic,
+        }
+    }
+
+    /// Why `expected` and `actual` do not unify, without solving anything.
+    fn unify_probe(&self, expected: &Ty, actual: &Ty) -> Option<Mismatch> {
+        let mut probe = Inferencer {
+            solutions: self.solutions.clone(),
+            addable: self.addable.clone(),
+            scopes: vec![],
+            globals: HashSet::new(),
+            returns: vec![],
+            declared: vec![],
+            errors: vec![],
+            methods: MethodTable::default(),
+            depth: Depth::new(DEFAULT_MAX_DEPTH),
+        };
+        probe.unify(expected, actual).err()
+    }
+
+    fn unify(&mut self, a: &Ty, b: &Ty) -> Result<(), Mismatch> {
+        match (self.resolve(a), self.resolve(b)) {
+            (Ty::Var(a), Ty::Var(b)) if a == b => Ok(()),
+            (Ty::Var(var), ty) | (ty, Ty::Var(var)) => self.solve(var, ty),
+            (Ty::Function(a_params, a_ret), Ty::Function(b_params, b_ret)) => {
+                if a_params.len() != b_params.len() {
+                    return Err(Mismatch::Types);
+                }
+                for (a, b) in a_params.iter().zip(&b_params) {
+                    self.unify(a, b)?;
+                }
+                self.unify(&a_ret, &b_ret)
+            }
+            (a, b) if a == b => Ok(()),
+            _ => Err(Mismatch::Types),
+        }
+    }
+
+    fn solve(&mut self, var: usize, ty: Ty) -> Result<(), Mismatch> {
+        if self.occurs(var, &ty) {
+            return Err(Mismatch::Infinite(var));
+        }
+        if self.addable[var] && !self.make_addable(&ty) {
+            return Err(Mismatch::Types);
+        }
+        self.solutions[var] = Some(ty);
+        Ok(())
+    }
+
+    /// Restricts `ty` to Number or String, false if it is neither.
+    fn make_addable(&mut self, ty: &Ty) -> bool {
+        match self.resolve(ty) {
+            Ty::Var(var) => {
+                self.addable[var] = true;
+                true
+            }
+            Ty::Number | Ty::String => true,
+            _ => false,
+        }
+    }
+
+    fn occurs(&self, var: usize, ty: &Ty) -> bool {
+        match self.resolve(ty) {
+            Ty::Var(other) => other == var,
+            Ty::Function(params, ret) => {
+                params.iter().any(|param| self.occurs(var, param)) || self.occurs(var, &ret)
+            }
+            _ => false,
+        }
+    }
+
+    /// `ty` with the outermost solved variables replaced by their solution.
+    fn resolve(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Var(var) => match &self.solutions[*var] {
+                Some(solution) => self.resolve(solution),
+                None => ty.clone(),
+            },
+            _ => ty.clone(),
+        }
+    }
+
+    /// `ty` with every solved variable replaced by its solution.
+    fn zonk(&self, ty: &Ty) -> Ty {
+        match self.resolve(ty) {
+            Ty::Function(params, ret) => Ty::Function(
+                params.iter().map(|param| self.zonk(param)).collect(),
+                Box::new(self.zonk(&ret)),
+            ),
+            ty => ty,
+        }
+    }
+
+    fn free_vars(&self, ty: &Ty, vars: &mut Vec<usize>) {
+        match self.resolve(ty) {
+            Ty::Var(var) if !vars.contains(&var) => vars.push(var),
+            Ty::Function(params, ret) => {
+                for param in &params {
+                    self.free_vars(param, vars);
+                }
+                self.free_vars(&ret, vars);
+            }
+            _ => {}
+        }
+    }
+
+    /// Quantifies the variables of `ty` that no binding in scope mentions.
+    fn generalize(&self, ty: &Ty) -> Scheme {
+        let mut in_scope = vec![];
+        for scheme in self.scopes.iter().flat_map(HashMap::values) {
+            let mut vars = vec![];
+            self.free_vars(&scheme.ty, &mut vars);
+            in_scope.extend(vars.into_iter().filter(|var| !scheme.vars.contains(var)));
+        }
+        for returns in &self.returns {
+            self.free_vars(returns, &mut in_scope);
+        }
+        let in_scope = in_scope.into_iter().collect::<HashSet<usize>>();
+
+        let mut vars = vec![];
+        self.free_vars(ty, &mut vars);
+        Scheme {
+            vars: vars
+                .into_iter()
+                .filter(|var| !in_scope.contains(var))
+                .collect(),
+            ty: self.zonk(ty),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Ty {
+        let fresh = scheme
+            .vars
+            .iter()
+            .map(|var| {
+                let ty = self.fresh();
+                if let Ty::Var(new) = ty {
+                    self.addable[new] = self.addable[*var];
+                }
+                (*var, ty)
+            })
+            .collect::<HashMap<usize, Ty>>();
+        self.substitute(&scheme.ty, &fresh)
+    }
+
+    fn substitute(&self, ty: &Ty, fresh: &HashMap<usize, Ty>) -> Ty {
+        match self.resolve(ty) {
+            Ty::Var(var) => fresh.get(&var).cloned().unwrap_or(Ty::Var(var)),
+            Ty::Function(params, ret) => Ty::Function(
+                params
+                    .iter()
+                    .map(|param| self.substitute(param, fresh))
+                    .collect(),
+                Box::new(self.substitute(&ret, fresh)),
+            ),
+            ty => ty,
+        }
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.solutions.push(None);
+        self.addable.push(false);
+        Ty::Var(self.solutions.len() - 1)
+    }
+
+    /// The type of an annotation, reporting unknown type names.
+    fn annotated(&mut self, annotation: &TypeExpr) -> Ty {
+        let ty = Type::annotated(annotation, &self.methods, &mut self.errors);
+        self.lower(&ty)
+    }
+
+    /// The generalized type of the constructor of the struct `name`.
+    fn constructor(&mut self, name: &Token, fields: &[Param]) -> Scheme {
+        let params = fields
+            .iter()
+            .map(|field| match &field.annotation {
+                Some(annotation) => self.annotated(annotation),
+                None => self.fresh(),
+            })
+            .collect();
+        let instance = Ty::Struct(name.lexeme.to_string());
+        self.generalize(&Ty::Function(params, Box::new(instance)))
+    }
+
+    /// The inference type for `ty`, `Any` becomes a fresh variable.
+    fn lower(&mut self, ty: &Type) -> Ty {
+        match ty {
+            Type::Any => self.fresh(),
+            Type::Number => Ty::Number,
+            Type::String => Ty::String,
+            Type::Bool => Ty::Bool,
+            Type::Nil => Ty::Nil,
+            Type::List => Ty::List,
+            Type::Map => Ty::Map,
+            Type::Result => Ty::Result,
+            Type::Module => Ty::Module,
+            Type::Struct(name) => Ty::Struct(name.clone()),
+            Type::Function(params, ret) => Ty::Function(
+                params.iter().map(|param| self.lower(param)).collect(),
+                Box::new(self.lower(ret)),
+            ),
+        }
+    }
+
+    fn bind(&mut self, name: &str, scheme: Scheme) {
+        self.scopes
+            .last_mut()
+            .expect("the global scope is never popped")
+            .insert(name.to_string(), scheme);
+    }
+
+    /// Binds a declaration, remembering the globals for `declarations`.
+    fn declare(&mut self, name: &str, scheme: Scheme) {
+        if self.scopes.len() == 1 {
+            self.declared.retain(|declared| declared != name);
+            self.declared.push(name.to_string());
+        }
+        self.bind(name, scheme);
+    }
+
+    fn remove(&mut self, name: &str) -> Option<Scheme> {
+        self.scopes.last_mut()?.remove(name)
+    }
+
+    fn lookup(&mut self, ctx: &Ctx, name: &str) -> Ty {
+        let scheme = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned();
+        match scheme {
+            Some(scheme) => self.instantiate(&scheme),
+            // Used before its declaration, which the resolver reports
+            None if self.globals.contains(name) => self.fresh(),
+            None => {
+                let diagnostic = Diagnostic::error(ctx, format!("Name '{}' is not defined", name))
+                    .with_label("not found in this scope".to_string());
+                self.errors.push(UvlError::new(ErrorKind::Name, diagnostic));
+                self.fresh()
+            }
+        }
+    }
+
+    /// Renders types for a diagnostic, naming their variables 'a, 'b, ..
+    /// consistently across all of them.
+    fn show(&self, types: &[&Ty]) -> Vec<String> {
+        let mut names = vec![];
+        types.iter().map(|ty| self.render(ty, &mut names)).collect()
+    }
+
+    fn show_scheme(&self, scheme: &Scheme) -> String {
+        let mut names = vec![];
+        let rendered = self.render(&scheme.ty, &mut names);
+        let addable = names
+            .iter()
+            .filter(|var| self.addable[**var])
+            .map(|var| self.render(&Ty::Var(*var), &mut names.clone()))
+            .collect::<Vec<String>>();
+        if addable.is_empty() {
+            rendered
+        } else {
+            format!("{} where {}: Number | String", rendered, addable.join(", "))
+        }
+    }
+
+    fn render(&self, ty: &Ty, names: &mut Vec<usize>) -> String {
+        match self.resolve(ty) {
+            Ty::Var(var) => {
+                let index = names
+                    .iter()
+                    .position(|name| *name == var)
+                    .unwrap_or_else(|| {
+                        names.push(var);
+                        names.len() - 1
+                    });
+                match index {
+                    0..=25 => format!("'{}", (b'a' + index as u8) as char),
+                    _ => format!("'t{}", index),
+                }
+            }
+            Ty::Number => "Number".to_string(),
+            Ty::String => "String".to_string(),
+            Ty::Bool => "Bool".to_string(),
+            Ty::Nil => "Nil".to_string(),
+            Ty::List => "List".to_string(),
+            Ty::Map => "Map".to_string(),
+            Ty::Result => "Result".to_string(),
+            Ty::Module => "Module".to_string(),
+            Ty::Struct(name) => name,
+            Ty::Function(params, ret) => {
+                let params = params
+                    .iter()
+                    .map(|param| self.render(param, names))
+                    .collect::<Vec<String>>();
+                format!("fun({}) -> {}", params.join(", "), self.render(&ret, names))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+
+    fn infer(source: &str) -> Result<Vec<String>, Vec<String>> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.scan().unwrap().clone();
+        let mut stmts = Parser::new(&tokens, "main.uvl", false).parse().unwrap();
+        Resolver::new().resolve(&mut stmts).unwrap();
+        let mut inferencer = Inferencer::new();
+        match inferencer.infer(&stmts, &MethodTable::default(), HashSet::new()) {
+            Ok(()) => Ok(inferencer.declarations()),
+            Err(errors) => Err(errors
+                .iter()
+                .map(|e| format!("{}:{} {}", e.ctx().line, e.ctx().column, e.message()))
+                .collect()),
+        }
+    }
+
+    #[test]
+    fn infers_unannotated_programs() {
+        let source = "fun add(a, b) { return a - b; }\n\
+                      fun greet(name) { return \"hi \" + name; }\n\
+                      fun apply(f, x) { return f(x); }\n\
+                      let n = apply(greet, \"bob\");\n\
+                      const LIMIT = 10;";
+        assert_eq!(
+            infer(source).unwrap(),
+            vec![
+                "add: fun(Number, Number) -> Number",
+                "greet: fun(String) -> String",
+                "apply: fun(fun('a) -> 'b, 'a) -> 'b",
+                "n: String",
+                "LIMIT: Number",
+            ]
+        );
+    }
+
+    #[test]
+    fn generalizes_functions() {
+        let source = "fun id(x) { return x; }\n\
+                      fun twice(x) { return x + x; }\n\
+                      let a = id(1) + twice(2);\n\
+                      let b = id(\"s\") + twice(\"t\");\n\
+                      let c = id(true);";
+        assert_eq!(
+            infer(source).unwrap(),
+            vec![
+                "id: fun('a) -> 'a",
+                "twice: fun('a) -> 'a where 'a: Number | String",
+                "a: Number",
+                "b: String",
+                "c: Bool",
+            ]
+        );
+    }
+
+    #[test]
+    fn infers_recursive_functions_and_closures() {
+        let source = "fun fact(n) { if n <= 1 { return 1; } return n * fact(n - 1); }\n\
+                      fun counter(start) {\n  let mut n = start;\n  \
+                      fun next() { n = n + 1; return n; }\n  return next;\n}";
+        assert_eq!(
+            infer(source).unwrap(),
+            vec![
+                "fact: fun(Number) -> Number",
+                "counter: fun(Number) -> fun() -> Number"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_inferred_type_errors() {
+        let source = "fun inc(n) { return n + 1; }\n\
+                      inc(\"one\");\n\
+                      fun f(flag) { if flag { return 1; } }\n\
+                      let b = true + true;\n\
+                      fun self_apply(x) { return x(x); }\n\
+                      if inc(1) { }";
+        assert_eq!(
+            infer(source).unwrap_err(),
+            vec![
+                "2:5 Expected Number, found String",
+                "3:5 Function 'f' may finish without returning a Number",
+                "4:9 Operator '+' is not supported for Bool and Bool",
+                "5:28 Expected fun('a) -> 'b, found 'a",
+                "6:4 Expected a Bool condition, found Number",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_undefined_globals() {
+        let source = "fun f() { return later + missing; }\nlet later = 1;\nmissing = 2;";
+        assert_eq!(
+            infer(source).unwrap_err(),
+            vec![
+                "1:26 Name 'missing' is not defined",
+                "3:1 Name 'missing' is not defined",
+            ]
+        );
+    }
+}
@@ -1,160 +1,1981 @@
-use crate::ast::{Ctx, Expr, Stmt};
-use crate::common::make_error_msg;
-use crate::envr::Environment;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::ast::{Binding, Ctx, Expr, Param, Stmt, TypeExpr};
+use crate::checker::TypeChecker;
+use crate::common::{make_location, nesting_error};
+use crate::diagnostic::{Diagnostic, Level};
+use crate::env;
+use crate::envr::{Entry, Environment};
+use crate::error::{ErrorKind, Frame, UvlError};
+use crate::fs::{self, FsAccess};
+use crate::infer::Inferencer;
+use crate::io;
+use crate::json;
+use crate::lexer::Lexer;
+use crate::math;
+use crate::methods;
+use crate::native::{self, NativeRegistry};
+use crate::parser::DEFAULT_MAX_DEPTH;
+use crate::process;
+use crate::resolver::{MemberUse, Resolver};
 use crate::token::{Token, TokenType};
-use crate::value::{UvlError, UvlResult, UvlValue};
+use crate::types::{MethodTable, Type};
+use crate::value::{UvlFunction, UvlModule, UvlResult, UvlStruct, UvlValue};
+
+pub type RunResult = Result<UvlValue, Vec<UvlError>>;
+
+/// Default limit on nested function calls, low enough for the 8 MiB main
+/// thread of a debug build.
+pub const DEFAULT_RECURSION_LIMIT: usize = 100;
+
+/// Declares the traits operators dispatch to, run by every interpreter.
+const PRELUDE: &str = include_str!("prelude.uvl");
+
+/// Statements compiled from `source`. They borrow from it with a `'static`
+/// lifetime, but only live as long as a clone of `source` does.
+struct Program {
+    stmts: Vec<Stmt<'static>>,
+    source: Rc<str>,
+}
+
+impl Program {
+    /// The text of `source`, for a syntax tree that is only ever stored
+    /// next to a clone of it.
+    fn text(source: &Rc<str>) -> &'static str {
+        // SAFETY: the text of an `Rc<str>` neither moves nor is freed while a
+        // clone is alive, and every tree borrowing from it is stored with
+        // one: in a `Program`, or in a `UvlFunction` made from its statements
+        unsafe { &*Rc::as_ptr(source) }
+    }
+}
+
+/// A function call in progress.
+struct CallFrame {
+    function: String,
+    call_site: Ctx,
+}
+
+/// A declared trait and its methods, in declaration order.
+#[derive(Clone)]
+struct Trait {
+    ctx: Ctx,
+    methods: Vec<TraitItem>,
+}
+
+#[derive(Clone)]
+struct TraitItem {
+    name: String,
+    ctx: Ctx,
+    arity: usize,
+    default: Option<Rc<UvlFunction>>,
+}
+
+/// A method an impl provides to a type.
+struct Method {
+    trait_name: String,
+    function: Rc<UvlFunction>,
+}
+
+/// A source file with its own globals. Module 0 is the program or the
+/// prompt, the others are imported.
+struct Module {
+    name: String,
+    file: String,
+    /// The canonical path of a module loaded from a file.
+    path: Option<PathBuf>,
+    globals: HashMap<String, Entry>,
+    /// The names the module declares at the top level, known before it runs.
+    declared: HashSet<String>,
+    exports: HashSet<String>,
+    checker: TypeChecker,
+    inferencer: Inferencer,
+}
+
+impl Module {
+    fn new(name: &str, file: &str, path: Option<PathBuf>) -> Self {
+        Module {
+            name: name.to_string(),
+            file: file.to_string(),
+            path,
+            globals: HashMap::new(),
+            declared: HashSet::new(),
+            exports: HashSet::new(),
+            checker: TypeChecker::new(),
+            inferencer: Inferencer::new(),
+        }
+    }
+}
 
 pub struct UvlInterpreter {
     prompt_mode: bool,
     had_error: bool,
-    environment: Box<Environment>,
+    modules: Vec<Module>,
+    /// The module whose globals are in scope.
+    current: usize,
+    /// Imported modules by canonical path, each is loaded once.
+    module_ids: HashMap<PathBuf, usize>,
+    /// The modules whose imports are being loaded, to detect cycles.
+    loading: Vec<usize>,
+    /// Modules compiled but not run yet, in an order that runs every
+    /// module after the ones it imports.
+    pending: Vec<(usize, Program)>,
+    search_path: Vec<PathBuf>,
+    /// The source of every file compiled, for rendering diagnostics.
+    sources: HashMap<String, Rc<str>>,
+    /// The source of the code running, which the functions it declares
+    /// borrow their bodies from.
+    source: Rc<str>,
+    environment: Option<Box<Environment>>,
+    call_stack: Vec<CallFrame>,
+    recursion_limit: usize,
+    /// How deeply statements and expressions may nest, in the source and
+    /// while running, counting the ones of every call in progress.
+    max_depth: usize,
+    depth: usize,
+    return_value: Option<UvlValue>,
+    warnings: Vec<Diagnostic>,
+    deny_warnings: bool,
+    strict_types: bool,
+    traits: HashMap<String, Trait>,
+    /// Methods by type name, then by method name.
+    impls: HashMap<String, HashMap<String, Method>>,
+    /// The methods of every impl compiled so far, for the type checkers.
+    methods: MethodTable,
+    /// Globals implemented in Rust, such as the `math` module.
+    natives: NativeRegistry,
+    /// Shared with the functions of the `fs` module.
+    fs_access: Rc<RefCell<FsAccess>>,
+    /// The arguments `env.args()` returns.
+    args: Rc<RefCell<Vec<String>>>,
+    /// Shared with the functions of the `env`, `io` and `process` modules.
+    system_access: Rc<Cell<bool>>,
 }
 
 impl UvlInterpreter {
+    /// A new interpreter. The default limits on calls and nesting keep the
+    /// native stack under 8 MiB even in debug builds; raise them only on a
+    /// thread with a bigger stack.
     pub fn new(prompt_mode: bool) -> Self {
-        UvlInterpreter {
+        let mut interp = UvlInterpreter {
             prompt_mode,
             had_error: false,
-            environment: Box::new(Environment::new(None)),
+            modules: vec![Module::new("root", "", None)],
+            current: 0,
+            module_ids: HashMap::new(),
+            loading: vec![],
+            pending: vec![],
+            search_path: vec![],
+            sources: HashMap::new(),
+            source: Rc::from(""),
+            environment: None,
+            call_stack: vec![],
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: 0,
+            return_value: None,
+            warnings: vec![],
+            deny_warnings: false,
+            strict_types: false,
+            traits: HashMap::new(),
+            impls: HashMap::new(),
+            methods: MethodTable::default(),
+            natives: NativeRegistry::standard(),
+            fs_access: Rc::new(RefCell::new(FsAccess::Denied)),
+            args: Rc::new(RefCell::new(vec![])),
+            system_access: Rc::new(Cell::new(false)),
+        };
+        let system = interp.system_access.clone();
+        interp.add_native_module("math", math::members());
+        interp.add_native_module("fs", fs::members(interp.fs_access.clone()));
+        let env = env::members(interp.args.clone());
+        interp.add_native_module("env", native::gate("env", &system, env));
+        interp.add_native_module(
+            "process",
+            native::gate("process", &system, process::members()),
+        );
+        interp.add_native_module("io", native::gate("io", &system, io::members()));
+        interp.add_native_module("json", json::members());
+        interp.load_prelude();
+        interp
+    }
+
+    /// Makes a module of native members available as the global `name`.
+    fn add_native_module(&mut self, name: &str, members: Vec<(&str, UvlValue)>) {
+        let mut module = Module::new(name, &format!("<{}>", name), None);
+        for (member, value) in members {
+            let entry = Entry {
+                is_mutable: false,
+                value,
+            };
+            module.globals.insert(member.to_string(), entry);
+            module.declared.insert(member.to_string());
+            module.exports.insert(member.to_string());
         }
+        let id = self.modules.len();
+        self.modules.push(module);
+        let module = UvlModule {
+            id,
+            name: name.to_string(),
+        };
+        self.natives.define(name, UvlValue::Module(Rc::new(module)));
+    }
+
+    fn load_prelude(&mut self) {
+        let prompt_mode = std::mem::replace(&mut self.prompt_mode, false);
+        if let Err(errors) = self.run("<prelude>", PRELUDE) {
+            panic!("the prelude does not run: {}", errors[0].message());
+        }
+        self.prompt_mode = prompt_mode;
+    }
+
+    /// Sets how deeply function calls may nest before a RecursionError is
+    /// raised. A debug build needs roughly 32 KiB of native stack per call,
+    /// so raise `set_max_depth` along with it on a thread with a big stack.
+    pub fn set_recursion_limit(&mut self, limit: usize) {
+        self.recursion_limit = limit;
+    }
+
+    /// Sets how deeply statements and expressions may nest before a
+    /// RecursionError is raised, both in the source and while running, where
+    /// the levels of every call in progress add up. Every level takes native
+    /// stack, up to 16 KiB in a debug build, so a high limit needs a thread
+    /// with a big stack.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// With `deny`, warnings are reported as errors and stop the source
+    /// from running.
+    pub fn set_deny_warnings(&mut self, deny: bool) {
+        self.deny_warnings = deny;
+    }
+
+    /// With `strict`, the types of unannotated code are inferred and
+    /// checked too, instead of being left to the runtime.
+    pub fn set_strict_types(&mut self, strict: bool) {
+        self.strict_types = strict;
+    }
+
+    /// Lets scripts use the `fs` module, inside `root` and its
+    /// subdirectories when given. It is disabled by default.
+    pub fn allow_fs(&mut self, root: Option<&Path>) -> std::io::Result<()> {
+        let access = match root {
+            Some(root) => FsAccess::Under(root.canonicalize()?),
+            None => FsAccess::Anywhere,
+        };
+        *self.fs_access.borrow_mut() = access;
+        Ok(())
+    }
+
+    /// Lets scripts use the `env`, `io` and `process` modules, which read the
+    /// environment, use the standard streams and exit the process. They are
+    /// disabled by default.
+    pub fn allow_system(&mut self, allow: bool) {
+        self.system_access.set(allow);
+    }
+
+    /// Sets the arguments the script was given, which `env.args()` returns.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        *self.args.borrow_mut() = args;
+    }
+
+    /// The inferred types of the globals declared by the last source, as
+    /// `name: type`. Only filled in with strict types.
+    pub fn inferred_types(&self) -> Vec<String> {
+        self.modules[0].inferencer.declarations()
+    }
+
+    /// Adds a directory imports are looked up in when they are not found
+    /// next to the importing file.
+    pub fn add_search_path(&mut self, dir: PathBuf) {
+        self.search_path.push(dir);
+    }
+
+    /// The source of a file compiled so far, to render its diagnostics.
+    pub fn source(&self, file: &str) -> Option<&str> {
+        self.sources.get(file).map(|source| &**source)
+    }
+
+    /// Warnings found while compiling the sources run so far.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
     }
 
     pub fn reset(&mut self) {
         self.had_error = false;
     }
 
-    pub fn run(&mut self, source_name: &str, source: &str) -> UvlResult {
-        let mut lexer = crate::lexer::Lexer::new(source);
-        let tokens = lexer.scan().clone();
-        let mut parser = crate::parser::Parser::new(&tokens, source_name, self.prompt_mode);
+    /// Lexes, parses, resolves, type checks and runs `source`. Errors found
+    /// before running are all reported together, execution stops at the
+    /// first runtime error.
+    pub fn run(&mut self, source_name: &str, source: &str) -> RunResult {
+        let program = self.compile(source_name, source, true)?;
+        let source = std::mem::replace(&mut self.source, program.source);
+        let result = self.execute(&program.stmts);
+        self.source = source;
+        result.map_err(|e| vec![e])
+    }
+
+    /// Lexes, parses, resolves and type checks `source` without running it
+    /// or the modules it imports.
+    pub fn check(&mut self, source_name: &str, source: &str) -> Result<(), Vec<UvlError>> {
+        self.compile(source_name, source, false).map(|_| ())
+    }
 
-        match parser.parse() {
-            Ok(stmts) => self.execute(&stmts),
-            Err(e) => Err(UvlError::ParserError(e)),
+    /// Compiles `source` into the current module. With `load_imports`, the
+    /// modules it imports are loaded and run first.
+    fn compile(
+        &mut self,
+        source_name: &str,
+        source: &str,
+        load_imports: bool,
+    ) -> Result<Program, Vec<UvlError>> {
+        let text = Rc::from(source);
+        self.sources
+            .insert(source_name.to_string(), Rc::clone(&text));
+        let source = Program::text(&text);
+        if self.current == 0 {
+            let root = &mut self.modules[0];
+            root.file = source_name.to_string();
+            root.path = Path::new(source_name).canonicalize().ok();
+        }
+        let mut lexer = Lexer::new(source);
+        let tokens = match lexer.scan() {
+            Ok(tokens) => tokens.clone(),
+            Err(errors) => {
+                return Err(errors
+                    .iter()
+                    .map(|e| Lexer::make_lex_error(source_name, e))
+                    .collect())
+            }
+        };
+        let module_name = self.modules[self.current].name.clone();
+        let mut parser = crate::parser::Parser::new(&tokens, source_name, self.prompt_mode)
+            .with_module(&module_name)
+            .with_max_depth(self.max_depth);
+
+        let mut stmts = parser.parse()?;
+        let mut resolver = Resolver::new()
+            .with_prompt_mode(self.prompt_mode)
+            .with_max_depth(self.max_depth);
+        let mut errors = resolver.resolve(&mut stmts).err().unwrap_or_default();
+        self.methods.scan(&stmts);
+        let module = &mut self.modules[self.current];
+        let checked = if self.strict_types {
+            // Names the source does not declare are looked up here when it runs
+            let defined = module
+                .globals
+                .keys()
+                .map(String::as_str)
+                .chain(self.natives.names())
+                .map(str::to_string)
+                .collect();
+            module.inferencer.set_max_depth(self.max_depth);
+            module.inferencer.infer(&stmts, &self.methods, defined)
+        } else {
+            module.checker.set_max_depth(self.max_depth);
+            module.checker.check(&stmts, &self.methods)
+        };
+        errors.extend(checked.err().unwrap_or_default());
+        let members = resolver.take_members();
+        let warnings = resolver.take_warnings();
+        if self.deny_warnings && !warnings.is_empty() {
+            errors.extend(warnings.into_iter().map(|mut warning| {
+                warning.level = Level::Error;
+                UvlError::new(
+                    ErrorKind::Lint,
+                    warning.with_note("warnings are denied".to_string()),
+                )
+            }));
+        } else {
+            self.warnings.extend(warnings);
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
         }
+
+        let module = &mut self.modules[self.current];
+        for stmt in &stmts {
+            let names = match stmt.declaration() {
+                Stmt::Let(_, name, ..)
+                | Stmt::Const(_, name, ..)
+                | Stmt::Function(_, name, ..)
+                | Stmt::Struct(_, name, ..)
+                | Stmt::Import(_, _, name) => vec![name],
+                Stmt::Use(_, _, names) => names.iter().collect(),
+                _ => vec![],
+            };
+            let is_pub = matches!(stmt, Stmt::Pub(..));
+            for name in names {
+                module.declared.insert(name.lexeme.to_string());
+                if is_pub {
+                    module.exports.insert(name.lexeme.to_string());
+                }
+            }
+        }
+        if load_imports {
+            self.load_imports(&stmts, &members)?;
+        }
+        Ok(Program {
+            stmts,
+            source: text,
+        })
     }
 
-    fn execute(&mut self, stmts: &Vec<Stmt>) -> UvlResult {
+    /// Loads the modules `stmts` import, before the importing module runs.
+    /// The `members` of modules the source uses are checked before any
+    /// module runs, and the outermost import runs them all.
+    fn load_imports(
+        &mut self,
+        stmts: &[Stmt<'static>],
+        members: &[MemberUse],
+    ) -> Result<(), Vec<UvlError>> {
+        self.loading.push(self.current);
+        let mut errors = vec![];
+        let mut aliases = HashMap::new();
+        for stmt in stmts {
+            if let Stmt::Import(ctx, path, alias) = stmt {
+                match self.load_module(ctx, path) {
+                    Ok(id) => {
+                        aliases.insert(alias.lexeme, id);
+                    }
+                    Err(e) => errors.extend(e),
+                }
+            }
+        }
+        for member in members {
+            if let Some(id) = aliases.get(member.global.as_str()) {
+                if !self.modules[*id].exports.contains(&member.member) {
+                    errors.push(self.no_export(&member.ctx, *id, &member.global, &member.member));
+                }
+            }
+        }
+        self.loading.pop();
+        if !self.loading.is_empty() {
+            return if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            };
+        }
+
+        let mut pending = std::mem::take(&mut self.pending).into_iter();
+        if errors.is_empty() {
+            for (id, program) in pending.by_ref() {
+                if let Err(e) = self.run_module(id, program) {
+                    errors.push(e);
+                    self.module_ids.retain(|_, loaded| *loaded != id);
+                    break;
+                }
+            }
+        }
+        // Modules that did not run are loaded again by the next import
+        for (id, _) in pending {
+            self.module_ids.retain(|_, loaded| *loaded != id);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Runs the compiled module `id` from the top, in its own globals.
+    fn run_module(&mut self, id: usize, program: Program) -> UvlResult {
+        let current = std::mem::replace(&mut self.current, id);
+        let prompt_mode = std::mem::replace(&mut self.prompt_mode, false);
+        let environment = self.environment.take();
+        let source = std::mem::replace(&mut self.source, program.source);
+        let result = self.execute(&program.stmts);
+        self.current = current;
+        self.prompt_mode = prompt_mode;
+        self.environment = environment;
+        self.source = source;
+        result
+    }
+
+    /// Compiles the module `path` refers to, unless it is loaded already,
+    /// and returns its id. It runs along with the other pending modules.
+    fn load_module(&mut self, ctx: &Ctx, path: &Token) -> Result<usize, Vec<UvlError>> {
+        let (file, canonical) = self.find_module(ctx, path).map_err(|e| vec![e])?;
+        if let Some(id) = self.module_ids.get(&canonical) {
+            return Ok(*id);
+        }
+        let is_loading = |id: &usize| self.modules[*id].path.as_ref() == Some(&canonical);
+        if let Some(start) = self.loading.iter().position(is_loading) {
+            let mut cycle = self.loading[start..]
+                .iter()
+                .map(|id| self.modules[*id].file.clone())
+                .collect::<Vec<String>>();
+            cycle.push(file);
+            return Err(vec![UvlError::new(
+                ErrorKind::Import,
+                Diagnostic::error(ctx, format!("Import cycle: {}", cycle.join(" -> ")))
+                    .with_label("imported here".to_string())
+                    .with_note("modules cannot import each other, directly or not".to_string()),
+            )]);
+        }
+
+        let source = std::fs::read_to_string(&canonical).map_err(|e| {
+            vec![UvlError::new(
+                ErrorKind::Import,
+                Diagnostic::error(ctx, format!("Cannot read module '{}': {}", file, e))
+                    .with_label("imported here".to_string()),
+            )]
+        })?;
+        let name = canonical
+            .file_stem()
+            .map_or(file.clone(), |stem| stem.to_string_lossy().to_string());
+        let id = self.modules.len();
+        self.modules
+            .push(Module::new(&name, &file, Some(canonical.clone())));
+
+        let current = std::mem::replace(&mut self.current, id);
+        let prompt_mode = std::mem::replace(&mut self.prompt_mode, false);
+        let result = self.compile(&file, &source, true);
+        self.current = current;
+        self.prompt_mode = prompt_mode;
+
+        self.pending.push((id, result?));
+        self.module_ids.insert(canonical, id);
+        Ok(id)
+    }
+
+    /// The file an import refers to, as named in diagnostics and as a
+    /// canonical path. Paths are relative to the importing file, then to
+    /// the directories of the search path.
+    fn find_module(&self, ctx: &Ctx, path: &Token) -> Result<(String, PathBuf), UvlError> {
+        let requested = match path.ttype {
+            TokenType::String(requested) => requested,
+            _ => path.lexeme,
+        };
+        let importer = Path::new(&ctx.file).parent().unwrap_or(Path::new(""));
+        let candidates = std::iter::once(importer)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(requested))
+            .collect::<Vec<PathBuf>>();
+        for candidate in &candidates {
+            if let Ok(canonical) = candidate.canonicalize() {
+                if canonical.is_file() {
+                    return Ok((candidate.display().to_string(), canonical));
+                }
+            }
+        }
+
+        let searched = candidates
+            .iter()
+            .map(|candidate| candidate.display().to_string())
+            .collect::<Vec<String>>();
+        Err(UvlError::new(
+            ErrorKind::Import,
+            Diagnostic::error(
+                &Ctx::from_token(&ctx.file, path),
+                format!("Module '{}' not found", requested),
+            )
+            .with_label("imported here".to_string())
+            .with_note(format!("searched {}", searched.join(", "))),
+        ))
+    }
+
+    fn execute(&mut self, stmts: &[Stmt<'static>]) -> UvlResult {
         if self.prompt_mode {
-            self.exec_statement(&stmts[0])
+            match stmts.first() {
+                Some(stmt) => self.exec_statement(stmt),
+                None => Ok(UvlValue::Nil(())),
+            }
         } else {
             for stmt in stmts {
-                if let Err(e) = self.exec_statement(stmt) {
-                    return Err(e);
-                }
+                self.exec_statement(stmt)?;
             }
 
             Ok(UvlValue::Nil(()))
         }
     }
 
-    fn exec_statement(&mut self, stmt: &Stmt) -> UvlResult {
+    fn exec_statement(&mut self, stmt: &Stmt<'static>) -> UvlResult {
+        self.nested(stmt.ctx(), |interp| interp.exec_nested_statement(stmt))
+    }
+
+    fn exec_nested_statement(&mut self, stmt: &Stmt<'static>) -> UvlResult {
         match stmt {
             Stmt::Expression(_, expr) => self.eval_expr(expr),
             Stmt::PrintLn(_, expr) => match self.eval_expr(expr) {
                 Ok(val) => self.exec_println(&val),
                 Err(e) => Err(e),
             },
-            Stmt::Let(_, token, is_mutable, expr) => match self.eval_expr(expr) {
+            Stmt::Let(_, token, is_mutable, annotation, expr, _) => match self.eval_expr(expr) {
                 Ok(val) => {
-                    self.environment.define(token.lexeme, is_mutable.0, val);
+                    let declared = Type::from_annotation(annotation.as_ref(), &self.methods);
+                    if !declared.admits(&val) {
+                        return Err(UvlError::new(
+                            ErrorKind::Runtime,
+                            Diagnostic::error(
+                                expr.ctx(),
+                                format!(
+                                    "Expected {} for '{}', found {} of type {}",
+                                    declared,
+                                    token.lexeme,
+                                    val,
+                                    val.type_str()
+                                ),
+                            )
+                            .with_label(format!("expected {}", declared)),
+                        ));
+                    }
+                    self.define(token.lexeme, is_mutable.0, val);
                     Ok(UvlValue::Nil(()))
                 }
                 Err(e) => Err(e),
             },
-            Stmt::Block(_, stmts) => self.exec_block(
-                &stmts,
-                Box::new(Environment::new(Some(self.environment.clone()))),
-            ),
+            Stmt::Const(_, token, expr, _) => {
+                let value = self.eval_expr(expr)?;
+                self.define(token.lexeme, false, value);
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Block(_, stmts) => {
+                self.exec_block(stmts, Box::new(Environment::new(self.environment.clone())))
+            }
+            Stmt::If(_, condition, then_branch, else_branch) => match self.eval_expr(condition)? {
+                UvlValue::Bool(true) => self.exec_statement(then_branch),
+                UvlValue::Bool(false) => match else_branch {
+                    Some(else_branch) => self.exec_statement(else_branch),
+                    None => Ok(UvlValue::Nil(())),
+                },
+                value => Err(UvlError::new(
+                    ErrorKind::Runtime,
+                    Diagnostic::error(
+                        condition.ctx(),
+                        format!(
+                            "Expected a Bool condition, found {} of type {}",
+                            value,
+                            value.type_str()
+                        ),
+                    )
+                    .with_label("expected Bool".to_string()),
+                )),
+            },
+            Stmt::Function(ctx, name, params, return_type, body, _) => {
+                let function =
+                    self.make_function(ctx, name.lexeme, params, return_type.as_ref(), body);
+                self.define(name.lexeme, false, UvlValue::Function(function));
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Struct(_, name, fields, _) => {
+                let constructor = self.make_constructor(name.lexeme, fields);
+                self.define(name.lexeme, false, constructor);
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Trait(ctx, name, methods, _) => {
+                let methods = methods
+                    .iter()
+                    .map(|method| TraitItem {
+                        name: method.name.lexeme.to_string(),
+                        ctx: method.ctx.clone(),
+                        arity: method.params.len(),
+                        default: method.body.as_ref().map(|body| {
+                            self.make_function(
+                                &method.ctx,
+                                method.name.lexeme,
+                                &method.params,
+                                method.return_type.as_ref(),
+                                body,
+                            )
+                        }),
+                    })
+                    .collect();
+                self.traits.insert(
+                    name.lexeme.to_string(),
+                    Trait {
+                        ctx: Ctx::from_token(&ctx.file, name),
+                        methods,
+                    },
+                );
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Impl(ctx, trait_name, type_name, methods) => {
+                self.exec_impl(ctx, trait_name, type_name, methods)?;
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Return(_, expr) => {
+                let value = match expr {
+                    Some(expr) => self.eval_expr(expr)?,
+                    None => UvlValue::Nil(()),
+                };
+                self.return_value = Some(value);
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Import(ctx, path, name) => {
+                let (_, canonical) = self.find_module(ctx, path)?;
+                let id = self.module_ids[&canonical];
+                let module = Rc::new(UvlModule {
+                    id,
+                    name: name.lexeme.to_string(),
+                });
+                self.define(name.lexeme, false, UvlValue::Module(module));
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Use(ctx, module_name, names) => {
+                let module = match self.lookup(module_name.lexeme, Binding::Global) {
+                    Some(Entry {
+                        value: UvlValue::Module(module),
+                        ..
+                    }) => module,
+                    Some(entry) => {
+                        return Err(UvlError::new(
+                            ErrorKind::Runtime,
+                            Diagnostic::error(
+                                &Ctx::from_token(&ctx.file, module_name),
+                                format!(
+                                    "Expected a module, found {} of type {}",
+                                    entry.value,
+                                    entry.value.type_str()
+                                ),
+                            )
+                            .with_label("not a module".to_string()),
+                        ))
+                    }
+                    None => {
+                        let module_ctx = Ctx::from_token(&ctx.file, module_name);
+                        return Err(UvlInterpreter::undefined(
+                            &module_ctx,
+                            module_name.lexeme,
+                            Binding::Global,
+                        ));
+                    }
+                };
+                for name in names {
+                    let value =
+                        self.export(&Ctx::from_token(&ctx.file, name), &module, name.lexeme)?;
+                    self.define(name.lexeme, false, value);
+                }
+                Ok(UvlValue::Nil(()))
+            }
+            Stmt::Pub(_, stmt) => self.exec_statement(stmt),
         }
     }
 
-    fn exec_block(&mut self, stmts: &Vec<Box<Stmt>>, environment: Box<Environment>) -> UvlResult {
-        let prev = self.environment.clone();
-        self.environment = environment;
+    fn exec_block(&mut self, stmts: &[Stmt<'static>], environment: Box<Environment>) -> UvlResult {
+        let prev = self.environment.replace(environment);
 
         for stmt in stmts {
-            if let Err(e) = self.exec_statement(&stmt) {
+            if let Err(e) = self.exec_statement(stmt) {
                 self.environment = prev;
                 return Err(e);
             }
+
+            if self.return_value.is_some() {
+                break;
+            }
         }
 
         self.environment = prev;
         Ok(UvlValue::Nil(()))
     }
 
+    /// A function closing over the current environment.
+    fn make_function(
+        &self,
+        ctx: &Ctx,
+        name: &str,
+        params: &[Param],
+        return_type: Option<&TypeExpr>,
+        body: &Rc<Vec<Stmt<'static>>>,
+    ) -> Rc<UvlFunction> {
+        Rc::new(UvlFunction {
+            ctx: ctx.clone(),
+            name: name.to_string(),
+            params: params.iter().map(|p| p.name.lexeme.to_string()).collect(),
+            param_types: params
+                .iter()
+                .map(|p| Type::from_annotation(p.annotation.as_ref(), &self.methods))
+                .collect(),
+            return_type: Type::from_annotation(return_type, &self.methods),
+            module: self.current,
+            body: body.clone(),
+            source: Rc::clone(&self.source),
+            closure: self.environment.clone(),
+        })
+    }
+
+    /// The native function building instances of the struct `name`,
+    /// checking each field against its annotation.
+    fn make_constructor(&self, name: &str, fields: &[Param]) -> UvlValue {
+        let struct_name = name.to_string();
+        let fields: Vec<(String, Type)> = fields
+            .iter()
+            .map(|field| {
+                let declared = Type::from_annotation(field.annotation.as_ref(), &self.methods);
+                (field.name.lexeme.to_string(), declared)
+            })
+            .collect();
+        native::function(name, Some(fields.len()), move |args| {
+            let mut values = Vec::with_capacity(fields.len());
+            for ((field, declared), arg) in fields.iter().zip(args) {
+                if !declared.admits(arg) {
+                    return Err(format!(
+                        "Field '{}' of '{}' expects {}, found {} of type {}",
+                        field,
+                        struct_name,
+                        declared,
+                        arg,
+                        arg.type_str()
+                    ));
+                }
+                values.push((field.clone(), arg.clone()));
+            }
+            Ok(UvlValue::Struct(Rc::new(UvlStruct {
+                name: struct_name.clone(),
+                fields: values,
+            })))
+        })
+    }
+
+    /// Adds the methods of `impl trait_name for type_name` to the type,
+    /// filling in the trait's defaults.
+    fn exec_impl(
+        &mut self,
+        ctx: &Ctx,
+        trait_name: &Token,
+        type_name: &Token,
+        methods: &[Stmt<'static>],
+    ) -> Result<(), UvlError> {
+        let header =
+            Ctx::from_token(&ctx.file, trait_name).to(&Ctx::from_token(&ctx.file, type_name));
+        let declared = match self.traits.get(trait_name.lexeme) {
+            Some(declared) => declared.clone(),
+            None => {
+                return Err(UvlError::new(
+                    ErrorKind::Name,
+                    Diagnostic::error(
+                        &Ctx::from_token(&ctx.file, trait_name),
+                        format!("Trait '{}' is not defined", trait_name.lexeme),
+                    )
+                    .with_label("not found in this scope".to_string()),
+                ))
+            }
+        };
+        let declared_at = format!(
+            "'{}' is declared at {}",
+            trait_name.lexeme,
+            make_location(&declared.ctx.file, declared.ctx.line, declared.ctx.column)
+        );
+
+        let mut provided = HashMap::new();
+        for method in methods {
+            if let Stmt::Function(method_ctx, name, params, return_type, body, _) = method {
+                let name_ctx = Ctx::from_token(&method_ctx.file, name);
+                match declared
+                    .methods
+                    .iter()
+                    .find(|item| item.name == name.lexeme)
+                {
+                    None => {
+                        return Err(UvlError::new(
+                            ErrorKind::Runtime,
+                            Diagnostic::error(
+                                &name_ctx,
+                                format!(
+                                    "Method '{}' is not a member of trait '{}'",
+                                    name.lexeme, trait_name.lexeme
+                                ),
+                            )
+                            .with_label(format!("not a member of '{}'", trait_name.lexeme))
+                            .with_note(declared_at),
+                        ))
+                    }
+                    Some(item) if item.arity != params.len() => {
+                        return Err(UvlError::new(
+                            ErrorKind::Runtime,
+                            Diagnostic::error(
+                                &name_ctx,
+                                format!(
+                                    "Method '{}' has {} parameters but trait '{}' declares {}",
+                                    name.lexeme,
+                                    params.len(),
+                                    trait_name.lexeme,
+                                    item.arity
+                                ),
+                            )
+                            .with_note(format!(
+                                "'{}' is declared at {}",
+                                item.name,
+                                make_location(&item.ctx.file, item.ctx.line, item.ctx.column)
+                            )),
+                        ))
+                    }
+                    Some(_) => {
+                        let function = self.make_function(
+                            method_ctx,
+                            name.lexeme,
+                            params,
+                            return_type.as_ref(),
+                            body,
+                        );
+                        provided.insert(name.lexeme.to_string(), function);
+                    }
+                }
+            }
+        }
+
+        let mut missing = vec![];
+        for item in &declared.methods {
+            if provided.contains_key(&item.name) {
+                continue;
+            }
+            match &item.default {
+                Some(function) => {
+                    provided.insert(item.name.clone(), function.clone());
+                }
+                None => missing.push(item),
+            }
+        }
+        if !missing.is_empty() {
+            let names = missing
+                .iter()
+                .map(|item| format!("'{}'", item.name))
+                .collect::<Vec<String>>();
+            let mut diagnostic = Diagnostic::error(
+                &header,
+                format!(
+                    "Missing {} {} in impl of '{}' for {}",
+                    if missing.len() == 1 {
+                        "method"
+                    } else {
+                        "methods"
+                    },
+                    names.join(", "),
+                    trait_name.lexeme,
+                    type_name.lexeme
+                ),
+            )
+            .with_label(format!("missing {}", names.join(", ")));
+            for item in missing {
+                diagnostic = diagnostic.with_note(format!(
+                    "'{}' is declared at {}",
+                    item.name,
+                    make_location(&item.ctx.file, item.ctx.line, item.ctx.column)
+                ));
+            }
+            return Err(UvlError::new(ErrorKind::Runtime, diagnostic));
+        }
+
+        let table = self.impls.entry(type_name.lexeme.to_string()).or_default();
+        for name in provided.keys() {
+            if let Some(existing) = table.get(name) {
+                if existing.trait_name != trait_name.lexeme {
+                    return Err(UvlError::new(
+                        ErrorKind::Runtime,
+                        Diagnostic::error(
+                            &header,
+                            format!(
+                                "Method '{}' of {} is already provided by trait '{}'",
+                                name, type_name.lexeme, existing.trait_name
+                            ),
+                        )
+                        .with_label("conflicting implementation".to_string()),
+                    ));
+                }
+            }
+        }
+        for (name, function) in provided {
+            table.insert(
+                name,
+                Method {
+                    trait_name: trait_name.lexeme.to_string(),
+                    function,
+                },
+            );
+        }
+        Ok(())
+    }
+
     fn exec_println(&mut self, val: &UvlValue) -> UvlResult {
         println!("{}", val);
         Ok(UvlValue::Nil(()))
     }
 
-    fn eval_expr(&mut self, expr: &Expr) -> UvlResult {
+    fn eval_expr(&mut self, expr: &Expr<'static>) -> UvlResult {
+        self.nested(expr.ctx(), |interp| interp.eval_nested_expr(expr))
+    }
+
+    /// Runs `run` one nesting level deeper, raising a RecursionError once
+    /// the nesting exceeds `max_depth` instead of overflowing the native
+    /// stack.
+    fn nested(&mut self, ctx: &Ctx, run: impl FnOnce(&mut Self) -> UvlResult) -> UvlResult {
+        if self.depth >= self.max_depth {
+            return Err(nesting_error(ctx, self.max_depth));
+        }
+        self.depth += 1;
+        let result = run(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn eval_nested_expr(&mut self, expr: &Expr<'static>) -> UvlResult {
         match expr {
-            Expr::Binary(ctx, left, op, right) => self.eval_bin_expr(ctx, left, &op, right),
+            Expr::Binary(ctx, left, op, right) => self.eval_bin_expr(ctx, left, op, right),
             Expr::Grouping(_, expr) => self.eval_expr(expr),
-            Expr::Unary(ctx, op, expr) => match self.eval_expr(expr) {
-                Ok(expr) => expr.apply_operator(ctx, &op.ttype, None),
-                Err(e) => Err(e),
-            },
-            Expr::Literal(_, token) => match token.ttype {
-                TokenType::String(s) => Ok(UvlValue::String(s.to_string())),
-                TokenType::Number(n) => Ok(UvlValue::Number(n)),
-                _ => Ok(UvlValue::Nil(())),
-            },
-            Expr::Variable(ctx, token) => match self.environment.get(token.lexeme) {
+            Expr::Unary(ctx, op, expr) => {
+                let value = self.eval_expr(expr)?;
+                self.apply_operator(ctx, op, value, None)
+            }
+            Expr::Literal(_, token) => Ok(UvlValue::from_literal(token)),
+            Expr::Constant(_, value) => Ok(value.clone()),
+            Expr::Variable(ctx, token, binding) => match self.lookup(token.lexeme, *binding) {
                 Some(entry) => Ok(entry.value.clone()),
-                None => Err(UvlError::NameError(make_error_msg(
-                    ctx,
-                    format!("Name '{}' is not defined", token.lexeme),
-                ))),
+                None => Err(UvlInterpreter::undefined(ctx, token.lexeme, *binding)),
             },
-            Expr::Assign(ctx, token, expr) => {
-                if let Some(entry) = self.environment.get(token.lexeme) {
+            Expr::Assign(ctx, token, binding, expr) => {
+                if let Some(entry) = self.lookup(token.lexeme, *binding) {
                     if !entry.is_mutable {
-                        Err(UvlError::NameError(make_error_msg(
-                            ctx,
-                            format!("Name '{}' is immutable", token.lexeme),
-                        )))
+                        Err(UvlError::new(
+                            ErrorKind::Name,
+                            Diagnostic::error(ctx, format!("Name '{}' is immutable", token.lexeme))
+                                .with_label("cannot assign to an immutable binding".to_string())
+                                .with_help(format!("consider `let mut {}`", token.lexeme)),
+                        ))
                     } else {
                         match self.eval_expr(expr) {
                             Ok(val) => {
-                                self.environment.assign(token.lexeme, val);
+                                self.assign(token.lexeme, *binding, val);
                                 Ok(entry.value.clone())
                             }
                             Err(e) => Err(e),
                         }
                     }
                 } else {
-                    Err(UvlError::NameError(make_error_msg(
+                    Err(UvlInterpreter::undefined(ctx, token.lexeme, *binding))
+                }
+            }
+            Expr::Get(ctx, object, name) => match self.eval_expr(object)? {
+                UvlValue::Module(module) => {
+                    self.export(&Ctx::from_token(&ctx.file, name), &module, name.lexeme)
+                }
+                UvlValue::Struct(instance) if instance.field(name.lexeme).is_some() => Ok(instance
+                    .field(name.lexeme)
+                    .cloned()
+                    .expect("the field was just found")),
+                value => Err(UvlError::new(
+                    ErrorKind::Runtime,
+                    Diagnostic::error(
                         ctx,
-                        format!("Name '{}' is not defined", token.lexeme),
-                    )))
+                        format!(
+                            "{} of type {} has no member '{}'",
+                            value,
+                            value.type_str(),
+                            name.lexeme
+                        ),
+                    ),
+                )),
+            },
+            Expr::MethodCall(ctx, receiver, name, args) => {
+                let receiver = self.eval_expr(receiver)?;
+                let mut values = vec![receiver.clone()];
+                for arg in args {
+                    values.push(self.eval_expr(arg)?);
+                }
+
+                // Functions exported by a module are called like methods
+                if let UvlValue::Module(module) = &receiver {
+                    let name_ctx = Ctx::from_token(&ctx.file, name);
+                    let callee = self.export(&name_ctx, module, name.lexeme)?;
+                    return self.call_value(ctx, callee, values.split_off(1));
+                }
+
+                // Methods of impls shadow the native methods of the type
+                let name_ctx = Ctx::from_token(&ctx.file, name);
+                match self.method(&receiver, name.lexeme) {
+                    Some(function) if function.params.len() != values.len() => Err(UvlError::new(
+                        ErrorKind::Runtime,
+                        Diagnostic::error(
+                            ctx,
+                            format!(
+                                "Method '{}' expects {} arguments, got {}",
+                                name.lexeme,
+                                function.params.len() - 1,
+                                args.len()
+                            ),
+                        ),
+                    )),
+                    Some(function) => self.call_function(ctx, &function, values),
+                    None => match methods::call(ctx, &receiver, name.lexeme, &values[1..]) {
+                        Some(result) => result,
+                        None => Err(self.no_method(&name_ctx, &receiver, name.lexeme)),
+                    },
                 }
             }
+            Expr::Index(ctx, target, index) => {
+                let target = self.eval_expr(target)?;
+                let index = self.eval_expr(index)?;
+                match &target {
+                    UvlValue::List(items) => return methods::index(ctx, items, &index),
+                    UvlValue::Map(entries) => return methods::lookup(ctx, entries, &index),
+                    _ => (),
+                }
+                match self.method(&target, "index") {
+                    Some(function) => self.call_function(ctx, &function, vec![target, index]),
+                    None => Err(UvlError::new(
+                        ErrorKind::UnsupportedOperator,
+                        Diagnostic::error(
+                            ctx,
+                            format!("{} of type {} cannot be indexed", target, target.type_str()),
+                        )
+                        .with_help(format!("consider `impl Index for {}`", target.type_str())),
+                    )),
+                }
+            }
+            Expr::Call(ctx, callee, args) => {
+                let callee = self.eval_expr(callee)?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.eval_expr(arg))
+                    .collect::<Result<Vec<UvlValue>, UvlError>>()?;
+
+                self.call_value(ctx, callee, args)
+            }
+        }
+    }
+
+    fn call_value(&mut self, ctx: &Ctx, callee: UvlValue, args: Vec<UvlValue>) -> UvlResult {
+        match callee {
+            UvlValue::Function(function) => self.call_function(ctx, &function, args),
+            UvlValue::Native(function) => match function.arity {
+                Some(arity) if arity != args.len() => Err(UvlError::new(
+                    ErrorKind::Runtime,
+                    Diagnostic::error(
+                        ctx,
+                        format!(
+                            "Function '{}' expects {} arguments, got {}",
+                            function.name,
+                            arity,
+                            args.len()
+                        ),
+                    ),
+                )),
+                _ => (function.function)(&args).map_err(|message| {
+                    UvlError::new(ErrorKind::Runtime, Diagnostic::error(ctx, message))
+                }),
+            },
+            value => Err(UvlError::new(
+                ErrorKind::Runtime,
+                Diagnostic::error(
+                    ctx,
+                    format!("{} of type {} is not callable", value, value.type_str()),
+                ),
+            )),
+        }
+    }
+
+    /// The value a module exports as `name`.
+    fn export(&self, ctx: &Ctx, module: &UvlModule, name: &str) -> UvlResult {
+        let found = &self.modules[module.id];
+        match found.globals.get(name) {
+            Some(entry) if found.exports.contains(name) => Ok(entry.value.clone()),
+            _ => Err(self.no_export(ctx, module.id, &module.name, name)),
         }
     }
 
+    /// The error for using `name` of the module `id`, imported as `alias`,
+    /// which does not export it.
+    fn no_export(&self, ctx: &Ctx, id: usize, alias: &str, name: &str) -> UvlError {
+        let found = &self.modules[id];
+        let diagnostic = if found.declared.contains(name) {
+            Diagnostic::error(ctx, format!("'{}' is private to module '{}'", name, alias))
+                .with_label("not exported".to_string())
+                .with_help(format!("declare it with `pub` in {}", found.file))
+        } else {
+            Diagnostic::error(ctx, format!("Module '{}' has no member '{}'", alias, name))
+                .with_label("not found in the module".to_string())
+        };
+        UvlError::new(ErrorKind::Name, diagnostic)
+    }
+
+    fn call_function(
+        &mut self,
+        ctx: &Ctx,
+        function: &UvlFunction,
+        args: Vec<UvlValue>,
+    ) -> UvlResult {
+        let defined_at = || {
+            format!(
+                "'{}' is defined at {}",
+                function.name,
+                make_location(&function.ctx.file, function.ctx.line, function.ctx.column)
+            )
+        };
+        if args.len() != function.params.len() {
+            return Err(UvlError::new(
+                ErrorKind::Runtime,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Function '{}' expects {} arguments, got {}",
+                        function.name,
+                        function.params.len(),
+                        args.len()
+                    ),
+                )
+                .with_note(defined_at()),
+            ));
+        }
+
+        // Unannotated callers are only checked here, at the boundary
+        for ((param, declared), arg) in function.params.iter().zip(&function.param_types).zip(&args)
+        {
+            if !declared.admits(arg) {
+                return Err(UvlError::new(
+                    ErrorKind::Runtime,
+                    Diagnostic::error(
+                        ctx,
+                        format!(
+                            "Parameter '{}' of '{}' expects {}, found {} of type {}",
+                            param,
+                            function.name,
+                            declared,
+                            arg,
+                            arg.type_str()
+                        ),
+                    )
+                    .with_note(defined_at()),
+                ));
+            }
+        }
+
+        if self.call_stack.len() >= self.recursion_limit {
+            return Err(UvlError::new(
+                ErrorKind::Recursion,
+                Diagnostic::error(ctx, "Maximum recursion depth exceeded".to_string()).with_note(
+                    format!("the limit is {} nested calls", self.recursion_limit),
+                ),
+            ));
+        }
+
+        let mut environment = Environment::new(function.closure.clone());
+        for arg in args {
+            environment.define(false, arg);
+        }
+
+        self.call_stack.push(CallFrame {
+            function: function.name.clone(),
+            call_site: ctx.clone(),
+        });
+        // The body sees the globals of the module it is declared in
+        let module = std::mem::replace(&mut self.current, function.module);
+        let source = std::mem::replace(&mut self.source, Rc::clone(&function.source));
+        let result = match self.exec_block(&function.body, Box::new(environment)) {
+            Err(e) if e.traceback().is_empty() => {
+                let traceback = self.traceback(e.ctx());
+                Err(e.with_traceback(traceback))
+            }
+            result => result,
+        };
+        self.current = module;
+        self.source = source;
+        self.call_stack.pop();
+
+        let value = self.return_value.take().unwrap_or(UvlValue::Nil(()));
+        result?;
+        if !function.return_type.admits(&value) {
+            return Err(UvlError::new(
+                ErrorKind::Runtime,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Function '{}' must return {}, returned {} of type {}",
+                        function.name,
+                        function.return_type,
+                        value,
+                        value.type_str()
+                    ),
+                )
+                .with_note(defined_at()),
+            ));
+        }
+        Ok(value)
+    }
+
+    /// Binds `name` in the current scope, or as a global at the top level.
+    fn define(&mut self, name: &str, is_mutable: bool, value: UvlValue) {
+        match &mut self.environment {
+            Some(environment) => environment.define(is_mutable, value),
+            None => {
+                self.modules[self.current]
+                    .globals
+                    .insert(name.to_string(), Entry { is_mutable, value });
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str, binding: Binding) -> Option<Entry> {
+        match binding {
+            Binding::Global => match self.modules[self.current].globals.get(name) {
+                Some(entry) => Some(entry.clone()),
+                None => self.natives.get(name).map(|value| Entry {
+                    is_mutable: false,
+                    value: value.clone(),
+                }),
+            },
+            Binding::Local(depth, slot) => self.environment.as_ref()?.get(depth, slot),
+        }
+    }
+
+    fn assign(&mut self, name: &str, binding: Binding, value: UvlValue) -> bool {
+        match binding {
+            Binding::Global => match self.modules[self.current].globals.get_mut(name) {
+                Some(entry) => {
+                    entry.value = value;
+                    true
+                }
+                None => false,
+            },
+            Binding::Local(depth, slot) => match &mut self.environment {
+                Some(environment) => environment.assign(depth, slot, value),
+                None => false,
+            },
+        }
+    }
+
+    fn no_method(&self, ctx: &Ctx, receiver: &UvlValue, name: &str) -> UvlError {
+        let mut diagnostic = Diagnostic::error(
+            ctx,
+            format!("{} has no method '{}'", receiver.type_str(), name),
+        )
+        .with_label("method not found".to_string());
+        let mut traits = self
+            .traits
+            .iter()
+            .filter(|(_, declared)| declared.methods.iter().any(|item| item.name == name))
+            .map(|(trait_name, _)| trait_name.as_str())
+            .collect::<Vec<&str>>();
+        traits.sort();
+        for trait_name in traits {
+            diagnostic = diagnostic.with_help(format!(
+                "trait '{}' declares '{}', consider `impl {} for {}`",
+                trait_name,
+                name,
+                trait_name,
+                receiver.type_str()
+            ));
+        }
+        UvlError::new(ErrorKind::Name, diagnostic)
+    }
+
+    fn undefined(ctx: &Ctx, name: &str, binding: Binding) -> UvlError {
+        let (message, label) = match binding {
+            Binding::Global => (
+                format!("Name '{}' is not defined", name),
+                "not found in this scope",
+            ),
+            Binding::Local(..) => (
+                format!("Name '{}' is used before its declaration", name),
+                "not initialized yet",
+            ),
+        };
+        UvlError::new(
+            ErrorKind::Name,
+            Diagnostic::error(ctx, message).with_label(label.to_string()),
+        )
+    }
+
+    /// The frames of the current call stack, ending with the innermost one
+    /// at `ctx`.
+    fn traceback(&self, ctx: &Ctx) -> Vec<Frame> {
+        let mut frames = vec![];
+        let outermost = self.call_stack.first().map_or(ctx, |call| &call.call_site);
+        let mut function = format!("<{}>", outermost.module);
+        for call in &self.call_stack {
+            frames.push(Frame {
+                function,
+                ctx: call.call_site.clone(),
+            });
+            function = call.function.clone();
+        }
+        frames.push(Frame {
+            function,
+            ctx: ctx.clone(),
+        });
+        frames
+    }
+
     fn eval_bin_expr(
         &mut self,
         ctx: &Ctx,
-        left: &Box<Expr>,
+        left: &Expr<'static>,
+        op: &Token,
+        right: &Expr<'static>,
+    ) -> UvlResult {
+        let left_val = self.eval_expr(left)?;
+        let right_val = self.eval_expr(right)?;
+
+        self.apply_operator(ctx, op, left_val, Some(right_val))
+    }
+
+    /// Applies a binary operator, or a unary one without `right`. Operators
+    /// on structs and functions, and operators the built-in types do not
+    /// support, call the operator method of the left operand's type.
+    fn apply_operator(
+        &mut self,
+        ctx: &Ctx,
         op: &Token,
-        right: &Box<Expr>,
+        left: UvlValue,
+        right: Option<UvlValue>,
     ) -> UvlResult {
-        let left_val = self.eval_expr(left);
-        let right_val = self.eval_expr(right);
+        let method = UvlValue::operator_method(&op.ttype, right.is_some())
+            .and_then(|(_, name)| self.method(&left, name));
+        let function = match method {
+            Some(function) if !left.is_primitive() => function,
+            _ => match left.apply_operator(ctx, &op.ttype, right.as_ref()) {
+                Err(e) if e.kind() == ErrorKind::UnsupportedOperator => match method {
+                    Some(function) => function,
+                    None => return Err(e),
+                },
+                result => return result,
+            },
+        };
 
-        if left_val.is_err() {
-            return left_val;
+        let mut args = vec![left];
+        args.extend(right);
+        self.call_function(ctx, &function, args)
+    }
+
+    /// The method `name` of the receiver's type, if an impl provides one.
+    fn method(&self, receiver: &UvlValue, name: &str) -> Option<Rc<UvlFunction>> {
+        self.impls
+            .get(receiver.type_str())
+            .and_then(|methods| methods.get(name))
+            .map(|method| method.function.clone())
+    }
+}
+
+// The embedding API, for host programs
+impl UvlInterpreter {
+    /// Makes the Rust closure `function` callable from uvl as the global
+    /// `name`. It receives the arguments as they are, an `Err` is raised as
+    /// a RuntimeError at the call site.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[UvlValue]) -> Result<UvlValue, String> + 'static,
+    ) {
+        self.natives
+            .define(name, native::function(name, None, function));
+    }
+
+    /// Declares the immutable global `name` of the program, replacing any
+    /// global of that name.
+    pub fn set_global(&mut self, name: &str, value: impl Into<UvlValue>) {
+        let entry = Entry {
+            is_mutable: false,
+            value: value.into(),
+        };
+        self.modules[0].globals.insert(name.to_string(), entry);
+    }
+
+    /// The value of the global `name` of the program.
+    pub fn get_global(&self, name: &str) -> Option<UvlValue> {
+        self.modules[0]
+            .globals
+            .get(name)
+            .map(|entry| entry.value.clone())
+            .or_else(|| self.natives.get(name).cloned())
+    }
+
+    /// Calls the function declared as the global `name` of the program.
+    pub fn call(&mut self, name: &str, args: Vec<UvlValue>) -> UvlResult {
+        let ctx = UvlInterpreter::host_ctx();
+        match self.get_global(name) {
+            Some(callee) => self.call_value(&ctx, callee, args),
+            None => Err(UvlInterpreter::undefined(&ctx, name, Binding::Global)),
         }
+    }
 
-        if right_val.is_err() {
-            return right_val;
+    /// Calls a function value, such as one a uvl function returned.
+    pub fn call_fn(&mut self, function: &UvlValue, args: Vec<UvlValue>) -> UvlResult {
+        self.call_value(&UvlInterpreter::host_ctx(), function.clone(), args)
+    }
+
+    /// Where calls from the host come from in diagnostics.
+    fn host_ctx() -> Ctx {
+        Ctx {
+            file: "<host>".to_string(),
+            module: "root".to_string(),
+            ..Ctx::default()
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(interp: &mut UvlInterpreter, source: &str) -> UvlValue {
+        interp.run("main.uvl", source).unwrap()
+    }
+
+    #[test]
+    fn functions_return_values() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(
+            &mut interp,
+            "fun fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }",
+        );
+
+        assert_eq!(eval(&mut interp, "fib(10)"), UvlValue::Number(55.0));
+    }
+
+    #[test]
+    fn sources_live_as_long_as_their_functions() {
+        let mut interp = UvlInterpreter::new(true);
+        interp.run("lib.uvl", "fun one() { return 1; }").unwrap();
+        interp.run("stdin", "one() + 1").unwrap();
+
+        // Held by `sources` and the clone here, and by `one` for its body
+        let lib = Rc::clone(&interp.sources["lib.uvl"]);
+        assert_eq!(Rc::strong_count(&lib), 3);
+        let stdin = Rc::clone(&interp.sources["stdin"]);
+        assert_eq!(Rc::strong_count(&stdin), 2);
+        interp.run("stdin", "2").unwrap();
+        assert_eq!(Rc::strong_count(&stdin), 1);
+    }
+
+    #[test]
+    fn functions_close_over_their_environment() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(
+            &mut interp,
+            "fun counter() { let mut c = 0; fun inc() { c = c + 1; return c; } return inc; }",
+        );
+        eval(&mut interp, "let inc = counter();");
+        eval(&mut interp, "inc()");
+
+        assert_eq!(eval(&mut interp, "inc()"), UvlValue::Number(2.0));
+        assert_eq!(eval(&mut interp, "!(inc() != 3)"), UvlValue::Bool(true));
+    }
+
+    #[test]
+    fn native_globals_can_be_shadowed() {
+        let mut interp = UvlInterpreter::new(true);
+
+        assert_eq!(
+            eval(&mut interp, "math.max(2, math.sqrt(16))"),
+            UvlValue::Number(4.0)
+        );
+        let errors = interp.run("main.uvl", "math.pow(2)").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Function 'pow' expects 2 arguments, got 1"
+        );
+        eval(&mut interp, "let math = 1;");
+        assert_eq!(eval(&mut interp, "math + 1"), UvlValue::Number(2.0));
+    }
+
+    #[test]
+    fn hosts_register_functions_and_call_back() {
+        let mut interp = UvlInterpreter::new(false);
+        interp.register_fn("twice", |args| {
+            let n = f64::try_from(args[0].clone())?;
+            Ok(UvlValue::from(n * 2.0))
+        });
+        interp.set_global("greeting", "hi");
+        interp
+            .run(
+                "main.uvl",
+                "let doubled = twice(21);\nfun shout(s) { return s.upper() + \"!\"; }",
+            )
+            .unwrap();
+
+        assert_eq!(interp.get_global("doubled"), Some(UvlValue::Number(42.0)));
+        let greeting = interp.get_global("greeting").unwrap();
+        let shouted = interp.call("shout", vec![greeting]).unwrap();
+        assert_eq!(String::try_from(shouted), Ok("HI!".to_string()));
+        let errors = interp.run("main.uvl", "twice(\"x\");").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Expected Number, found \"x\" of type String"
+        );
+        let twice = interp.get_global("twice").unwrap();
+        assert_eq!(
+            interp.call_fn(&twice, vec![UvlValue::from(2)]).unwrap(),
+            UvlValue::Number(4.0)
+        );
+        assert_eq!(
+            Vec::<Option<bool>>::try_from(UvlValue::from(vec![Some(true), None])),
+            Ok(vec![Some(true), None])
+        );
+    }
+
+    #[test]
+    fn system_modules_must_be_allowed() {
+        let mut interp = UvlInterpreter::new(true);
+        interp.set_args(vec!["in.txt".to_string()]);
+
+        let errors = interp.run("main.uvl", "env.args()").unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::Runtime);
+        assert_eq!(
+            errors[0].message(),
+            "The env module is disabled, the host has not allowed system access"
+        );
+
+        interp.allow_system(true);
+        assert_eq!(
+            eval(&mut interp, "env.args()"),
+            UvlValue::from(vec!["in.txt"])
+        );
+        let errors = interp.run("main.uvl", "process.exit(300)").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Exit code must be a whole number from 0 to 255, found 300"
+        );
+    }
+
+    #[test]
+    fn constant_errors_are_reported_once() {
+        for strict in [false, true] {
+            let mut interp = UvlInterpreter::new(true);
+            interp.set_strict_types(strict);
+            let errors = interp.run("main.uvl", "const S = \"a\" + 1;").unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0]
+                .message()
+                .starts_with("Operator '+' is not supported"));
+        }
+    }
+
+    #[test]
+    fn deep_evaluation_raises_recursion_error() {
+        let mut interp = UvlInterpreter::new(true);
+        interp.set_max_depth(30);
+        eval(
+            &mut interp,
+            "fun down(n) { if n == 0 { return 0; } return down(n - 1); }",
+        );
+
+        // The nesting of every call in progress adds up
+        let errors = interp.run("main.uvl", "down(20)").unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::Recursion);
+        assert_eq!(
+            errors[0].message(),
+            "Too many nested statements or expressions"
+        );
+        assert_eq!(eval(&mut interp, "down(2)"), UvlValue::Number(0.0));
+    }
+
+    #[test]
+    fn deep_recursion_raises_recursion_error() {
+        let mut interp = UvlInterpreter::new(true);
+        interp.set_recursion_limit(50);
+        eval(
+            &mut interp,
+            "fun down(n) { if n == 0 { return 0; } return down(n - 1); }",
+        );
+
+        let errors = interp.run("main.uvl", "down(100)").unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::Recursion);
+        assert_eq!(errors[0].traceback().len(), 51);
+
+        // The interpreter is still usable afterwards
+        assert_eq!(eval(&mut interp, "down(40)"), UvlValue::Number(0.0));
+    }
+
+    #[test]
+    fn annotations_are_checked_at_runtime_boundaries() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(&mut interp, "fun id(x) { return x; }");
+        eval(
+            &mut interp,
+            "fun inc(n: Number) -> Number { return n + 1; }",
+        );
+        eval(&mut interp, "fun bad() -> Number { return id(\"one\"); }");
+
+        assert_eq!(eval(&mut interp, "inc(id(1))"), UvlValue::Number(2.0));
+        let errors = interp.run("main.uvl", "inc(id(\"1\"))").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Parameter 'n' of 'inc' expects Number, found \"1\" of type String"
+        );
+        let errors = interp.run("main.uvl", "bad()").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Function 'bad' must return Number, returned \"one\" of type String"
+        );
+        let errors = interp.run("main.uvl", "let b: Bool = id(1);").unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::Runtime);
+
+        // Types of earlier lines are remembered
+        let errors = interp.run("main.uvl", "inc(\"1\")").unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::Type);
+    }
+
+    #[test]
+    fn methods_dispatch_through_impls() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(
+            &mut interp,
+            "trait Describe { fun name(self); fun describe(self, prefix) { return prefix + self.name(); } }",
+        );
+        eval(
+            &mut interp,
+            "impl Describe for Number { fun name(self) { return \"number\"; } }",
+        );
+        eval(
+            &mut interp,
+            "impl Describe for String { fun name(self) { return self; } fun describe(self, prefix) { return \"!\"; } }",
+        );
+
+        assert_eq!(
+            eval(&mut interp, "1.name()"),
+            UvlValue::String("number".to_string())
+        );
+        assert_eq!(
+            eval(&mut interp, "2.describe(\"a \")"),
+            UvlValue::String("a number".to_string())
+        );
+        assert_eq!(
+            eval(&mut interp, "\"s\".describe(\"a \")"),
+            UvlValue::String("!".to_string())
+        );
+
+        let errors = interp.run("main.uvl", "true.name()").unwrap_err();
+        assert_eq!(errors[0].message(), "Bool has no method 'name'");
+        assert_eq!(
+            errors[0].diagnostic().help,
+            vec!["trait 'Describe' declares 'name', consider `impl Describe for Bool`"]
+        );
+    }
+
+    #[test]
+    fn impls_must_provide_required_methods() {
+        let mut interp = UvlInterpreter::new(false);
+        let errors = interp
+            .run(
+                "main.uvl",
+                "trait Display { fun fmt(self); }\nimpl Display for Nil { fun show(self) {} }",
+            )
+            .unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Method 'show' is not a member of trait 'Display'"
+        );
+
+        let errors = interp
+            .run(
+                "main.uvl",
+                "trait Display { fun fmt(self); }\nimpl Display for Nil {}",
+            )
+            .unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Missing method 'fmt' in impl of 'Display' for Nil"
+        );
+        assert_eq!(errors[0].notes(), ["'fmt' is declared at main.uvl:1:17"]);
+
+        let errors = interp
+            .run("main.uvl", "impl Display for Point {}")
+            .unwrap_err();
+        assert_eq!(errors[0].message(), "Unknown type 'Point'");
+    }
+
+    #[test]
+    fn structs_build_instances() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(&mut interp, "struct Point { x: Number, y }");
+        eval(&mut interp, "trait Norm { fun norm(self); }");
+        eval(
+            &mut interp,
+            "impl Norm for Point { fun norm(self) { return self.x * self.x + self.y * self.y; } }",
+        );
+        eval(&mut interp, "let p = Point(3, 4);");
+        eval(&mut interp, "fun member(value) { return value.z; }");
+        eval(&mut interp, "fun make(x: Any) { return Point(x, 4); }");
+
+        assert_eq!(eval(&mut interp, "p.y"), UvlValue::Number(4.0));
+        assert_eq!(eval(&mut interp, "p.norm()"), UvlValue::Number(25.0));
+        assert_eq!(eval(&mut interp, "p").to_string(), "Point { x: 3, y: 4 }");
+
+        let errors = interp.run("main.uvl", "member(p)").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Point { x: 3, y: 4 } of type Point has no member 'z'"
+        );
+        let errors = interp.run("main.uvl", "make(\"3\")").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Field 'x' of 'Point' expects Number, found \"3\" of type String"
+        );
+    }
+
+    #[test]
+    fn impl_methods_shadow_native_methods() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(&mut interp, "trait Sized { fun len(self); }");
+        eval(
+            &mut interp,
+            "impl Sized for String { fun len(self) { return 42; } }",
+        );
+
+        assert_eq!(eval(&mut interp, "\"abc\".len()"), UvlValue::Number(42.0));
+        assert_eq!(
+            eval(&mut interp, "\"abc\".upper()"),
+            UvlValue::String("ABC".to_string())
+        );
+    }
+
+    #[test]
+    fn operators_dispatch_to_methods() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(
+            &mut interp,
+            "impl Mul for String { fun mul(self, n) { if n < 1 { return \"\"; } return self + self * (n - 1); } }",
+        );
+        eval(
+            &mut interp,
+            "impl Eq for Function { fun eq(self, other) { return true; } }",
+        );
+        eval(
+            &mut interp,
+            "impl Index for Function { fun index(self, i) { return self(i); } }",
+        );
+        eval(&mut interp, "fun double(n) { return n * 2; }");
+
+        assert_eq!(
+            eval(&mut interp, "\"ab\" * 3"),
+            UvlValue::String("ababab".to_string())
+        );
+        assert_eq!(eval(&mut interp, "double[21]"), UvlValue::Number(42.0));
+        assert_eq!(eval(&mut interp, "double != 1"), UvlValue::Bool(false));
+        assert_eq!(eval(&mut interp, "1 + 2"), UvlValue::Number(3.0));
+
+        let errors = interp.run("main.uvl", "true[0]").unwrap_err();
+        assert_eq!(errors[0].message(), "Bool cannot be indexed");
+        let errors = interp.run("main.uvl", "double - 1").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Operator '-' is not supported for fun(Any) -> Any and Number"
+        );
+        let errors = interp
+            .run(
+                "main.uvl",
+                "impl Eq for String { fun eq(self, other) { return true; } }",
+            )
+            .unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Trait 'Eq' cannot be implemented for String"
+        );
+    }
+
+    #[test]
+    fn operators_dispatch_to_struct_methods() {
+        let mut interp = UvlInterpreter::new(true);
+        eval(&mut interp, "struct Vector { x, y }");
+        eval(
+            &mut interp,
+            "impl Add for Vector { fun add(self, other) { return Vector(self.x + other.x, self.y + other.y); } }",
+        );
+        eval(
+            &mut interp,
+            "impl Eq for Vector { fun eq(self, other) { return self.x == other.x; } }",
+        );
+        eval(
+            &mut interp,
+            "impl Ord for Vector { fun lt(self, other) { return self.x < other.x; } }",
+        );
+        eval(&mut interp, "let a = Vector(1, 2);");
+
+        assert_eq!(
+            eval(&mut interp, "a + Vector(3, 4)").to_string(),
+            "Vector { x: 4, y: 6 }"
+        );
+        assert_eq!(eval(&mut interp, "a == Vector(1, 5)"), UvlValue::Bool(true));
+        assert_eq!(
+            eval(&mut interp, "a != Vector(1, 5)"),
+            UvlValue::Bool(false)
+        );
+        assert_eq!(eval(&mut interp, "a <= Vector(1, 5)"), UvlValue::Bool(true));
+        assert_eq!(eval(&mut interp, "a > Vector(0, 0)"), UvlValue::Bool(true));
+
+        eval(&mut interp, "struct Tag { name }");
+        assert_eq!(
+            eval(&mut interp, "Tag(\"a\") == Tag(\"a\")"),
+            UvlValue::Bool(true)
+        );
+        let errors = interp.run("main.uvl", "a - a").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "Operator '-' is not supported for Vector { x: 1, y: 2 } of type Vector and Vector { x: 1, y: 2 } of type Vector"
+        );
+    }
+
+    #[test]
+    fn modules_are_loaded_once() {
+        let dir = std::env::temp_dir().join(format!("uvl-modules-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        let write = |name: &str, source: &str| std::fs::write(dir.join(name), source).unwrap();
+        write(
+            "lib/counter.uvl",
+            "pub let mut count = 0;\npub fun bump() { count = count + 1; return count; }\nlet hidden = 1;",
+        );
+        write("lib/a.uvl", "import \"counter.uvl\" as c;\nc.bump();");
+        write(
+            "main.uvl",
+            "import \"lib/counter.uvl\" as counter;\nimport \"lib/a.uvl\" as a;\nuse counter::{bump};",
+        );
+        write("cycle.uvl", "import \"main2.uvl\" as m;");
+        write("main2.uvl", "import \"cycle.uvl\" as c;");
+
+        let mut interp = UvlInterpreter::new(false);
+        let main = dir.join("main.uvl");
+        let source = std::fs::read_to_string(&main).unwrap();
+        interp.run(main.to_str().unwrap(), &source).unwrap();
+        interp.prompt_mode = true;
+        assert_eq!(eval(&mut interp, "counter.count"), UvlValue::Number(1.0));
+        assert_eq!(eval(&mut interp, "bump()"), UvlValue::Number(2.0));
+        assert_eq!(eval(&mut interp, "counter.count"), UvlValue::Number(2.0));
+
+        let errors = interp.run("main.uvl", "counter.hidden").unwrap_err();
+        assert_eq!(
+            errors[0].message(),
+            "'hidden' is private to module 'counter'"
+        );
+
+        let cycle = dir.join("cycle.uvl");
+        let errors = UvlInterpreter::new(false)
+            .run(cycle.to_str().unwrap(), "import \"main2.uvl\" as m;")
+            .unwrap_err();
+        assert!(errors[0].message().starts_with("Import cycle: "));
+        assert!(errors[0].message().ends_with("cycle.uvl"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn module_members_are_checked_before_imports_run() {
+        let dir = std::env::temp_dir().join(format!("uvl-members-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("util.uvl"),
+            "pub fun f() { return 1; }\nfun _hidden() { }\nlet _ran = 1 / 0;",
+        )
+        .unwrap();
+
+        let main = dir.join("main.uvl");
+        let source = "import \"util.uvl\" as u;\nuse u::{f, nothere};\nu._hidden();";
+        let errors = UvlInterpreter::new(false)
+            .run(main.to_str().unwrap(), source)
+            .unwrap_err();
+        let messages = errors.iter().map(|e| e.message()).collect::<Vec<&str>>();
+        assert_eq!(
+            messages,
+            vec![
+                "Module 'u' has no member 'nothere'",
+                "'_hidden' is private to module 'u'",
+            ]
+        );
 
-        left_val
-            .unwrap()
-            .apply_operator(ctx, &op.ttype, Some(&right_val.unwrap()))
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
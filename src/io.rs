@@ -0,0 +1,80 @@
+use std::io::{BufRead, Read, Write};
+
+use crate::native::function;
+use crate::value::UvlValue;
+
+/// The members of the `io` module. Printing writes Strings as they are,
+/// without the quotes the `println` statement shows.
+pub fn members() -> Vec<(&'static str, UvlValue)> {
+    vec![
+        // nil at the end of the input
+        (
+            "read_line",
+            function("read_line", Some(0), |_| {
+                read_line(&mut std::io::stdin().lock())
+            }),
+        ),
+        (
+            "read_all",
+            function("read_all", Some(0), |_| {
+                let mut text = String::new();
+                match std::io::stdin().lock().read_to_string(&mut text) {
+                    Ok(_) => Ok(UvlValue::String(text)),
+                    Err(e) => Err(format!("Cannot read from stdin: {}", e)),
+                }
+            }),
+        ),
+        ("print", print("print", std::io::stdout, "")),
+        ("print_line", print("print_line", std::io::stdout, "\n")),
+        ("eprint", print("eprint", std::io::stderr, "")),
+        ("eprint_line", print("eprint_line", std::io::stderr, "\n")),
+    ]
+}
+
+/// The next line of `input` without its line ending, nil at the end.
+fn read_line(input: &mut impl BufRead) -> Result<UvlValue, String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) => Ok(UvlValue::Nil(())),
+        Ok(_) => {
+            let end = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(end);
+            Ok(UvlValue::String(line))
+        }
+        Err(e) => Err(format!("Cannot read from stdin: {}", e)),
+    }
+}
+
+fn print<W: Write + 'static>(name: &'static str, output: fn() -> W, end: &'static str) -> UvlValue {
+    function(name, Some(1), move |args| {
+        let text = match &args[0] {
+            UvlValue::String(s) => s.clone(),
+            value => value.to_string(),
+        };
+        write!(output(), "{}{}", text, end)
+            .map(|_| UvlValue::Nil(()))
+            .map_err(|e| format!("Cannot write: {}", e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lines_without_their_endings() {
+        let string = |s: &str| Ok(UvlValue::String(s.to_string()));
+        let mut input = "one\r\ntwo\n\nlast".as_bytes();
+        assert_eq!(read_line(&mut input), string("one"));
+        assert_eq!(read_line(&mut input), string("two"));
+        assert_eq!(read_line(&mut input), string(""));
+        assert_eq!(read_line(&mut input), string("last"));
+        assert_eq!(read_line(&mut input), Ok(UvlValue::Nil(())));
+
+        let mut invalid = &[0xff, b'\n'][..];
+        assert_eq!(
+            read_line(&mut invalid),
+            Err("Cannot read from stdin: stream did not contain valid UTF-8".to_string())
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+
+use crate::native::{boolean, function, string};
+use crate::value::UvlValue;
+
+/// Nesting deeper than this is rejected instead of overflowing the stack.
+const MAX_DEPTH: usize = 512;
+
+/// The members of the `json` module.
+pub fn members() -> Vec<(&'static str, UvlValue)> {
+    vec![
+        // Malformed input is an Err Result saying where it is malformed
+        (
+            "parse",
+            function("parse", Some(1), |args| {
+                let result = parse(string("parse", args, 0)?).map_err(UvlValue::String);
+                Ok(UvlValue::Result(Rc::new(result)))
+            }),
+        ),
+        (
+            "stringify",
+            function("stringify", Some(2), |args| {
+                let pretty = boolean("stringify", args, 1)?;
+                let mut out = String::new();
+                write_value(&args[0], pretty, 0, &mut out)?;
+                Ok(UvlValue::String(out))
+            }),
+        ),
+    ]
+}
+
+/// Parses a JSON document. Objects become Maps, arrays Lists and null nil.
+pub fn parse(text: &str) -> Result<UvlValue, String> {
+    let mut parser = JsonParser {
+        chars: text.chars().peekable(),
+        line: 1,
+        column: 1,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek().copied() {
+        Some(c) => Err(parser.error(format!("Unexpected '{}' after the value", c))),
+        None => Ok(value),
+    }
+}
+
+struct JsonParser<'t> {
+    chars: Peekable<Chars<'t>>,
+    /// Where the next character is, 1-based, counting characters.
+    line: usize,
+    column: usize,
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn value(&mut self) -> Result<UvlValue, String> {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some('{') => self.nested(JsonParser::object),
+            Some('[') => self.nested(JsonParser::array),
+            Some('"') => self.string().map(UvlValue::String),
+            Some('t') => self.literal("true", UvlValue::Bool(true)),
+            Some('f') => self.literal("false", UvlValue::Bool(false)),
+            Some('n') => self.literal("null", UvlValue::Nil(())),
+            Some('-' | '0'..='9') => self.number(),
+            Some(c) => Err(self.error(format!("Unexpected '{}'", c))),
+            None => Err(self.error("Unexpected end of input".to_string())),
+        }
+    }
+
+    fn nested(
+        &mut self,
+        parse: fn(&mut Self) -> Result<UvlValue, String>,
+    ) -> Result<UvlValue, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(format!("Nesting is deeper than {} levels", MAX_DEPTH)));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<UvlValue, String> {
+        self.advance();
+        let mut entries = BTreeMap::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.advance();
+            return Ok(UvlValue::Map(Rc::new(entries)));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(self.unexpected("a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&':') {
+                return Err(self.unexpected("':' after the key"));
+            }
+            self.advance();
+            entries.insert(key, self.value()?);
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some(',') => self.advance(),
+                Some('}') => break,
+                _ => return Err(self.unexpected("',' or '}' in the object")),
+            }
+        }
+        self.advance();
+        Ok(UvlValue::Map(Rc::new(entries)))
+    }
+
+    fn array(&mut self) -> Result<UvlValue, String> {
+        self.advance();
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.advance();
+            return Ok(UvlValue::List(Rc::new(items)));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some(',') => self.advance(),
+                Some(']') => break,
+                _ => return Err(self.unexpected("',' or ']' in the array")),
+            }
+        }
+        self.advance();
+        Ok(UvlValue::List(Rc::new(items)))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.advance();
+        let mut s = String::new();
+        loop {
+            let (line, column) = (self.line, self.column);
+            match self.chars.peek().copied() {
+                None => return Err(self.error("Unterminated string".to_string())),
+                Some('"') => break,
+                Some('\\') => {
+                    self.advance();
+                    s.push(self.escape().map_err(|e| error_at(line, column, e))?);
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(self.error(format!(
+                        "Control character U+{:04X} must be escaped in a string",
+                        c as u32
+                    )))
+                }
+                Some(c) => {
+                    self.advance();
+                    s.push(c);
+                }
+            }
+        }
+        self.advance();
+        Ok(s)
+    }
+
+    /// The character an escape stands for, after its backslash.
+    fn escape(&mut self) -> Result<char, String> {
+        let c = self.chars.peek().copied();
+        self.advance();
+        match c {
+            Some('"') => Ok('"'),
+            Some('\\') => Ok('\\'),
+            Some('/') => Ok('/'),
+            Some('b') => Ok('\u{8}'),
+            Some('f') => Ok('\u{c}'),
+            Some('n') => Ok('\n'),
+            Some('r') => Ok('\r'),
+            Some('t') => Ok('\t'),
+            Some('u') => {
+                let high = self.hex()?;
+                if !(0xD800..0xDC00).contains(&high) {
+                    return char::from_u32(high)
+                        .ok_or("Invalid escape, a lone low surrogate".to_string());
+                }
+                // Characters outside the Basic Multilingual Plane are pairs
+                if self.chars.next_if_eq(&'\\').is_none() || self.chars.next_if_eq(&'u').is_none() {
+                    return Err(
+                        "Invalid escape, a high surrogate must be followed by a low one"
+                            .to_string(),
+                    );
+                }
+                self.column += 2;
+                let low = self.hex()?;
+                if !(0xDC00..0xE000).contains(&low) {
+                    return Err(
+                        "Invalid escape, a high surrogate must be followed by a low one"
+                            .to_string(),
+                    );
+                }
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                Ok(char::from_u32(code).expect("surrogate pairs are valid characters"))
+            }
+            Some(c) => Err(format!("Invalid escape '\\{}'", c)),
+            None => Err("Unterminated string".to_string()),
+        }
+    }
+
+    /// The four hex digits of a `\u` escape.
+    fn hex(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.chars.peek().and_then(|c| c.to_digit(16));
+            match digit {
+                Some(digit) => {
+                    self.advance();
+                    code = code * 16 + digit;
+                }
+                None => return Err("Invalid escape, '\\u' takes four hex digits".to_string()),
+            }
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<UvlValue, String> {
+        let (line, column) = (self.line, self.column);
+        let mut text = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            self.column += 1;
+            text.push(c);
+        }
+        if !valid_number(&text) {
+            return Err(error_at(line, column, format!("Invalid number '{}'", text)));
+        }
+        match text.parse::<f64>().expect("valid JSON numbers parse") {
+            n if n.is_finite() => Ok(UvlValue::Number(n)),
+            _ => Err(error_at(
+                line,
+                column,
+                format!("Number '{}' is out of range", text),
+            )),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: UvlValue) -> Result<UvlValue, String> {
+        let (line, column) = (self.line, self.column);
+        for expected in word.chars() {
+            if self.chars.peek() != Some(&expected) {
+                return Err(error_at(
+                    line,
+                    column,
+                    format!("Invalid literal, expected '{}'", word),
+                ));
+            }
+            self.advance();
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.advance();
+        }
+    }
+
+    fn advance(&mut self) {
+        match self.chars.next() {
+            Some('\n') => {
+                self.line += 1;
+                self.column = 1;
+            }
+            Some(_) => self.column += 1,
+            None => (),
+        }
+    }
+
+    /// "Expected `what`" at the next character.
+    fn unexpected(&mut self, what: &str) -> String {
+        let found = match self.chars.peek() {
+            Some(c) => format!("'{}'", c),
+            None => "the end of input".to_string(),
+        };
+        self.error(format!("Expected {}, found {}", what, found))
+    }
+
+    fn error(&self, message: String) -> String {
+        error_at(self.line, self.column, message)
+    }
+}
+
+fn error_at(line: usize, column: usize, message: String) -> String {
+    format!("{} at {}:{}", message, line, column)
+}
+
+/// Whether `text` follows the JSON number grammar, which is stricter than
+/// Rust's: no leading zeros, `+` or bare `.`.
+fn valid_number(text: &str) -> bool {
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = text.strip_prefix('-').unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.find(['e', 'E']) {
+        Some(at) => (&unsigned[..at], Some(&unsigned[at + 1..])),
+        None => (unsigned, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let exponent = exponent.map(|e| e.strip_prefix(['+', '-']).unwrap_or(e));
+    digits(integer)
+        && (integer == "0" || !integer.starts_with('0'))
+        && fraction.is_none_or(digits)
+        && exponent.is_none_or(digits)
+}
+
+/// Writes `value`, nested in `depth` Lists and Maps, as JSON. Lists and
+/// Maps are immutable, so none can contain itself.
+fn write_value(
+    value: &UvlValue,
+    pretty: bool,
+    depth: usize,
+    out: &mut String,
+) -> Result<(), String> {
+    match value {
+        UvlValue::Nil(_) => out.push_str("null"),
+        UvlValue::Bool(b) => out.push_str(&b.to_string()),
+        UvlValue::Number(n) if n.is_finite() => out.push_str(&n.to_string()),
+        UvlValue::String(s) => write_string(s, out),
+        UvlValue::List(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                separate(out, pretty, depth + 1, index);
+                write_value(item, pretty, depth + 1, out)?;
+            }
+            close(out, pretty, depth, items.is_empty(), ']');
+        }
+        UvlValue::Map(entries) => {
+            out.push('{');
+            for (index, (key, value)) in entries.iter().enumerate() {
+                separate(out, pretty, depth + 1, index);
+                write_string(key, out);
+                out.push_str(if pretty { ": " } else { ":" });
+                write_value(value, pretty, depth + 1, out)?;
+            }
+            close(out, pretty, depth, entries.is_empty(), '}');
+        }
+        value => {
+            return Err(format!(
+                "{} of type {} cannot be represented in JSON",
+                value,
+                value.type_str()
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Starts the item at `index` of an array or object, on its own line
+/// indented to `depth` when `pretty`.
+fn separate(out: &mut String, pretty: bool, depth: usize, index: usize) {
+    if index > 0 {
+        out.push(',');
+    }
+    if pretty {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+}
+
+/// Ends an array or object whose closing bracket is indented to `depth`.
+fn close(out: &mut String, pretty: bool, depth: usize, empty: bool, bracket: char) {
+    if pretty && !empty {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+    out.push(bracket);
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stringify(value: &UvlValue, pretty: bool) -> Result<String, String> {
+        let mut out = String::new();
+        write_value(value, pretty, 0, &mut out).map(|_| out)
+    }
+
+    #[test]
+    fn round_trips_documents() {
+        let text = r#" {"name": "uvl", "tags": ["a\n", "é😀"], "n": -1.5e2,
+                        "ok": true, "none": null, "empty": {}} "#;
+        let value = parse(text).unwrap();
+
+        assert_eq!(
+            stringify(&value, false).unwrap(),
+            r#"{"empty":{},"n":-150,"name":"uvl","none":null,"ok":true,"tags":["a\n","é😀"]}"#
+        );
+        assert_eq!(
+            stringify(&parse("[1, {\"a\": []}]").unwrap(), true).unwrap(),
+            "[\n  1,\n  {\n    \"a\": []\n  }\n]"
+        );
+        assert_eq!(
+            stringify(&UvlValue::Number(f64::NAN), false),
+            Err("NaN of type Number cannot be represented in JSON".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_input_is_located() {
+        let error = |text: &str| parse(text).unwrap_err();
+
+        assert_eq!(
+            error("{\n  \"a\": 1,\n  \"b\" 2\n}"),
+            "Expected ':' after the key, found '2' at 3:7"
+        );
+        assert_eq!(error("[1, 2,]"), "Unexpected ']' at 1:7");
+        assert_eq!(error("[01]"), "Invalid number '01' at 1:2");
+        assert_eq!(error("\"tab\\x\""), "Invalid escape '\\x' at 1:5");
+        assert_eq!(error("tru"), "Invalid literal, expected 'true' at 1:1");
+        assert_eq!(error("{} x"), "Unexpected 'x' after the value at 1:4");
+        assert_eq!(error("[\"open"), "Unterminated string at 1:7");
+        assert_eq!(
+            error("[1, -1e400]"),
+            "Number '-1e400' is out of range at 1:5"
+        );
+        assert_eq!(
+            error(&"[".repeat(MAX_DEPTH + 1)),
+            format!("Nesting is deeper than 512 levels at 1:{}", MAX_DEPTH + 1)
+        );
+    }
+}
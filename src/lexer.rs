@@ -1,25 +1,37 @@
 use std::collections::HashMap;
 
-use crate::token::{Token, TokenType};
+use unicode_xid::UnicodeXID;
+
+use crate::ast::Ctx;
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
+use crate::token::{Span, Token, TokenType};
 
 lazy_static! {
     static ref KEYWORDS: HashMap<&'static str, TokenType<'static>> = {
         let mut keywords = HashMap::new();
         keywords.insert("and", TokenType::And);
+        keywords.insert("as", TokenType::As);
         keywords.insert("class", TokenType::Class);
         keywords.insert("else", TokenType::Else);
         keywords.insert("false", TokenType::False);
         keywords.insert("for", TokenType::For);
         keywords.insert("fun", TokenType::Fun);
         keywords.insert("if", TokenType::If);
+        keywords.insert("impl", TokenType::Impl);
+        keywords.insert("import", TokenType::Import);
         keywords.insert("nil", TokenType::Nil);
         keywords.insert("mut", TokenType::Mut);
         keywords.insert("or", TokenType::Or);
         keywords.insert("println", TokenType::PrintLn);
+        keywords.insert("pub", TokenType::Pub);
         keywords.insert("return", TokenType::Return);
+        keywords.insert("struct", TokenType::Struct);
         keywords.insert("super", TokenType::Super);
         keywords.insert("this", TokenType::This);
+        keywords.insert("trait", TokenType::Trait);
         keywords.insert("true", TokenType::True);
+        keywords.insert("use", TokenType::Use);
         keywords.insert("let", TokenType::Let);
         keywords.insert("const", TokenType::Const);
         keywords.insert("while", TokenType::While);
@@ -27,18 +39,84 @@ lazy_static! {
     };
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    UnterminatedComment,
+}
+
+impl std::fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedCharacter(c) => write!(f, "Unexpected character '{}'", c),
+            Self::UnterminatedString => write!(f, "Unterminated string"),
+            Self::UnterminatedComment => write!(f, "Unterminated block comment"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub line: usize,
+    pub column: usize,
+    pub span: Span,
+    /// The source line the error was found on.
+    pub snippet: String,
+}
+
+/// Identifiers follow the Unicode XID rules (UAX #31), with `_` also
+/// allowed as the first character.
+fn is_identifier_start(c: char) -> bool {
+    c == '_' || UnicodeXID::is_xid_start(c)
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    UnicodeXID::is_xid_continue(c)
+}
+
+/// Whether `text` is a number literal as the lexer scans it: digits,
+/// optionally followed by a '.' and more digits.
+pub(crate) fn is_number_literal(text: &str) -> bool {
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    match text.split_once('.') {
+        Some((whole, fraction)) => digits(whole) && digits(fraction),
+        None => digits(text),
+    }
+}
+
+/// 1-based line and column (in chars) of a source position, plus the byte
+/// offset where its line begins.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    line: usize,
+    column: usize,
+    line_start: usize,
+}
+
+/// `start` and `current` are byte offsets into the source and always sit on
+/// a char boundary.
 struct LexerState {
     start: usize,
     current: usize,
-    line: usize,
+    start_position: Position,
+    position: Position,
 }
 
 impl LexerState {
     pub fn new() -> Self {
+        let position = Position {
+            line: 1,
+            column: 1,
+            line_start: 0,
+        };
+
         LexerState {
             start: 0,
             current: 0,
-            line: 0,
+            start_position: position,
+            position,
         }
     }
 }
@@ -46,6 +124,9 @@ impl LexerState {
 pub struct Lexer<'a> {
     source: &'a str,
     tokens: Vec<Token<'a>>,
+    errors: Vec<LexError>,
+    /// `///` lines waiting to be attached to the next declaration keyword.
+    pending_doc: Vec<&'a str>,
     state: LexerState,
 }
 
@@ -54,20 +135,44 @@ impl<'a> Lexer<'a> {
         Lexer {
             source,
             tokens: vec![],
+            errors: vec![],
+            pending_doc: vec![],
             state: LexerState::new(),
         }
     }
 
-    pub fn scan(&mut self) -> &Vec<Token<'a>> {
+    /// Scans the whole source. Lexing continues past a bad character so
+    /// every lexical error in the source is reported at once.
+    pub fn scan(&mut self) -> Result<&Vec<Token<'a>>, Vec<LexError>> {
         while !self.is_at_end() {
             self.state.start = self.state.current;
+            self.state.start_position = self.state.position;
             self.scan_token();
         }
 
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+
         // Set end of line
-        self.tokens
-            .push(Token::new(TokenType::Eof, "", self.state.line));
-        &self.tokens
+        self.state.start = self.state.current;
+        self.state.start_position = self.state.position;
+        self.add_token(TokenType::Eof);
+        Ok(&self.tokens)
+    }
+
+    pub fn make_lex_error(source_name: &str, error: &LexError) -> UvlError {
+        let ctx = Ctx {
+            file: source_name.to_string(),
+            line: error.line,
+            column: error.column,
+            span: error.span,
+            module: "root".to_string(),
+        };
+        UvlError::new(
+            ErrorKind::Lexer(error.kind),
+            Diagnostic::error(&ctx, error.kind.to_string()),
+        )
     }
 
     pub(crate) fn is_at_end(&self) -> bool {
@@ -81,10 +186,27 @@ impl<'a> Lexer<'a> {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
             '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
+            '-' => {
+                let t = if self.match_next('>') {
+                    TokenType::Arrow
+                } else {
+                    TokenType::Minus
+                };
+                self.add_token(t);
+            }
             '+' => self.add_token(TokenType::Plus),
+            ':' => {
+                let t = if self.match_next(':') {
+                    TokenType::ColonColon
+                } else {
+                    TokenType::Colon
+                };
+                self.add_token(t);
+            }
             ';' => self.add_token(TokenType::Semicolon),
             '*' => self.add_token(TokenType::Star),
             '!' => {
@@ -125,86 +247,145 @@ impl<'a> Lexer<'a> {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
+                    self.add_doc_comment();
+                } else if self.match_next('*') {
+                    self.scan_block_comment();
                 } else {
                     self.add_token(TokenType::Slash);
                 }
             }
-            c if c.is_digit(10) => self.scan_number(),
-            c if c.is_alphabetic() => self.scan_identifier(),
+            c if c.is_ascii_digit() => self.scan_number(),
+            c if is_identifier_start(c) => self.scan_identifier(),
             '"' => self.scan_string(),
             // Increment new line state
-            '\n' => self.state.line += 1,
+            '\n' => self.new_line(),
             // Ignore whitespace
             ' ' | '\r' | '\t' => (),
-            _ => println!("[TODO make this a Err(..)] -- Unexpected character {}", c)
-            // _ => self
-            //     .logger
-            //     .error(self.state.line, &format!("Unexpected character {}", c)),
+            _ => self.error(LexErrorKind::UnexpectedCharacter(c)),
         }
     }
 
+    /// Must be called right after consuming a `'\n'`.
+    pub(crate) fn new_line(&mut self) {
+        self.state.position.line += 1;
+        self.state.position.column = 1;
+        self.state.position.line_start = self.state.current;
+    }
+
+    /// Records an error located at the first char of the current token.
+    pub(crate) fn error(&mut self, kind: LexErrorKind) {
+        let position = self.state.start_position;
+        let first_char = self.source[self.state.start..].chars().next();
+        let end = self.state.start + first_char.map_or(0, char::len_utf8);
+        let line = &self.source[position.line_start..];
+        let snippet = line.split('\n').next().unwrap_or_default().to_string();
+        self.errors.push(LexError {
+            kind,
+            line: position.line,
+            column: position.column,
+            span: Span::new(self.state.start, end),
+            snippet,
+        });
+    }
+
+    /// Consumes the next char. `current` is a byte offset into `source`,
+    /// so this is O(1) regardless of where the cursor is.
     pub(crate) fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.state.current).expect(&format!(
-            "Unexpected error in the lexer reading char @ index {}",
-            self.state.current
-        ));
-        self.state.current += 1;
+        let c = self.peek();
+        self.state.current += c.len_utf8();
+        self.state.position.column += 1;
         c
     }
 
     pub(crate) fn add_token(&mut self, ttype: TokenType<'a>) {
         let text = &self.source[self.state.start..self.state.current];
-        self.tokens.push(Token::new(ttype, text, self.state.line));
+        let mut token = Token::new(
+            ttype,
+            text,
+            self.state.start_position.line,
+            self.state.start_position.column,
+            Span::new(self.state.start, self.state.current),
+        );
+
+        // Doc comments only document declarations, anything else drops them
+        match token.ttype {
+            TokenType::Let
+            | TokenType::Const
+            | TokenType::Fun
+            | TokenType::Struct
+            | TokenType::Trait => token.doc = std::mem::take(&mut self.pending_doc),
+            // `pub` leaves them to the declaration it exports
+            TokenType::Pub => {}
+            _ => self.pending_doc.clear(),
+        }
+
+        self.tokens.push(token);
     }
 
-    pub(crate) fn match_next(&mut self, expected_char: char) -> bool {
-        if self.is_at_end() {
-            return false;
+    /// Keeps the text of a `///` comment (but not `////`) so it can be
+    /// attached to the declaration that follows it.
+    pub(crate) fn add_doc_comment(&mut self) {
+        let comment = &self.source[self.state.start..self.state.current];
+        if let Some(text) = comment.strip_prefix("///") {
+            if !text.starts_with('/') {
+                let text = text.strip_prefix(' ').unwrap_or(text);
+                self.pending_doc.push(text.trim_end_matches('\r'));
+            }
         }
+    }
+
+    /// Consumes a `/* ... */` comment. Block comments nest, so every `/*`
+    /// inside needs its own `*/`.
+    pub(crate) fn scan_block_comment(&mut self) {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.is_at_end() {
+                self.error(LexErrorKind::UnterminatedComment);
+                return;
+            }
 
-        let next_char = self.source.chars().nth(self.state.current).expect(&format!(
-            "Unexpected error in the lexer reading char @ index {}",
-            self.state.current
-        ));
-        if next_char != expected_char {
+            match self.advance() {
+                '/' if self.match_next('*') => depth += 1,
+                '*' if self.match_next('/') => depth -= 1,
+                '\n' => self.new_line(),
+                _ => (),
+            }
+        }
+    }
+
+    pub(crate) fn match_next(&mut self, expected_char: char) -> bool {
+        if self.is_at_end() || self.peek() != expected_char {
             return false;
         }
 
-        self.state.current += 1;
+        self.state.current += expected_char.len_utf8();
+        self.state.position.column += 1;
         true
     }
 
     pub(crate) fn peek(&self) -> char {
-        if self.is_at_end() {
-            '\0'
-        } else {
-            self.source.chars().nth(self.state.current).expect(&format!(
-                "Unexpected error in the lexer reading char @ index {}",
-                self.state.current
-            ))
-        }
+        self.source[self.state.current..]
+            .chars()
+            .next()
+            .unwrap_or('\0')
     }
 
-    pub(crate) fn peek_next(&mut self) -> char {
-        if self.state.current + 1 >= self.source.len() {
-            '\0'
-        } else {
-            self.source
-                .chars()
-                .nth(self.state.current + 1)
-                .expect(&format!(
-                    "Unexpected error in the lexer reading char @ index {}",
-                    self.state.current + 1
-                ))
-        }
+    pub(crate) fn peek_next(&self) -> char {
+        let mut chars = self.source[self.state.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
     pub(crate) fn scan_string(&mut self) {
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.state.line += 1;
+            if self.advance() == '\n' {
+                self.new_line();
             }
-            self.advance();
+        }
+
+        if self.is_at_end() {
+            self.error(LexErrorKind::UnterminatedString);
+            return;
         }
 
         // Consume the closing "
@@ -216,30 +397,32 @@ impl<'a> Lexer<'a> {
     }
 
     pub(crate) fn scan_number(&mut self) {
-        while self.peek().is_digit(10) {
+        while self.peek().is_ascii_digit() {
             self.advance();
         }
 
         // Check if this is a fraction
-        if self.peek() == '.' && self.peek_next().is_digit(10) {
+        if self.peek() == '.' && self.peek_next().is_ascii_digit() {
             // Consumes the "."
             self.advance();
 
-            while self.peek().is_digit(10) {
+            while self.peek().is_ascii_digit() {
                 self.advance();
             }
         }
 
         let literal = &self.source[self.state.start..self.state.current];
-        let value = literal.parse::<f64>().expect(&format!(
-            "Interpreter internal error: failed to parse {} as a f64",
-            literal
-        ));
+        let value = literal.parse::<f64>().unwrap_or_else(|_| {
+            panic!(
+                "Interpreter internal error: failed to parse {} as a f64",
+                literal
+            )
+        });
         self.add_token(TokenType::Number(value))
     }
 
     pub(crate) fn scan_identifier(&mut self) {
-        while self.peek().is_alphabetic() || self.peek().is_alphanumeric() {
+        while is_identifier_continue(self.peek()) {
             self.advance();
         }
 
@@ -260,19 +443,148 @@ mod tests {
     fn scan_integer() {
         let mut lex = Lexer::new("1");
 
-        let tokens = lex.scan();
+        let tokens = lex.scan().unwrap();
         assert_eq!(tokens.len(), 2);
         assert_eq!(tokens[0].lexeme, "1");
         assert!(matches!(tokens[0].ttype, TokenType::Number(1.0)));
     }
 
     #[test]
-    fn t_a() {
-        assert!(true);
+    fn unterminated_string() {
+        let mut lex = Lexer::new("let a = 1;\nlet s = \"abc");
+
+        let errors = lex.scan().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedString);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(errors[0].column, 9);
+        assert_eq!(errors[0].snippet, "let s = \"abc");
+    }
+
+    #[test]
+    fn collects_every_unexpected_character() {
+        let mut lex = Lexer::new("1 $ 2\n# 3");
+
+        let errors = lex.scan().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].kind, LexErrorKind::UnexpectedCharacter('$'));
+        assert_eq!((errors[0].line, errors[0].column), (1, 3));
+        assert_eq!(errors[1].kind, LexErrorKind::UnexpectedCharacter('#'));
+        assert_eq!((errors[1].line, errors[1].column), (2, 1));
+        assert_eq!(errors[1].snippet, "# 3");
+    }
+
+    #[test]
+    fn string_with_emoji() {
+        let mut lex = Lexer::new("\"héllo 🦀 wörld\" + \"🎉\"");
+
+        let tokens = lex.scan().unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[0].ttype, TokenType::String("héllo 🦀 wörld"));
+        assert_eq!(tokens[1].ttype, TokenType::Plus);
+        assert_eq!(tokens[2].ttype, TokenType::String("🎉"));
+    }
+
+    #[test]
+    fn scan_type_annotations() {
+        let mut lex = Lexer::new("fun f(a: Number) -> Bool 1 - -2");
+
+        let tokens = lex.scan().unwrap();
+        let ttypes = tokens
+            .iter()
+            .map(|t| t.ttype.clone())
+            .collect::<Vec<TokenType>>();
+        assert_eq!(ttypes[4], TokenType::Colon);
+        assert_eq!(ttypes[7], TokenType::Arrow);
+        assert_eq!(ttypes[10], TokenType::Minus);
+        assert_eq!(ttypes[11], TokenType::Minus);
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        let mut lex = Lexer::new("let _naïve = 1; let 変数2 = π;");
+
+        let tokens = lex.scan().unwrap();
+        let identifiers = tokens
+            .iter()
+            .filter(|t| t.ttype == TokenType::Identifier)
+            .map(|t| t.lexeme)
+            .collect::<Vec<&str>>();
+        assert_eq!(identifiers, vec!["_naïve", "変数2", "π"]);
+    }
+
+    #[test]
+    fn error_column_counts_chars() {
+        let mut lex = Lexer::new("\"ü🦀\" 🦀");
+
+        let errors = lex.scan().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::UnexpectedCharacter('🦀'));
+        assert_eq!(errors[0].column, 6);
     }
 
     #[test]
-    fn t_b() {
-        assert!(false);
+    fn nested_block_comments() {
+        let mut lex = Lexer::new("1 /* outer /* inner\n */ still\n comment */ + 2");
+
+        let tokens = lex.scan().unwrap();
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[1].ttype, TokenType::Plus);
+        assert_eq!(tokens[2].line, 3);
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let mut lex = Lexer::new("1 /* /* */\n");
+
+        let errors = lex.scan().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedComment);
+        assert_eq!((errors[0].line, errors[0].column), (1, 3));
+    }
+
+    #[test]
+    fn doc_comments_attach_to_declarations() {
+        let mut lex = Lexer::new(
+            "/// The answer.\n///\n/// Computed slowly.\nlet a = 42;\n/// dropped\n1;\n//// not a doc\nlet b = 1;\n/// Exported.\npub let c = 2;",
+        );
+
+        let tokens = lex.scan().unwrap();
+        let lets = tokens
+            .iter()
+            .filter(|t| t.ttype == TokenType::Let)
+            .collect::<Vec<&Token>>();
+        assert_eq!(lets[0].doc, vec!["The answer.", "", "Computed slowly."]);
+        assert!(lets[1].doc.is_empty());
+        assert_eq!(lets[2].doc, vec!["Exported."]);
+        assert!(tokens
+            .iter()
+            .all(|t| t.ttype == TokenType::Let || t.doc.is_empty()));
+    }
+
+    #[test]
+    fn token_positions() {
+        let mut lex = Lexer::new("let π = 1;\n  π = \"ü\";");
+
+        let tokens = lex.scan().unwrap();
+        let positions = tokens
+            .iter()
+            .map(|t| (t.line, t.column, t.span.start, t.span.end))
+            .collect::<Vec<(usize, usize, usize, usize)>>();
+        assert_eq!(
+            positions,
+            vec![
+                (1, 1, 0, 3),
+                (1, 5, 4, 6),
+                (1, 7, 7, 8),
+                (1, 9, 9, 10),
+                (1, 10, 10, 11),
+                (2, 3, 14, 16),
+                (2, 5, 17, 18),
+                (2, 7, 19, 23),
+                (2, 10, 23, 24),
+                (2, 11, 24, 24),
+            ]
+        );
     }
 }
@@ -0,0 +1,40 @@
+//! The uvl language: a lexer, a parser and a tree-walking interpreter.
+//!
+//! Most programs only need [`UvlInterpreter`], which compiles and runs
+//! sources and embeds Rust functions. [`Lexer`] and [`Parser`] give tools
+//! the tokens and syntax tree of a source.
+
+#[macro_use]
+extern crate lazy_static;
+
+mod ast;
+mod checker;
+mod common;
+mod diagnostic;
+mod env;
+mod envr;
+mod error;
+mod fs;
+mod infer;
+mod interp;
+mod io;
+mod json;
+mod lexer;
+mod math;
+mod methods;
+mod native;
+mod parser;
+mod process;
+mod resolver;
+mod token;
+mod types;
+mod value;
+
+pub use ast::{to_string, Expr, Stmt};
+pub use diagnostic::Diagnostic;
+pub use error::{ErrorKind, UvlError};
+pub use interp::{RunResult, UvlInterpreter};
+pub use lexer::Lexer;
+pub use parser::Parser;
+pub use token::{Token, TokenType};
+pub use value::{UvlResult, UvlValue};
@@ -1,22 +1,50 @@
-#[macro_use]
-extern crate lazy_static;
-
 use std::env;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+use uvl::{ErrorKind, UvlInterpreter, UvlValue};
 
-mod ast;
-mod common;
-mod envr;
-mod interp;
-mod lexer;
-mod parser;
-mod token;
-mod value;
+const USAGE: &str =
+    "Usage: uvl [--deny-warnings] [--strict-types] [--print-types] [--allow-fs[=dir]] [file [args...]]";
 
-use interp::UvlInterpreter;
-use value::UvlError;
+/// Command line flags.
+#[derive(Debug, Default)]
+struct Options {
+    deny_warnings: bool,
+    strict_types: bool,
+    print_types: bool,
+    /// `Some(None)` allows the whole file system.
+    allow_fs: Option<Option<String>>,
+}
+
+/// An interpreter set up from the flags. Imports not found next to the
+/// importing file are looked up in the directories of `UVL_PATH`.
+fn new_interpreter(prompt_mode: bool, options: &Options) -> UvlInterpreter {
+    let mut interp = UvlInterpreter::new(prompt_mode);
+    interp.set_recursion_limit(RECURSION_LIMIT);
+    interp.set_max_depth(MAX_DEPTH);
+    interp.set_deny_warnings(options.deny_warnings);
+    interp.set_strict_types(options.strict_types);
+    interp.allow_system(true);
+    if let Some(root) = &options.allow_fs {
+        if let Err(err) = interp.allow_fs(root.as_deref().map(Path::new)) {
+            println!(
+                "Cannot allow file system access in {}, error: {}",
+                root.as_deref().unwrap_or("."),
+                err
+            );
+            std::process::exit(65);
+        }
+    }
+    if let Some(paths) = env::var_os("UVL_PATH") {
+        for dir in env::split_paths(&paths) {
+            interp.add_search_path(dir);
+        }
+    }
+    interp
+}
 
-fn run_file(file_path: &str) {
+fn run_file(file_path: &str, args: Vec<String>, options: &Options) {
     let source_file = match std::fs::read_to_string(file_path) {
         Ok(source) => source,
         Err(err) => {
@@ -25,34 +53,89 @@ fn run_file(file_path: &str) {
         }
     };
 
-    let mut interp = UvlInterpreter::new(false);
-    if let Err(err) = interp.run(file_path, &source_file) {
-        println!("{}", err);
+    let mut interp = new_interpreter(false, options);
+    interp.set_args(args);
+    // Printing the types only checks the program, it does not run it
+    let result = if options.print_types {
+        interp.check(file_path, &source_file).map(|_| {
+            for line in interp.inferred_types() {
+                println!("{}", line);
+            }
+        })
+    } else {
+        interp.run(file_path, &source_file).map(|_| ())
+    };
+    let color = std::io::stdout().is_terminal();
+    let warnings = interp.take_warnings();
+    // Diagnostics may point into imported modules
+    let source_of = |file: &str| interp.source(file).unwrap_or(&source_file).to_string();
+    for warning in warnings {
+        println!("{}\n", warning.render(&source_of(&warning.ctx.file), color));
+    }
 
-        match err {
-            UvlError::RuntimeError(_) => std::process::exit(70),
-            _ => std::process::exit(65),
+    if let Err(errors) = result {
+        let rendered = errors
+            .iter()
+            .map(|e| e.render(&source_of(&e.ctx().file), color))
+            .collect::<Vec<String>>();
+        println!("{}", rendered.join("\n\n"));
+
+        if errors.len() > 1 {
+            println!("\nerror: aborting due to {} previous errors", errors.len());
+        }
+
+        if errors
+            .iter()
+            .any(|e| matches!(e.kind(), ErrorKind::Runtime | ErrorKind::Recursion))
+        {
+            std::process::exit(70)
+        } else {
+            std::process::exit(65)
         }
     }
 }
 
-fn run_prompt() {
+fn run_prompt(options: &Options) {
     let mut line_buffer = String::new();
     let stdin = std::io::stdin();
 
-    let mut interp = UvlInterpreter::new(true);
+    let mut interp = new_interpreter(true, options);
     loop {
         print!("::> ");
         std::io::stdout().flush().unwrap();
 
         match stdin.read_line(&mut line_buffer) {
+            Ok(0) => {
+                println!();
+                std::process::exit(0);
+            }
             Ok(_) => {
-                match interp.run("stdin", &line_buffer) {
+                let result = interp.run("stdin", &line_buffer);
+                for warning in interp.take_warnings() {
+                    let source = interp.source(&warning.ctx.file).unwrap_or(&line_buffer);
+                    println!(
+                        "{}",
+                        warning.render(source, std::io::stdout().is_terminal())
+                    );
+                }
+
+                if options.print_types && result.is_ok() {
+                    for line in interp.inferred_types() {
+                        println!("{}", line);
+                    }
+                }
+
+                match result {
                     Ok(value) => match value {
-                        value::UvlValue::Nil(_) => (),
+                        UvlValue::Nil(_) => (),
                         _ => println!("{}", value),
                     },
-                    Err(err) => println!("{}", err),
+                    Err(errors) => {
+                        for err in errors {
+                            let source = interp.source(&err.ctx().file).unwrap_or(&line_buffer);
+                            println!("{}", err.render(source, std::io::stdout().is_terminal()));
+                        }
+                    }
                 }
 
                 line_buffer.clear();
@@ -65,14 +148,57 @@ fn run_prompt() {
     }
 }
 
+/// Native stack for the interpreter thread, enough for the limits below even
+/// in debug builds.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+/// Nested function calls allowed, higher than the library default since the
+/// interpreter thread has a big stack.
+const RECURSION_LIMIT: usize = 1000;
+
+/// Nesting of statements and expressions allowed, enough for the recursion
+/// limit above.
+const MAX_DEPTH: usize = 10_000;
+
 fn main() {
-    let args = env::args().collect::<Vec<String>>();
-    if args.len() > 2 {
-        println!("Usage: uvl [file]");
-        std::process::exit(65);
-    } else if args.len() == 2 {
-        run_file(&args[1]);
-    } else {
-        run_prompt();
+    let mut options = Options::default();
+    let mut file = None;
+    let mut args = env::args().skip(1);
+    // Options come before the file, the arguments after it are the script's
+    for arg in args.by_ref() {
+        match arg.as_str() {
+            "--deny-warnings" => options.deny_warnings = true,
+            "--strict-types" => options.strict_types = true,
+            // The types are inferred in strict mode
+            "--print-types" => {
+                options.strict_types = true;
+                options.print_types = true;
+            }
+            "--allow-fs" => options.allow_fs = Some(None),
+            flag if flag.starts_with("--allow-fs=") => {
+                options.allow_fs = Some(Some(flag["--allow-fs=".len()..].to_string()));
+            }
+            flag if flag.starts_with("--") => {
+                println!("Unknown option {}\n{}", flag, USAGE);
+                std::process::exit(65);
+            }
+            _ => {
+                file = Some(arg);
+                break;
+            }
+        }
+    }
+    let script_args = args.collect::<Vec<String>>();
+
+    let interpreter = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || match file {
+            Some(file) => run_file(&file, script_args, &options),
+            None => run_prompt(&options),
+        })
+        .expect("failed to start the interpreter thread");
+    // A panic is an internal error, report it like a runtime error
+    if interpreter.join().is_err() {
+        std::process::exit(70);
     }
 }
@@ -0,0 +1,122 @@
+use crate::native::{function, number};
+use crate::value::UvlValue;
+
+type Unary = fn(f64) -> f64;
+
+/// The members of the `math` module.
+pub fn members() -> Vec<(&'static str, UvlValue)> {
+    let unary: [(&'static str, Unary); 15] = [
+        ("sqrt", f64::sqrt),
+        ("abs", f64::abs),
+        ("floor", f64::floor),
+        ("ceil", f64::ceil),
+        ("round", f64::round),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        ("tan", f64::tan),
+        ("asin", f64::asin),
+        ("acos", f64::acos),
+        ("atan", f64::atan),
+        ("exp", f64::exp),
+        ("log", f64::ln),
+        ("log2", f64::log2),
+        ("log10", f64::log10),
+    ];
+    let mut members = unary
+        .into_iter()
+        .map(|(name, apply)| {
+            let value = function(name, Some(1), move |args| {
+                Ok(UvlValue::Number(apply(number(name, args, 0)?)))
+            });
+            (name, value)
+        })
+        .collect::<Vec<(&str, UvlValue)>>();
+
+    members.extend([
+        (
+            "pow",
+            function("pow", Some(2), |args| {
+                Ok(UvlValue::Number(
+                    number("pow", args, 0)?.powf(number("pow", args, 1)?),
+                ))
+            }),
+        ),
+        (
+            "atan2",
+            function("atan2", Some(2), |args| {
+                Ok(UvlValue::Number(
+                    number("atan2", args, 0)?.atan2(number("atan2", args, 1)?),
+                ))
+            }),
+        ),
+        ("min", extremum("min", f64::min)),
+        ("max", extremum("max", f64::max)),
+        (
+            "is_nan",
+            function("is_nan", Some(1), |args| {
+                Ok(UvlValue::Bool(number("is_nan", args, 0)?.is_nan()))
+            }),
+        ),
+        (
+            "is_finite",
+            function("is_finite", Some(1), |args| {
+                Ok(UvlValue::Bool(number("is_finite", args, 0)?.is_finite()))
+            }),
+        ),
+        ("PI", UvlValue::Number(std::f64::consts::PI)),
+        ("E", UvlValue::Number(std::f64::consts::E)),
+        ("INFINITY", UvlValue::Number(f64::INFINITY)),
+        ("NAN", UvlValue::Number(f64::NAN)),
+    ]);
+    members
+}
+
+/// `min` or `max` of one or more Numbers, NAN if any of them is.
+fn extremum(name: &'static str, pick: fn(f64, f64) -> f64) -> UvlValue {
+    function(name, None, move |args| {
+        if args.is_empty() {
+            return Err(format!("Function '{}' expects at least 1 argument", name));
+        }
+        let mut result = number(name, args, 0)?;
+        for position in 1..args.len() {
+            let n = number(name, args, position)?;
+            result = if result.is_nan() || n.is_nan() {
+                f64::NAN
+            } else {
+                pick(result, n)
+            };
+        }
+        Ok(UvlValue::Number(result))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::call_member;
+
+    fn call(name: &str, args: &[UvlValue]) -> Result<UvlValue, String> {
+        call_member(&members(), name, args)
+    }
+
+    #[test]
+    fn computes_with_numbers() {
+        let n = UvlValue::Number;
+        assert_eq!(call("sqrt", &[n(9.0)]), Ok(n(3.0)));
+        assert_eq!(call("pow", &[n(2.0), n(10.0)]), Ok(n(1024.0)));
+        assert_eq!(call("round", &[n(-2.5)]), Ok(n(-3.0)));
+        assert_eq!(call("max", &[n(1.0), n(7.0), n(3.0)]), Ok(n(7.0)));
+        assert_eq!(call("is_nan", &[n(f64::NAN)]), Ok(UvlValue::Bool(true)));
+        assert!(
+            matches!(call("min", &[n(1.0), n(f64::NAN)]), Ok(UvlValue::Number(m)) if m.is_nan())
+        );
+        assert_eq!(
+            call("sqrt", &[UvlValue::String("9".to_string())]),
+            Err("Argument 1 of 'sqrt' must be a Number, found \"9\" of type String".to_string())
+        );
+        assert_eq!(
+            call("min", &[]),
+            Err("Function 'min' expects at least 1 argument".to_string())
+        );
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::ast::Ctx;
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
+use crate::lexer::is_number_literal;
+use crate::types::Type;
+use crate::value::{UvlResult, UvlValue};
+
+/// Longest String in bytes `repeat` builds, so a huge count raises an error
+/// instead of aborting on allocation.
+const MAX_STRING_LEN: usize = 1 << 30;
+
+/// The parameter and return types of the native method `name` of the
+/// type called `type_name`.
+pub fn signature(type_name: &str, name: &str) -> Option<(Vec<Type>, Type)> {
+    let (params, returns) = match (type_name, name) {
+        ("List", "len") => (vec![], Type::Number),
+        ("Map", "len") => (vec![], Type::Number),
+        ("Map", "keys") => (vec![], Type::List),
+        ("Map", "has") => (vec![Type::String], Type::Bool),
+        // nil when the key is missing
+        ("Map", "get") => (vec![Type::String], Type::Any),
+        ("Result", "is_ok" | "is_err") => (vec![], Type::Bool),
+        // The error is nil for Ok
+        ("Result", "unwrap" | "error") => (vec![], Type::Any),
+        ("Result", "unwrap_or") => (vec![Type::Any], Type::Any),
+        ("String", name) => string_signature(name)?,
+        _ => return None,
+    };
+    Some((params, returns))
+}
+
+fn string_signature(name: &str) -> Option<(Vec<Type>, Type)> {
+    let (params, returns) = match name {
+        "len" => (vec![], Type::Number),
+        "upper" | "lower" | "trim" => (vec![], Type::String),
+        "split" => (vec![Type::String], Type::List),
+        "replace" => (vec![Type::String, Type::String], Type::String),
+        "starts_with" | "ends_with" => (vec![Type::String], Type::Bool),
+        // nil when not found
+        "find" => (vec![Type::String], Type::Any),
+        "chars" => (vec![], Type::List),
+        "repeat" => (vec![Type::Number], Type::String),
+        "slice" => (vec![Type::Number, Type::Number], Type::String),
+        "parse_number" => (vec![], Type::Number),
+        _ => return None,
+    };
+    Some((params, returns))
+}
+
+/// Calls the native method `name` of the receiver, `None` if its type has
+/// no such method.
+pub fn call(ctx: &Ctx, receiver: &UvlValue, name: &str, args: &[UvlValue]) -> Option<UvlResult> {
+    let (params, _) = signature(receiver.type_str(), name)?;
+    if params.len() != args.len() {
+        return Some(Err(error(
+            ctx,
+            format!(
+                "Method '{}' expects {} arguments, got {}",
+                name,
+                params.len(),
+                args.len()
+            ),
+        )));
+    }
+    for (position, (param, arg)) in params.iter().zip(args).enumerate() {
+        if !param.admits(arg) {
+            return Some(Err(error(
+                ctx,
+                format!(
+                    "Argument {} of '{}' must be a {}, found {} of type {}",
+                    position + 1,
+                    name,
+                    param,
+                    arg,
+                    arg.type_str()
+                ),
+            )));
+        }
+    }
+
+    Some(match receiver {
+        UvlValue::String(receiver) => call_string(ctx, receiver, name, args),
+        UvlValue::List(items) => Ok(UvlValue::Number(items.len() as f64)),
+        UvlValue::Map(entries) => Ok(call_map(entries, name, args)),
+        UvlValue::Result(result) => call_result(ctx, result, name, args),
+        _ => unreachable!("only Strings, Lists, Maps and Results have native methods"),
+    })
+}
+
+/// Lengths and positions count characters, not bytes.
+fn call_string(ctx: &Ctx, receiver: &str, name: &str, args: &[UvlValue]) -> UvlResult {
+    let string = |s: String| Ok(UvlValue::String(s));
+    let text = |position: usize| match &args[position] {
+        UvlValue::String(s) => s.as_str(),
+        _ => unreachable!("argument types are checked above"),
+    };
+    match name {
+        "len" => Ok(UvlValue::Number(receiver.chars().count() as f64)),
+        "upper" => string(receiver.to_uppercase()),
+        "lower" => string(receiver.to_lowercase()),
+        "trim" => string(receiver.trim().to_string()),
+        "split" => match text(0) {
+            "" => Err(
+                error(ctx, "Separator of 'split' must not be empty".to_string())
+                    .with_help("use `chars()` to split a String into characters".to_string()),
+            ),
+            separator => Ok(list(receiver.split(separator).map(str::to_string))),
+        },
+        "replace" => match text(0) {
+            "" => Err(error(
+                ctx,
+                "Pattern of 'replace' must not be empty".to_string(),
+            )),
+            pattern => string(receiver.replace(pattern, text(1))),
+        },
+        "starts_with" => Ok(UvlValue::Bool(receiver.starts_with(text(0)))),
+        "ends_with" => Ok(UvlValue::Bool(receiver.ends_with(text(0)))),
+        "find" => Ok(match receiver.find(text(0)) {
+            Some(byte) => UvlValue::Number(receiver[..byte].chars().count() as f64),
+            None => UvlValue::Nil(()),
+        }),
+        "chars" => Ok(list(receiver.chars().map(String::from))),
+        "repeat" => {
+            let n = count(ctx, name, &args[0])?;
+            match receiver.len().checked_mul(n) {
+                Some(len) if len <= MAX_STRING_LEN => string(receiver.repeat(n)),
+                _ => Err(error(
+                    ctx,
+                    format!(
+                        "Result of 'repeat' would be longer than {} bytes",
+                        MAX_STRING_LEN
+                    ),
+                )),
+            }
+        }
+        "slice" => slice(ctx, receiver, &args[0], &args[1]),
+        "parse_number" => {
+            // A number literal, negated by a leading '-'
+            let text = receiver.trim();
+            let literal = text.strip_prefix('-').unwrap_or(text);
+            match text.parse::<f64>() {
+                Ok(n) if is_number_literal(literal) => Ok(UvlValue::Number(n)),
+                _ => Err(error(
+                    ctx,
+                    format!("Cannot parse \"{}\" as a Number", receiver),
+                )),
+            }
+        }
+        _ => unreachable!("every method has a signature"),
+    }
+}
+
+fn call_map(entries: &BTreeMap<String, UvlValue>, name: &str, args: &[UvlValue]) -> UvlValue {
+    let key = || match &args[0] {
+        UvlValue::String(key) => key.as_str(),
+        _ => unreachable!("argument types are checked above"),
+    };
+    match name {
+        "len" => UvlValue::Number(entries.len() as f64),
+        "keys" => list(entries.keys().cloned()),
+        "has" => UvlValue::Bool(entries.contains_key(key())),
+        "get" => entries.get(key()).cloned().unwrap_or(UvlValue::Nil(())),
+        _ => unreachable!("every method has a signature"),
+    }
+}
+
+fn call_result(
+    ctx: &Ctx,
+    result: &Result<UvlValue, UvlValue>,
+    name: &str,
+    args: &[UvlValue],
+) -> UvlResult {
+    match (name, result) {
+        ("is_ok", _) => Ok(UvlValue::Bool(result.is_ok())),
+        ("is_err", _) => Ok(UvlValue::Bool(result.is_err())),
+        ("unwrap" | "unwrap_or", Ok(value)) => Ok(value.clone()),
+        ("unwrap", Err(e)) => Err(UvlError::new(
+            ErrorKind::Runtime,
+            Diagnostic::error(ctx, format!("Called 'unwrap' on Err({})", e))
+                .with_help("check `is_ok()` first, or use `unwrap_or(default)`".to_string()),
+        )),
+        ("unwrap_or", Err(_)) => Ok(args[0].clone()),
+        ("error", Ok(_)) => Ok(UvlValue::Nil(())),
+        ("error", Err(e)) => Ok(e.clone()),
+        _ => unreachable!("every method has a signature"),
+    }
+}
+
+/// The item of a List at `index`.
+pub fn index(ctx: &Ctx, items: &[UvlValue], index: &UvlValue) -> UvlResult {
+    match index {
+        UvlValue::Number(n) if *n >= 0.0 && n.fract() == 0.0 && (*n as usize) < items.len() => {
+            Ok(items[*n as usize].clone())
+        }
+        UvlValue::Number(n) if n.fract() == 0.0 => Err(error(
+            ctx,
+            format!(
+                "Index {} is out of range for a List of length {}",
+                n,
+                items.len()
+            ),
+        )),
+        _ => Err(error(
+            ctx,
+            format!(
+                "List indices must be whole numbers, found {} of type {}",
+                index,
+                index.type_str()
+            ),
+        )),
+    }
+}
+
+/// The value of a Map at `key`.
+pub fn lookup(ctx: &Ctx, entries: &BTreeMap<String, UvlValue>, key: &UvlValue) -> UvlResult {
+    match key {
+        UvlValue::String(key) => entries.get(key).cloned().ok_or_else(|| {
+            UvlError::new(
+                ErrorKind::Runtime,
+                Diagnostic::error(ctx, format!("Key \"{}\" is not in the Map", key))
+                    .with_help("use `get(key)` for nil when a key is missing".to_string()),
+            )
+        }),
+        _ => Err(error(
+            ctx,
+            format!(
+                "Map keys must be Strings, found {} of type {}",
+                key,
+                key.type_str()
+            ),
+        )),
+    }
+}
+
+/// The characters from `start` up to, not including, `end`.
+fn slice(ctx: &Ctx, receiver: &str, start: &UvlValue, end: &UvlValue) -> UvlResult {
+    let (start, end) = (count(ctx, "slice", start)?, count(ctx, "slice", end)?);
+    let len = receiver.chars().count();
+    if start > end || end > len {
+        return Err(error(
+            ctx,
+            format!(
+                "Slice {}..{} is out of range for a String of length {}",
+                start, end, len
+            ),
+        ));
+    }
+    Ok(UvlValue::String(
+        receiver.chars().skip(start).take(end - start).collect(),
+    ))
+}
+
+/// A count or position argument, which must be a whole number that is
+/// not negative.
+fn count(ctx: &Ctx, method: &str, arg: &UvlValue) -> Result<usize, UvlError> {
+    match arg {
+        UvlValue::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        _ => Err(error(
+            ctx,
+            format!(
+                "Arguments of '{}' must be whole numbers that are not negative, found {}",
+                method, arg
+            ),
+        )),
+    }
+}
+
+fn list(items: impl Iterator<Item = String>) -> UvlValue {
+    UvlValue::List(Rc::new(items.map(UvlValue::String).collect()))
+}
+
+fn error(ctx: &Ctx, message: String) -> UvlError {
+    UvlError::new(ErrorKind::Runtime, Diagnostic::error(ctx, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_ok(receiver: &str, name: &str, args: &[UvlValue]) -> UvlValue {
+        call(&Ctx::default(), &string(receiver), name, args)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn string(s: &str) -> UvlValue {
+        UvlValue::String(s.to_string())
+    }
+
+    #[test]
+    fn methods_count_characters() {
+        assert_eq!(call_ok("naïve", "len", &[]), UvlValue::Number(5.0));
+        assert_eq!(
+            call_ok("naïve", "find", &[string("v")]),
+            UvlValue::Number(3.0)
+        );
+        assert_eq!(
+            call_ok(
+                "naïve",
+                "slice",
+                &[UvlValue::Number(1.0), UvlValue::Number(3.0)]
+            ),
+            string("aï")
+        );
+        assert_eq!(call_ok("straße", "upper", &[]), string("STRASSE"));
+        assert_eq!(
+            call_ok("a,b", "split", &[string(",")]),
+            UvlValue::List(Rc::new(vec![string("a"), string("b")]))
+        );
+    }
+
+    #[test]
+    fn bad_arguments_are_runtime_errors() {
+        let ctx = Ctx::default();
+        let ab = string("ab");
+        let message =
+            |result: Option<UvlResult>| result.unwrap().unwrap_err().message().to_string();
+
+        assert_eq!(
+            message(call(&ctx, &ab, "repeat", &[UvlValue::Number(-1.0)])),
+            "Arguments of 'repeat' must be whole numbers that are not negative, found -1"
+        );
+        assert_eq!(
+            message(call(&ctx, &ab, "repeat", &[UvlValue::Number(1e19)])),
+            "Result of 'repeat' would be longer than 1073741824 bytes"
+        );
+        assert_eq!(call_ok("", "repeat", &[UvlValue::Number(1e19)]), string(""));
+        assert_eq!(
+            message(call(&ctx, &ab, "starts_with", &[UvlValue::Number(1.0)])),
+            "Argument 1 of 'starts_with' must be a String, found 1 of type Number"
+        );
+        assert_eq!(
+            message(call(
+                &ctx,
+                &ab,
+                "slice",
+                &[UvlValue::Number(1.0), UvlValue::Number(3.0)]
+            )),
+            "Slice 1..3 is out of range for a String of length 2"
+        );
+        assert_eq!(
+            message(call(&ctx, &string("1x"), "parse_number", &[])),
+            "Cannot parse \"1x\" as a Number"
+        );
+        for text in [
+            "inf", "NaN", "infinity", "-inf", "1e5", "+1", ".5", "1.", "--1", "",
+        ] {
+            assert_eq!(
+                message(call(&ctx, &string(text), "parse_number", &[])),
+                format!("Cannot parse \"{}\" as a Number", text)
+            );
+        }
+        assert_eq!(call_ok(" 42 ", "parse_number", &[]), UvlValue::Number(42.0));
+        assert_eq!(call_ok("-2.5", "parse_number", &[]), UvlValue::Number(-2.5));
+        assert!(call(&ctx, &ab, "reverse", &[]).is_none());
+        assert!(call(&ctx, &UvlValue::Number(1.0), "len", &[]).is_none());
+    }
+}
@@ -0,0 +1,166 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::value::{NativeFunction, UvlValue};
+
+/// Globals implemented in Rust. The interpreter looks a global up here when
+/// the source does not declare it, so the source may shadow them.
+#[derive(Default)]
+pub struct NativeRegistry {
+    globals: HashMap<String, UvlValue>,
+}
+
+impl NativeRegistry {
+    /// The registry every interpreter starts with: the `Ok` and `Err`
+    /// constructors of Results.
+    pub fn standard() -> Self {
+        let mut registry = NativeRegistry::default();
+        registry.define(
+            "Ok",
+            function("Ok", Some(1), |args| {
+                Ok(UvlValue::Result(Rc::new(Ok(args[0].clone()))))
+            }),
+        );
+        registry.define(
+            "Err",
+            function("Err", Some(1), |args| {
+                Ok(UvlValue::Result(Rc::new(Err(args[0].clone()))))
+            }),
+        );
+        registry
+    }
+
+    pub fn define(&mut self, name: &str, value: UvlValue) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&UvlValue> {
+        self.globals.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.globals.keys().map(String::as_str)
+    }
+}
+
+/// A native function value.
+pub fn function(
+    name: &str,
+    arity: Option<usize>,
+    function: impl Fn(&[UvlValue]) -> Result<UvlValue, String> + 'static,
+) -> UvlValue {
+    UvlValue::Native(Rc::new(NativeFunction::new(name, arity, function)))
+}
+
+/// The `members` of the module `module`, each raising an error when called
+/// while `allowed` is false. It is checked on every call, so access granted
+/// later applies to them too.
+pub fn gate(
+    module: &'static str,
+    allowed: &Rc<Cell<bool>>,
+    members: Vec<(&'static str, UvlValue)>,
+) -> Vec<(&'static str, UvlValue)> {
+    let gated = |value: UvlValue| match value {
+        UvlValue::Native(native) => {
+            let (name, arity, allowed) = (native.name.clone(), native.arity, allowed.clone());
+            function(&name, arity, move |args| {
+                if !allowed.get() {
+                    return Err(format!(
+                        "The {} module is disabled, the host has not allowed system access",
+                        module
+                    ));
+                }
+                (native.function)(args)
+            })
+        }
+        value => value,
+    };
+    members
+        .into_iter()
+        .map(|(name, value)| (name, gated(value)))
+        .collect()
+}
+
+/// Calls the member `name` of a native module, which must be a function.
+#[cfg(test)]
+pub fn call_member(
+    members: &[(&str, UvlValue)],
+    name: &str,
+    args: &[UvlValue],
+) -> Result<UvlValue, String> {
+    match members.iter().find(|(member, _)| *member == name) {
+        Some((_, UvlValue::Native(native))) => (native.function)(args),
+        _ => panic!("'{}' is not a function", name),
+    }
+}
+
+/// The argument at `position` of the native function `name`, which must be
+/// a Number.
+pub fn number(name: &str, args: &[UvlValue], position: usize) -> Result<f64, String> {
+    match &args[position] {
+        UvlValue::Number(n) => Ok(*n),
+        arg => Err(format!(
+            "Argument {} of '{}' must be a Number, found {} of type {}",
+            position + 1,
+            name,
+            arg,
+            arg.type_str()
+        )),
+    }
+}
+
+/// The argument at `position` of the native function `name`, which must be
+/// a String.
+pub fn string<'v>(name: &str, args: &'v [UvlValue], position: usize) -> Result<&'v str, String> {
+    match &args[position] {
+        UvlValue::String(s) => Ok(s),
+        arg => Err(format!(
+            "Argument {} of '{}' must be a String, found {} of type {}",
+            position + 1,
+            name,
+            arg,
+            arg.type_str()
+        )),
+    }
+}
+
+/// The argument at `position` of the native function `name`, which must be
+/// a Bool.
+pub fn boolean(name: &str, args: &[UvlValue], position: usize) -> Result<bool, String> {
+    match &args[position] {
+        UvlValue::Bool(b) => Ok(*b),
+        arg => Err(format!(
+            "Argument {} of '{}' must be a Bool, found {} of type {}",
+            position + 1,
+            name,
+            arg,
+            arg.type_str()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gated_members_check_access_on_every_call() {
+        let allowed = Rc::new(Cell::new(false));
+        let members = gate(
+            "clock",
+            &allowed,
+            vec![(
+                "now",
+                function("now", Some(0), |_| Ok(UvlValue::Number(1.0))),
+            )],
+        );
+
+        assert_eq!(
+            call_member(&members, "now", &[]),
+            Err("The clock module is disabled, the host has not allowed system access".to_string())
+        );
+        allowed.set(true);
+        assert_eq!(call_member(&members, "now", &[]), Ok(UvlValue::Number(1.0)));
+    }
+}
@@ -1,58 +1,176 @@
-use crate::ast::{Ctx, Expr, Mutable, Stmt};
+use std::rc::Rc;
+
+use crate::ast::{Binding, Ctx, Doc, Expr, Mutable, Param, Stmt, TraitMethod, TypeExpr};
+use crate::common::nesting_error;
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
 use crate::token::{Token, TokenType};
 
-type ParserResult<T> = Result<T, String>;
+type ParserResult<T> = Result<T, UvlError>;
+
+/// The name, parameters and return type of a function.
+type Signature<'a> = (Token<'a>, Vec<Param<'a>>, Option<TypeExpr<'a>>);
+
+/// Default limit on how deeply statements and expressions may nest, low
+/// enough for the 8 MiB main thread of a debug build.
+pub const DEFAULT_MAX_DEPTH: usize = 500;
 
 #[derive(Debug)]
 struct ParserState {
     current: usize,
+    depth: usize,
+    function_depth: usize,
 }
 
 impl ParserState {
     pub fn new() -> Self {
-        ParserState { current: 0 }
+        ParserState {
+            current: 0,
+            depth: 0,
+            function_depth: 0,
+        }
     }
 }
 
-pub struct Parser<'a> {
-    source_name: &'a str,
+/// Parses tokens borrowed for `'t` into statements that borrow the source
+/// text for `'a`, so the AST can outlive the token buffer.
+pub struct Parser<'t, 'a> {
+    source_name: &'t str,
+    module: &'t str,
     prompt_mode: bool,
-    tokens: &'a Vec<Token<'a>>,
+    max_depth: usize,
+    tokens: &'t [Token<'a>],
+    errors: Vec<UvlError>,
     state: ParserState,
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a Vec<Token<'a>>, source_name: &'a str, prompt_mode: bool) -> Self {
+impl<'t, 'a> Parser<'t, 'a> {
+    pub fn new(tokens: &'t [Token<'a>], source_name: &'t str, prompt_mode: bool) -> Self {
         Parser {
             source_name,
+            module: "root",
             prompt_mode,
+            max_depth: DEFAULT_MAX_DEPTH,
             tokens,
+            errors: vec![],
             state: ParserState::new(),
         }
     }
 
-    pub fn parse(&mut self) -> ParserResult<Vec<Stmt<'a>>> {
+    /// Sets how deeply statements and expressions may nest before parsing
+    /// fails with a RecursionError.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the module name the parsed nodes belong to.
+    pub fn with_module(mut self, module: &'t str) -> Self {
+        self.module = module;
+        self
+    }
+
+    /// Parses the whole program. After a syntax error the parser skips to
+    /// the next statement and carries on, so every error is reported.
+    pub fn parse(&mut self) -> Result<Vec<Stmt<'a>>, Vec<UvlError>> {
         let mut stmts = Vec::<Stmt<'a>>::new();
         while !self.is_at_end() {
+            let start = self.state.current;
             match self.statement() {
                 Ok(stmt) => stmts.push(stmt),
-                Err(e) => return Err(e),
+                Err(e) => {
+                    let current = self.state.current;
+                    self.errors.push(e);
+                    self.synchronize(start);
+
+                    // A stray '}' has no block to close at the top level
+                    if self.state.current == current && self.check(&TokenType::RightBrace) {
+                        self.advance();
+                    }
+                }
             }
         }
 
-        Ok(stmts)
+        if self.errors.is_empty() {
+            Ok(stmts)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Context for `token` in the source being parsed.
+    fn ctx(&self, token: &Token<'a>) -> Ctx {
+        Ctx {
+            module: self.module.to_string(),
+            ..Ctx::from_token(self.source_name, token)
+        }
+    }
+
+    /// Context spanning from `start` to the last consumed token.
+    fn ctx_since(&self, start: &Ctx) -> Ctx {
+        start.to(&self.ctx(self.previous()))
     }
 
     fn statement(&mut self) -> ParserResult<Stmt<'a>> {
-        if self.match_ttokens(&vec![&TokenType::Let]) {
+        self.nested(Self::any_statement)
+    }
+
+    fn any_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        if self.match_ttokens(&[
+            &TokenType::Import,
+            &TokenType::Use,
+            &TokenType::Pub,
+            &TokenType::Struct,
+        ]) {
+            // Statements are parsed one level deep at the top level
+            if self.state.depth > 1 {
+                let message = format!(
+                    "'{}' is only allowed at the top level of a module",
+                    self.previous().lexeme
+                );
+                return Err(self.error(&self.previous().clone(), &message, None));
+            }
+            return match self.previous().ttype {
+                TokenType::Import => self.import_statement(),
+                TokenType::Use => self.use_statement(),
+                TokenType::Struct => self.struct_statement(),
+                _ => self.pub_statement(),
+            };
+        }
+
+        if self.match_ttokens(&[&TokenType::Let]) {
             return self.let_statement();
         }
 
-        if self.match_ttokens(&vec![&TokenType::PrintLn]) {
+        if self.match_ttokens(&[&TokenType::Const]) {
+            return self.const_statement();
+        }
+
+        if self.match_ttokens(&[&TokenType::Fun]) {
+            return self.function_statement();
+        }
+
+        if self.match_ttokens(&[&TokenType::Trait]) {
+            return self.trait_statement();
+        }
+
+        if self.match_ttokens(&[&TokenType::Impl]) {
+            return self.impl_statement();
+        }
+
+        if self.match_ttokens(&[&TokenType::If]) {
+            return self.if_statement();
+        }
+
+        if self.match_ttokens(&[&TokenType::Return]) {
+            return self.return_statement();
+        }
+
+        if self.match_ttokens(&[&TokenType::PrintLn]) {
             return self.print_statement();
         }
 
-        if self.match_ttokens(&vec![&TokenType::LeftBrace]) {
+        if self.match_ttokens(&[&TokenType::LeftBrace]) {
             return self.block_statement();
         }
 
@@ -60,18 +178,19 @@ impl<'a> Parser<'a> {
     }
 
     fn print_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.previous());
         match self.expr() {
             Ok(expr) => {
-                if self.match_ttokens(&vec![&TokenType::Semicolon]) || self.prompt_mode {
-                    Ok(Stmt::PrintLn(
-                        Ctx::from_token(self.previous()),
-                        Box::new(expr),
-                    ))
+                if self.match_ttokens(&[&TokenType::Semicolon]) || self.prompt_mode {
+                    Ok(Stmt::PrintLn(self.ctx_since(&start), Box::new(expr)))
                 } else {
-                    Err(Parser::make_parse_error_message(
-                        &self,
-                        self.peek(),
-                        "Expect ';' after statement",
+                    Err(UvlError::new(
+                        ErrorKind::Parser,
+                        Parser::make_parse_error_message(
+                            self,
+                            self.peek(),
+                            "Expect ';' after statement",
+                        ),
                     ))
                 }
             }
@@ -80,6 +199,8 @@ impl<'a> Parser<'a> {
     }
 
     fn let_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let doc = Doc(self.previous().doc.clone());
+        let start = self.ctx(self.previous());
         let is_mutable = if self.peek().ttype == TokenType::Mut {
             self.advance();
             true
@@ -89,77 +210,478 @@ impl<'a> Parser<'a> {
 
         if self.peek().ttype == TokenType::Identifier {
             let identifier = self.advance().clone();
+            let annotation = self.annotation()?;
 
-            if self.match_ttokens(&vec![&TokenType::Equal]) {
+            if self.match_ttokens(&[&TokenType::Equal]) {
                 match self.expr() {
                     Ok(expr) => {
-                        if self.match_ttokens(&vec![&TokenType::Semicolon]) || self.prompt_mode {
+                        if self.match_ttokens(&[&TokenType::Semicolon]) || self.prompt_mode {
                             Ok(Stmt::Let(
-                                Ctx::from_token(&identifier),
+                                self.ctx_since(&start),
                                 identifier,
                                 Mutable(is_mutable),
+                                annotation,
                                 Box::new(expr),
+                                doc,
                             ))
                         } else {
-                            Err(Parser::make_parse_error_message(
-                                &self,
-                                self.peek(),
-                                "Expect ';' after expression",
+                            Err(UvlError::new(
+                                ErrorKind::Parser,
+                                Parser::make_parse_error_message(
+                                    self,
+                                    self.peek(),
+                                    "Expect ';' after expression",
+                                ),
                             ))
                         }
                     }
                     Err(e) => Err(e),
                 }
             } else {
-                Err(Parser::make_parse_error_message(
-                    &self,
-                    self.peek(),
-                    "Expect initialization",
+                Err(UvlError::new(
+                    ErrorKind::Parser,
+                    Parser::make_parse_error_message(self, self.peek(), "Expect initialization"),
                 ))
             }
         } else {
-            Err(Parser::make_parse_error_message(
-                &self,
-                self.peek(),
-                "Expect identifier after let",
+            Err(UvlError::new(
+                ErrorKind::Parser,
+                Parser::make_parse_error_message(self, self.peek(), "Expect identifier after let"),
             ))
         }
     }
 
+    fn const_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let doc = Doc(self.previous().doc.clone());
+        let start = self.ctx(self.previous());
+        let name = self.consume(&TokenType::Identifier, "Expect identifier after const")?;
+        self.consume(&TokenType::Equal, "Expect initialization")?;
+        let expr = self.expr()?;
+        if !self.match_ttokens(&[&TokenType::Semicolon]) && !self.prompt_mode {
+            return Err(self.error(self.peek(), "Expect ';' after expression", None));
+        }
+
+        Ok(Stmt::Const(
+            self.ctx_since(&start),
+            name,
+            Box::new(expr),
+            doc,
+        ))
+    }
+
+    fn function_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let doc = Doc(self.previous().doc.clone());
+        let start = self.ctx(self.previous());
+        let (name, params, return_type) = self.function_signature()?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' before function body")?;
+        let body = self.function_body()?;
+
+        Ok(Stmt::Function(
+            self.ctx_since(&start),
+            name,
+            params,
+            return_type,
+            body,
+            doc,
+        ))
+    }
+
+    /// Parses `name(params) -> Type` after `fun`.
+    fn function_signature(&mut self) -> ParserResult<Signature<'a>> {
+        let name = self.consume(&TokenType::Identifier, "Expect function name")?;
+        let paren = self.consume(&TokenType::LeftParen, "Expect '(' after function name")?;
+
+        let mut params = vec![];
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                let name = self.consume(&TokenType::Identifier, "Expect parameter name")?;
+                let annotation = self.annotation()?;
+                params.push(Param { name, annotation });
+                if !self.match_ttokens(&[&TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        if !self.match_ttokens(&[&TokenType::RightParen]) {
+            return Err(self.error(self.peek(), "Expect ')' after parameters", Some(&paren)));
+        }
+        let return_type = if self.match_ttokens(&[&TokenType::Arrow]) {
+            Some(self.type_expr()?)
+        } else {
+            None
+        };
+
+        Ok((name, params, return_type))
+    }
+
+    /// Parses a method signature, whose first parameter is `self`.
+    fn method_signature(&mut self) -> ParserResult<Signature<'a>> {
+        let (name, params, return_type) = self.function_signature()?;
+        match params.first() {
+            Some(param) if param.name.lexeme == "self" => Ok((name, params, return_type)),
+            Some(param) => Err(self.error(
+                &param.name,
+                "Expect 'self' as the first parameter of a method",
+                None,
+            )),
+            None => Err(self.error(
+                self.previous(),
+                "Expect 'self' as the first parameter of a method",
+                None,
+            )),
+        }
+    }
+
+    /// Parses a function body whose '{' was just consumed.
+    fn function_body(&mut self) -> ParserResult<Rc<Vec<Stmt<'a>>>> {
+        self.state.function_depth += 1;
+        let body = self.block();
+        self.state.function_depth -= 1;
+        Ok(Rc::new(body?))
+    }
+
+    fn import_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.previous());
+        if !matches!(self.peek().ttype, TokenType::String(_)) {
+            return Err(self.error(self.peek(), "Expect module path after 'import'", None));
+        }
+        let path = self.advance().clone();
+        self.consume(&TokenType::As, "Expect 'as' after module path")?;
+        let name = self.consume(&TokenType::Identifier, "Expect module name after 'as'")?;
+        self.end_statement("Expect ';' after import")?;
+
+        Ok(Stmt::Import(self.ctx_since(&start), path, name))
+    }
+
+    /// Parses `module::name;` or `module::{a, b};` after `use`.
+    fn use_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.previous());
+        let module = self.consume(&TokenType::Identifier, "Expect module name after 'use'")?;
+        self.consume(&TokenType::ColonColon, "Expect '::' after module name")?;
+
+        let mut names = vec![];
+        if self.match_ttokens(&[&TokenType::LeftBrace]) {
+            let brace = self.previous().clone();
+            loop {
+                names.push(self.consume(&TokenType::Identifier, "Expect name to use")?);
+                if !self.match_ttokens(&[&TokenType::Comma]) {
+                    break;
+                }
+            }
+            if !self.match_ttokens(&[&TokenType::RightBrace]) {
+                return Err(self.error(self.peek(), "Expect '}' after names", Some(&brace)));
+            }
+        } else {
+            names.push(self.consume(&TokenType::Identifier, "Expect name to use")?);
+        }
+        self.end_statement("Expect ';' after use")?;
+
+        Ok(Stmt::Use(self.ctx_since(&start), module, names))
+    }
+
+    fn pub_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.previous());
+        let stmt = if self.match_ttokens(&[&TokenType::Let]) {
+            self.let_statement()?
+        } else if self.match_ttokens(&[&TokenType::Const]) {
+            self.const_statement()?
+        } else if self.match_ttokens(&[&TokenType::Fun]) {
+            self.function_statement()?
+        } else if self.match_ttokens(&[&TokenType::Struct]) {
+            self.struct_statement()?
+        } else {
+            return Err(self.error(
+                self.peek(),
+                "Expect 'let', 'const', 'fun' or 'struct' after 'pub'",
+                None,
+            ));
+        };
+
+        Ok(Stmt::Pub(self.ctx_since(&start), Box::new(stmt)))
+    }
+
+    /// Consumes the ';' ending a statement, optional at the prompt.
+    fn end_statement(&mut self, message: &str) -> ParserResult<()> {
+        if self.match_ttokens(&[&TokenType::Semicolon]) || self.prompt_mode {
+            Ok(())
+        } else {
+            Err(self.error(self.peek(), message, None))
+        }
+    }
+
+    /// Parses `Name { field, field: Type }` after `struct`.
+    fn struct_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let doc = Doc(self.previous().doc.clone());
+        let start = self.ctx(self.previous());
+        let name = self.consume(&TokenType::Identifier, "Expect struct name")?;
+        let brace = self.consume(&TokenType::LeftBrace, "Expect '{' after struct name")?;
+
+        let mut fields = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            match self.struct_field() {
+                Ok(field) => fields.push(field),
+                Err(e) => {
+                    self.recover_in_item(e)?;
+                    break;
+                }
+            }
+            if !self.match_ttokens(&[&TokenType::Comma]) {
+                break;
+            }
+        }
+        if !self.match_ttokens(&[&TokenType::RightBrace]) {
+            return Err(self.error(self.peek(), "Expect '}' after fields", Some(&brace)));
+        }
+
+        Ok(Stmt::Struct(self.ctx_since(&start), name, fields, doc))
+    }
+
+    fn struct_field(&mut self) -> ParserResult<Param<'a>> {
+        let name = self.consume(&TokenType::Identifier, "Expect field name")?;
+        let annotation = self.annotation()?;
+        Ok(Param { name, annotation })
+    }
+
+    fn trait_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let doc = Doc(self.previous().doc.clone());
+        let start = self.ctx(self.previous());
+        let name = self.consume(&TokenType::Identifier, "Expect trait name")?;
+        let brace = self.consume(&TokenType::LeftBrace, "Expect '{' after trait name")?;
+
+        let mut methods = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            match self.trait_method() {
+                Ok(method) => methods.push(method),
+                Err(e) => self.recover_in_item(e)?,
+            }
+        }
+        if !self.match_ttokens(&[&TokenType::RightBrace]) {
+            return Err(self.error(self.peek(), "Expect '}' after trait body", Some(&brace)));
+        }
+
+        Ok(Stmt::Trait(self.ctx_since(&start), name, methods, doc))
+    }
+
+    fn impl_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.previous());
+        let trait_name = self.consume(&TokenType::Identifier, "Expect trait name after impl")?;
+        self.consume(&TokenType::For, "Expect 'for' after trait name")?;
+        let type_name = self.consume(&TokenType::Identifier, "Expect type name after 'for'")?;
+        let brace = self.consume(&TokenType::LeftBrace, "Expect '{' after impl type")?;
+
+        let mut methods = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            match self.impl_method() {
+                Ok(method) => methods.push(method),
+                Err(e) => self.recover_in_item(e)?,
+            }
+        }
+        if !self.match_ttokens(&[&TokenType::RightBrace]) {
+            return Err(self.error(self.peek(), "Expect '}' after impl body", Some(&brace)));
+        }
+
+        Ok(Stmt::Impl(
+            self.ctx_since(&start),
+            trait_name,
+            type_name,
+            methods,
+        ))
+    }
+
+    fn trait_method(&mut self) -> ParserResult<TraitMethod<'a>> {
+        let start = self.ctx(self.peek());
+        self.consume(&TokenType::Fun, "Expect 'fun' before trait method")?;
+        let (name, params, return_type) = self.method_signature()?;
+        let body = if self.match_ttokens(&[&TokenType::Semicolon]) {
+            None
+        } else {
+            self.consume(
+                &TokenType::LeftBrace,
+                "Expect '{' or ';' after method signature",
+            )?;
+            Some(self.function_body()?)
+        };
+
+        Ok(TraitMethod {
+            ctx: self.ctx_since(&start),
+            name,
+            params,
+            return_type,
+            body,
+        })
+    }
+
+    fn impl_method(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.peek());
+        let doc = Doc(self.peek().doc.clone());
+        self.consume(&TokenType::Fun, "Expect 'fun' before method")?;
+        let (name, params, return_type) = self.method_signature()?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' before method body")?;
+        let body = self.function_body()?;
+
+        Ok(Stmt::Function(
+            self.ctx_since(&start),
+            name,
+            params,
+            return_type,
+            body,
+            doc,
+        ))
+    }
+
+    /// Records an error inside a trait, impl or struct body and skips to its
+    /// next method or closing '}'.
+    fn recover_in_item(&mut self, error: UvlError) -> ParserResult<()> {
+        if error.kind() == ErrorKind::Recursion {
+            return Err(error);
+        }
+        self.errors.push(error);
+
+        let mut depth = 0;
+        while !self.is_at_end() {
+            match self.peek().ttype {
+                TokenType::Fun | TokenType::RightBrace if depth == 0 => break,
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => depth -= 1,
+                _ => {}
+            }
+            self.advance();
+        }
+        Ok(())
+    }
+
+    /// Parses an optional `: Type` annotation.
+    fn annotation(&mut self) -> ParserResult<Option<TypeExpr<'a>>> {
+        if self.match_ttokens(&[&TokenType::Colon]) {
+            Ok(Some(self.type_expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses a type name or a function type such as `fun(Number) -> Bool`.
+    fn type_expr(&mut self) -> ParserResult<TypeExpr<'a>> {
+        if !self.match_ttokens(&[&TokenType::Fun]) {
+            let name = self.consume(&TokenType::Identifier, "Expect a type")?;
+            return Ok(TypeExpr::Named(self.ctx(&name), name));
+        }
+
+        let start = self.ctx(self.previous());
+        let paren = self.consume(&TokenType::LeftParen, "Expect '(' after fun")?;
+        let mut params = vec![];
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                params.push(self.nested(Self::type_expr)?);
+                if !self.match_ttokens(&[&TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        if !self.match_ttokens(&[&TokenType::RightParen]) {
+            return Err(self.error(
+                self.peek(),
+                "Expect ')' after parameter types",
+                Some(&paren),
+            ));
+        }
+        self.consume(&TokenType::Arrow, "Expect '->' after parameter types")?;
+        let return_type = self.nested(Self::type_expr)?;
+
+        Ok(TypeExpr::Function(
+            self.ctx_since(&start),
+            params,
+            Box::new(return_type),
+        ))
+    }
+
+    fn if_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.previous());
+        let condition = self.expr()?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' after if condition")?;
+        let then_branch = self.block_statement()?;
+
+        let else_branch = if self.match_ttokens(&[&TokenType::Else]) {
+            if self.match_ttokens(&[&TokenType::If]) {
+                Some(Box::new(self.if_statement()?))
+            } else {
+                self.consume(&TokenType::LeftBrace, "Expect '{' after else")?;
+                Some(Box::new(self.block_statement()?))
+            }
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(
+            self.ctx_since(&start),
+            Box::new(condition),
+            Box::new(then_branch),
+            else_branch,
+        ))
+    }
+
+    fn return_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let keyword = self.previous().clone();
+        if self.state.function_depth == 0 {
+            return Err(self.error(&keyword, "Can't return from top-level code", None));
+        }
+
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(Box::new(self.expr()?))
+        };
+        self.consume(&TokenType::Semicolon, "Expect ';' after return value")?;
+
+        Ok(Stmt::Return(self.ctx_since(&self.ctx(&keyword)), value))
+    }
+
     fn block_statement(&mut self) -> ParserResult<Stmt<'a>> {
+        let start = self.ctx(self.previous());
+        let stmts = self.block()?;
+        Ok(Stmt::Block(self.ctx_since(&start), stmts))
+    }
+
+    /// Parses the statements of a block whose '{' was just consumed.
+    fn block(&mut self) -> ParserResult<Vec<Stmt<'a>>> {
         let mut stmts = Vec::new();
-        let ctx = Ctx::from_token(self.previous());
+        let start = self.ctx(self.previous());
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let start = self.state.current;
             match self.statement() {
-                Ok(stmt) => stmts.push(Box::new(stmt)),
-                Err(e) => return Err(e),
+                Ok(stmt) => stmts.push(stmt),
+                // Recovering would only hit the limit again
+                Err(e) if e.kind() == ErrorKind::Recursion => return Err(e),
+                Err(e) => {
+                    self.errors.push(e);
+                    self.synchronize(start);
+                }
             }
         }
 
-        if !self.match_ttokens(&vec![&TokenType::RightBrace]) {
-            return Err(Parser::make_parse_error_message(
-                &self,
-                self.peek(),
-                "Expect '}' after block",
+        if !self.match_ttokens(&[&TokenType::RightBrace]) {
+            return Err(UvlError::new(
+                ErrorKind::Parser,
+                Parser::make_parse_error_message(self, self.peek(), "Expect '}' after block")
+                    .with_secondary_label(start.span, "unclosed delimiter".to_string()),
             ));
         }
 
-        Ok(Stmt::Block(ctx, stmts))
+        Ok(stmts)
     }
 
     fn expression_statement(&mut self) -> ParserResult<Stmt<'a>> {
         match self.expr() {
             Ok(expr) => {
-                if self.match_ttokens(&vec![&TokenType::Semicolon]) || self.prompt_mode {
-                    Ok(Stmt::Expression(
-                        Ctx::from_token(self.previous()),
-                        Box::new(expr),
-                    ))
+                if self.match_ttokens(&[&TokenType::Semicolon]) || self.prompt_mode {
+                    Ok(Stmt::Expression(self.ctx_since(expr.ctx()), Box::new(expr)))
                 } else {
-                    Err(Parser::make_parse_error_message(
-                        &self,
-                        self.peek(),
-                        "Expect ';' after expression",
+                    Err(UvlError::new(
+                        ErrorKind::Parser,
+                        Parser::make_parse_error_message(
+                            self,
+                            self.peek(),
+                            "Expect ';' after expression",
+                        ),
                     ))
                 }
             }
@@ -168,31 +690,26 @@ impl<'a> Parser<'a> {
     }
 
     fn expr(&mut self) -> ParserResult<Expr<'a>> {
-        self.assignment()
+        self.nested(Self::assignment)
     }
 
     fn assignment(&mut self) -> ParserResult<Expr<'a>> {
-        let expr = match self.equality() {
-            Ok(expr) => expr,
-            Err(e) => return Err(e),
-        };
+        let expr = self.equality()?;
 
-        if self.match_ttokens(&vec![&TokenType::Equal]) {
+        if self.match_ttokens(&[&TokenType::Equal]) {
             let toke_eq = self.previous().clone();
-            let value = match self.assignment() {
-                Ok(value) => value,
-                Err(e) => return Err(e),
-            };
+            let value = self.expr()?;
 
             match expr {
-                Expr::Variable(ctx, token) => {
-                    let name = token.clone();
-                    Ok(Expr::Assign(ctx.clone(), name, Box::new(value.clone())))
-                }
-                _ => Err(Parser::make_parse_error_message(
-                    &self,
-                    &toke_eq,
-                    "Invalid assignment value",
+                Expr::Variable(ctx, name, binding) => Ok(Expr::Assign(
+                    ctx.to(value.ctx()),
+                    name,
+                    binding,
+                    Box::new(value),
+                )),
+                _ => Err(UvlError::new(
+                    ErrorKind::Parser,
+                    Parser::make_parse_error_message(self, &toke_eq, "Invalid assignment value"),
                 )),
             }
         } else {
@@ -201,144 +718,184 @@ impl<'a> Parser<'a> {
     }
 
     fn equality(&mut self) -> ParserResult<Expr<'a>> {
-        match self.comparison() {
-            Ok(expr) => {
-                while self.match_ttokens(&vec![&TokenType::BangEqual, &TokenType::EqualEqual]) {
-                    let operator = self.previous().clone();
-                    if let Ok(right) = self.comparison() {
-                        return Ok(Expr::Binary(
-                            Ctx::from_token(self.previous()),
-                            Box::new(expr),
-                            operator,
-                            Box::new(right),
-                        ));
-                    };
-                }
+        let mut expr = self.comparison()?;
 
-                Ok(expr)
-            }
-            Err(e) => Err(e),
+        while self.match_ttokens(&[&TokenType::BangEqual, &TokenType::EqualEqual]) {
+            let operator = self.previous().clone();
+            self.link()?;
+            let right = self.comparison()?;
+            expr = Expr::Binary(
+                expr.ctx().to(right.ctx()),
+                Box::new(expr),
+                operator,
+                Box::new(right),
+            );
         }
+
+        Ok(expr)
     }
 
     fn comparison(&mut self) -> ParserResult<Expr<'a>> {
-        match self.term() {
-            Ok(expr) => {
-                while self.match_ttokens(&vec![
-                    &TokenType::Greater,
-                    &TokenType::GreaterEqual,
-                    &TokenType::Less,
-                    &TokenType::LessEqual,
-                ]) {
-                    let operator = self.previous().clone();
-                    if let Ok(right) = self.term() {
-                        return Ok(Expr::Binary(
-                            Ctx::from_token(self.previous()),
-                            Box::new(expr),
-                            operator,
-                            Box::new(right),
-                        ));
-                    }
-                }
+        let mut expr = self.term()?;
 
-                Ok(expr)
-            }
-            Err(e) => Err(e),
+        while self.match_ttokens(&[
+            &TokenType::Greater,
+            &TokenType::GreaterEqual,
+            &TokenType::Less,
+            &TokenType::LessEqual,
+        ]) {
+            let operator = self.previous().clone();
+            self.link()?;
+            let right = self.term()?;
+            expr = Expr::Binary(
+                expr.ctx().to(right.ctx()),
+                Box::new(expr),
+                operator,
+                Box::new(right),
+            );
         }
+
+        Ok(expr)
     }
 
     fn term(&mut self) -> ParserResult<Expr<'a>> {
-        match self.factor() {
-            Ok(expr) => {
-                while self.match_ttokens(&vec![&TokenType::Minus, &TokenType::Plus]) {
-                    let operator = self.previous().clone();
-                    if let Ok(right) = self.factor() {
-                        return Ok(Expr::Binary(
-                            Ctx::from_token(self.previous()),
-                            Box::new(expr),
-                            operator,
-                            Box::new(right),
-                        ));
-                    }
-                }
+        let mut expr = self.factor()?;
 
-                Ok(expr)
-            }
-            Err(e) => Err(e),
+        while self.match_ttokens(&[&TokenType::Minus, &TokenType::Plus]) {
+            let operator = self.previous().clone();
+            self.link()?;
+            let right = self.factor()?;
+            expr = Expr::Binary(
+                expr.ctx().to(right.ctx()),
+                Box::new(expr),
+                operator,
+                Box::new(right),
+            );
         }
+
+        Ok(expr)
     }
 
     fn factor(&mut self) -> ParserResult<Expr<'a>> {
-        match self.unary() {
-            Ok(expr) => {
-                while self.match_ttokens(&vec![&TokenType::Slash, &TokenType::Star]) {
-                    let operator = self.previous().clone();
-                    if let Ok(right) = self.unary() {
-                        return Ok(Expr::Binary(
-                            Ctx::from_token(self.previous()),
-                            Box::new(expr),
-                            operator,
-                            Box::new(right),
-                        ));
-                    }
-                }
+        let mut expr = self.unary()?;
 
-                Ok(expr)
-            }
-            Err(e) => Err(e),
+        while self.match_ttokens(&[&TokenType::Slash, &TokenType::Star]) {
+            let operator = self.previous().clone();
+            self.link()?;
+            let right = self.unary()?;
+            expr = Expr::Binary(
+                expr.ctx().to(right.ctx()),
+                Box::new(expr),
+                operator,
+                Box::new(right),
+            );
         }
+
+        Ok(expr)
     }
 
     fn unary(&mut self) -> ParserResult<Expr<'a>> {
-        if self.match_ttokens(&vec![&TokenType::Bang, &TokenType::Minus]) {
+        if self.match_ttokens(&[&TokenType::Bang, &TokenType::Minus]) {
             let operator = self.previous().clone();
-            if let Ok(right) = self.unary() {
-                return Ok(Expr::Unary(
-                    Ctx::from_token(self.previous()),
-                    operator,
-                    Box::new(right),
-                ));
+            let right = self.nested(Self::unary)?;
+            return Ok(Expr::Unary(
+                self.ctx(&operator).to(right.ctx()),
+                operator,
+                Box::new(right),
+            ));
+        }
+
+        self.call()
+    }
+
+    fn call(&mut self) -> ParserResult<Expr<'a>> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.check(&TokenType::LeftParen)
+                || self.check(&TokenType::Dot)
+                || self.check(&TokenType::LeftBracket)
+            {
+                self.link()?;
+            }
+            if self.match_ttokens(&[&TokenType::LeftParen]) {
+                let args = self.arguments()?;
+                expr = Expr::Call(self.ctx_since(expr.ctx()), Box::new(expr), args);
+            } else if self.match_ttokens(&[&TokenType::Dot]) {
+                let name = self.consume(&TokenType::Identifier, "Expect name after '.'")?;
+                if self.match_ttokens(&[&TokenType::LeftParen]) {
+                    let args = self.arguments()?;
+                    expr = Expr::MethodCall(self.ctx_since(expr.ctx()), Box::new(expr), name, args);
+                } else {
+                    expr = Expr::Get(self.ctx_since(expr.ctx()), Box::new(expr), name);
+                }
+            } else if self.match_ttokens(&[&TokenType::LeftBracket]) {
+                let bracket = self.previous().clone();
+                let index = self.expr()?;
+                if !self.match_ttokens(&[&TokenType::RightBracket]) {
+                    return Err(self.error(self.peek(), "Expect ']' after index", Some(&bracket)));
+                }
+                expr = Expr::Index(self.ctx_since(expr.ctx()), Box::new(expr), Box::new(index));
+            } else {
+                break;
             }
         }
 
-        self.primary()
+        Ok(expr)
     }
 
-    fn primary(&mut self) -> Result<Expr<'a>, String> {
+    /// Parses call arguments after the '(' that was just consumed.
+    fn arguments(&mut self) -> ParserResult<Vec<Expr<'a>>> {
+        let paren = self.previous().clone();
+        let mut args = vec![];
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                args.push(self.expr()?);
+                if !self.match_ttokens(&[&TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        if !self.match_ttokens(&[&TokenType::RightParen]) {
+            return Err(self.error(self.peek(), "Expect ')' after arguments", Some(&paren)));
+        }
+
+        Ok(args)
+    }
+
+    fn primary(&mut self) -> ParserResult<Expr<'a>> {
         match self.peek().ttype {
             TokenType::False | TokenType::True | TokenType::Nil => {
-                let token = self.advance();
-                Ok(Expr::Literal(
-                    Ctx::from_token(token),
-                    self.previous().clone(),
-                ))
+                let token = self.advance().clone();
+                Ok(Expr::Literal(self.ctx(&token), token))
             }
             TokenType::Number(_) => {
-                let token = self.advance();
-                Ok(Expr::Literal(
-                    Ctx::from_token(token),
-                    self.previous().clone(),
-                ))
+                let token = self.advance().clone();
+                Ok(Expr::Literal(self.ctx(&token), token))
             }
             TokenType::String(_) => {
-                let token = self.advance();
-                Ok(Expr::Literal(
-                    Ctx::from_token(token),
-                    self.previous().clone(),
-                ))
+                let token = self.advance().clone();
+                Ok(Expr::Literal(self.ctx(&token), token))
             }
             TokenType::LeftParen => {
                 let token = self.advance().clone();
 
                 match self.expr() {
                     Ok(expr) => {
-                        if self.match_ttokens(&vec![&TokenType::RightParen]) {
-                            Ok(Expr::Grouping(Ctx::from_token(&token), Box::new(expr)))
+                        if self.match_ttokens(&[&TokenType::RightParen]) {
+                            Ok(Expr::Grouping(
+                                self.ctx_since(&self.ctx(&token)),
+                                Box::new(expr),
+                            ))
                         } else {
-                            Err(Parser::make_parse_error_message(
-                                &self,
-                                self.peek(),
-                                "Expect ')' after expression",
+                            Err(UvlError::new(
+                                ErrorKind::Parser,
+                                Parser::make_parse_error_message(
+                                    self,
+                                    self.peek(),
+                                    "Expect ')' after expression",
+                                )
+                                .with_secondary_label(token.span, "unclosed delimiter".to_string()),
                             ))
                         }
                     }
@@ -346,18 +903,58 @@ impl<'a> Parser<'a> {
                 }
             }
             TokenType::Identifier => {
-                let token = self.advance();
-                Ok(Expr::Variable(Ctx::from_token(token), token.clone()))
+                let token = self.advance().clone();
+                Ok(Expr::Variable(self.ctx(&token), token, Binding::Global))
             }
-            _ => Err(Parser::make_parse_error_message(
-                &self,
-                self.peek(),
-                "Expect expression",
+            _ => Err(UvlError::new(
+                ErrorKind::Parser,
+                Parser::make_parse_error_message(self, self.peek(), "Expect expression"),
             )),
         }
     }
 
-    fn match_ttokens(&mut self, ttypes: &[&'a TokenType<'a>]) -> bool {
+    /// Runs `parse` one nesting level deeper, failing once the nesting
+    /// exceeds `max_depth` instead of overflowing the native stack.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> ParserResult<T>) -> ParserResult<T> {
+        let depth = self.state.depth;
+        self.link()?;
+        let result = parse(self);
+        // Also undoes the links of the chains `parse` went through
+        self.state.depth = depth;
+        result
+    }
+
+    /// Counts one more level of nesting. Chains such as `a + b + c` or
+    /// `f()()` nest as deeply as they are long although they are parsed in
+    /// a loop, so each link counts.
+    fn link(&mut self) -> ParserResult<()> {
+        if self.state.depth >= self.max_depth {
+            return Err(nesting_error(&self.ctx(self.peek()), self.max_depth));
+        }
+        self.state.depth += 1;
+        Ok(())
+    }
+
+    fn consume(&mut self, ttype: &TokenType, message: &str) -> ParserResult<Token<'a>> {
+        if self.check(ttype) {
+            Ok(self.advance().clone())
+        } else {
+            Err(self.error(self.peek(), message, None))
+        }
+    }
+
+    /// A syntax error at `token`. `opening` is the unclosed delimiter, if
+    /// the error is a missing closing one.
+    fn error(&self, token: &Token<'a>, message: &str, opening: Option<&Token<'a>>) -> UvlError {
+        let mut diagnostic = Parser::make_parse_error_message(self, token, message);
+        if let Some(opening) = opening {
+            diagnostic =
+                diagnostic.with_secondary_label(opening.span, "unclosed delimiter".to_string());
+        }
+        UvlError::new(ErrorKind::Parser, diagnostic)
+    }
+
+    fn match_ttokens(&mut self, ttypes: &[&TokenType]) -> bool {
         for ttype in ttypes {
             if self.check(ttype) {
                 self.advance();
@@ -365,10 +962,10 @@ impl<'a> Parser<'a> {
             }
         }
 
-        return false;
+        false
     }
 
-    fn check(&self, ttype: &'a TokenType) -> bool {
+    fn check(&self, ttype: &TokenType) -> bool {
         if self.is_at_end() {
             return false;
         }
@@ -396,47 +993,236 @@ impl<'a> Parser<'a> {
         &self.tokens[self.state.current]
     }
 
-    fn synchronize(&mut self) {
-        self.advance();
+    /// Skips tokens until the start of the next statement, which may be the
+    /// current token when the failed statement, begun at `start`, is only
+    /// missing its ';'. A '}' is left in place so the enclosing block can
+    /// still be closed.
+    fn synchronize(&mut self, start: usize) {
+        // A statement that failed on its first token would fail there again
+        let stuck = self.state.current == start;
+        if !self.check(&TokenType::RightBrace) && (stuck || !self.starts_statement()) {
+            self.advance();
+        }
 
         while !self.is_at_end() {
-            match self.previous().ttype {
-                TokenType::Semicolon => {
-                    return;
-                }
-                _ => (),
+            if self.previous().ttype == TokenType::Semicolon
+                || self.check(&TokenType::RightBrace)
+                || self.starts_statement()
+            {
+                return;
             }
+            self.advance();
+        }
+    }
 
-            match self.peek().ttype {
-                TokenType::Class
+    /// Whether the current token is a keyword that begins a statement.
+    fn starts_statement(&self) -> bool {
+        matches!(
+            self.peek().ttype,
+            TokenType::Class
                 | TokenType::Fun
+                | TokenType::Struct
+                | TokenType::Trait
+                | TokenType::Impl
+                | TokenType::Import
+                | TokenType::Use
+                | TokenType::Pub
                 | TokenType::Let
+                | TokenType::Const
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
                 | TokenType::PrintLn
-                | TokenType::Return => {
-                    return;
-                }
-                _ => {
-                    self.advance();
-                }
-            }
-        }
+                | TokenType::Return
+        )
     }
 
-    fn make_parse_error_message(parser: &Parser<'a>, token: &Token<'a>, message: &str) -> String {
+    fn make_parse_error_message(
+        parser: &Parser<'t, 'a>,
+        token: &Token<'a>,
+        message: &str,
+    ) -> Diagnostic {
+        let ctx = parser.ctx(token);
         match token.ttype {
-            TokenType::Eof => {
-                format!(
-                    "File \"<{}>\", line {}, in <root>\n\tError: {} at end",
-                    parser.source_name, token.line, message
-                )
-            }
-            _ => format!(
-                "File \"<{}>\", line {}, in <root>\n\tError at '{}': {}",
-                parser.source_name, token.line, token.lexeme, message
-            ),
+            TokenType::Eof => Diagnostic::error(&ctx, message.to_string())
+                .with_label("unexpected end of file".to_string()),
+            _ => Diagnostic::error(&ctx, message.to_string())
+                .with_label(format!("unexpected '{}'", token.lexeme)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn parse_errors(source: &str) -> Vec<String> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.scan().unwrap().clone();
+        let mut parser = Parser::new(&tokens, "main.uvl", false);
+        match parser.parse() {
+            Ok(_) => vec![],
+            Err(errors) => errors
+                .iter()
+                .map(|e| format!("{}:{} {}", e.ctx().line, e.ctx().column, e.message()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_valid_program() {
+        assert!(parse_errors("let a = 1;\n{ let b = a + 2; println b; }").is_empty());
+    }
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let source = "let = 1;\nlet b = (2 + 3;\nprintln b;\nlet c = * 4;\nlet d = 1\nlet e = ;\n";
+
+        assert_eq!(
+            parse_errors(source),
+            vec![
+                "1:5 Expect identifier after let",
+                "2:15 Expect ')' after expression",
+                "4:9 Expect expression",
+                "6:1 Expect ';' after expression",
+                "6:9 Expect expression",
+            ]
+        );
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let source = "{\n  let a = ;\n  let b = 1\n}\nlet c = 2 +;\n}";
+
+        assert_eq!(
+            parse_errors(source),
+            vec![
+                "2:11 Expect expression",
+                "4:1 Expect ';' after expression",
+                "5:12 Expect expression",
+                "6:1 Expect expression",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_functions_and_calls() {
+        let source = "fun add(a, b) {\n  if a > b { return a; } else { return a + b; }\n}\nprintln add(1, 2);";
+        assert!(parse_errors(source).is_empty());
+    }
+
+    #[test]
+    fn parse_type_annotations() {
+        let source = "let mut n: Number = 5;\n\
+                      fun apply(f: fun(Number) -> Number, x) -> Number { return f(x); }";
+        assert!(parse_errors(source).is_empty());
+
+        assert_eq!(
+            parse_errors("let n: = 1;\nlet g: fun(Number) Number = n;"),
+            vec![
+                "1:8 Expect a type",
+                "2:20 Expect '->' after parameter types"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_traits_and_impls() {
+        let source = "trait Display {\n  fun fmt(self) -> String;\n  fun show(self) { println self.fmt(); }\n}\n\
+                      impl Display for Number {\n  fun fmt(self) -> String { return \"n\"; }\n}\n\
+                      1.show();";
+        assert!(parse_errors(source).is_empty());
+
+        assert_eq!(
+            parse_errors("trait T { fun f(x); }\nimpl T for Number { fun f(self) }\nx.1;"),
+            vec![
+                "1:17 Expect 'self' as the first parameter of a method",
+                "2:33 Expect '{' before method body",
+                "3:3 Expect name after '.'",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_structs() {
+        let source = "struct Empty {}\npub struct Point { x: Number, y, }\nlet p = Point(1, 2).x;";
+        assert!(parse_errors(source).is_empty());
+
+        assert_eq!(
+            parse_errors("struct;\nstruct P { 1 }\nfun f() { struct Q; }"),
+            vec![
+                "1:7 Expect struct name",
+                "2:12 Expect field name",
+                "3:11 'struct' is only allowed at the top level of a module",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_modules() {
+        let source = "import \"lib/vec.uvl\" as vec;\nuse vec::{add, ZERO};\nuse vec::sub;\n\
+                      pub fun f() { return vec.add(vec.ZERO, 1); }";
+        assert!(parse_errors(source).is_empty());
+
+        assert_eq!(
+            parse_errors("import vec;\nfun f() { use vec::a; }\npub 1;"),
+            vec![
+                "1:8 Expect module path after 'import'",
+                "2:11 'use' is only allowed at the top level of a module",
+                "3:5 Expect 'let', 'const', 'fun' or 'struct' after 'pub'",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_index() {
+        let source = "a[1][b + 1];";
+        assert!(parse_errors(source).is_empty());
+        assert_eq!(parse_errors("a[1;"), vec!["1:4 Expect ']' after index"]);
+    }
+
+    #[test]
+    fn return_outside_function() {
+        assert_eq!(
+            parse_errors("return 1;\nfun f() { return; }\nf(1;"),
+            vec![
+                "1:1 Can't return from top-level code",
+                "3:4 Expect ')' after arguments"
+            ]
+        );
+    }
+
+    #[test]
+    fn deep_nesting_raises_recursion_error() {
+        let source = format!(
+            "let a = {}1{};\n{{{{{{ let b = -(1); }}}}}}",
+            "(".repeat(20),
+            ")".repeat(20)
+        );
+        let mut lexer = Lexer::new(&source);
+        let tokens = lexer.scan().unwrap().clone();
+        let errors = Parser::new(&tokens, "main.uvl", false)
+            .with_max_depth(8)
+            .parse()
+            .unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::Recursion);
+        assert_eq!((errors[0].ctx().line, errors[0].ctx().column), (1, 16));
+
+        // Chains nest as deeply as they are long
+        let source = format!("let c = 1{};\nf(){};", " + 1".repeat(20), "()".repeat(20));
+        let mut lexer = Lexer::new(&source);
+        let tokens = lexer.scan().unwrap().clone();
+        let errors = Parser::new(&tokens, "main.uvl", false)
+            .with_max_depth(8)
+            .parse()
+            .unwrap_err();
+        let locations = errors
+            .iter()
+            .map(|e| (e.ctx().line, e.ctx().column))
+            .collect::<Vec<(usize, usize)>>();
+        assert_eq!(locations, vec![(1, 37), (2, 14)]);
+    }
+}
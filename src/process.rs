@@ -0,0 +1,48 @@
+use std::io::Write;
+
+use crate::native::{function, number};
+use crate::value::UvlValue;
+
+/// The members of the `process` module.
+pub fn members() -> Vec<(&'static str, UvlValue)> {
+    vec![(
+        "exit",
+        function("exit", Some(1), |args| {
+            let code = number("exit", args, 0)?;
+            // The shell only sees the low 8 bits of the code
+            if code.fract() != 0.0 || !(0.0..=255.0).contains(&code) {
+                return Err(format!(
+                    "Exit code must be a whole number from 0 to 255, found {}",
+                    code
+                ));
+            }
+            // Output printed so far must not be lost
+            let _ = std::io::stdout().flush();
+            std::process::exit(code as i32)
+        }),
+    )]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native::call_member;
+
+    #[test]
+    fn rejects_exit_codes_the_shell_cannot_see() {
+        let exit = |code: f64| call_member(&members(), "exit", &[UvlValue::Number(code)]);
+        for code in [-1.0, 256.0, 1.5, f64::NAN] {
+            assert_eq!(
+                exit(code),
+                Err(format!(
+                    "Exit code must be a whole number from 0 to 255, found {}",
+                    code
+                ))
+            );
+        }
+        assert_eq!(
+            call_member(&members(), "exit", &[UvlValue::String("1".to_string())]),
+            Err("Argument 1 of 'exit' must be a Number, found \"1\" of type String".to_string())
+        );
+    }
+}
@@ -0,0 +1,890 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::{Binding, Ctx, Expr, Mutable, Param, Stmt};
+use crate::common::Depth;
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
+use crate::parser::DEFAULT_MAX_DEPTH;
+use crate::token::{Span, Token};
+use crate::value::{UvlResult, UvlValue};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Let,
+    LetMut,
+    Const,
+    Function,
+    Struct,
+    Parameter,
+    Import,
+}
+
+/// How a name is used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// A name declared in a scope. Constants carry their folded value.
+#[derive(Debug, Clone)]
+struct Declaration {
+    slot: usize,
+    ctx: Ctx,
+    kind: Kind,
+    defined: bool,
+    used: bool,
+    reassigned: bool,
+    value: Option<UvlValue>,
+}
+
+impl Declaration {
+    fn new(slot: usize, ctx: Ctx, kind: Kind) -> Self {
+        Declaration {
+            slot,
+            ctx,
+            kind,
+            defined: false,
+            used: false,
+            reassigned: false,
+            value: None,
+        }
+    }
+}
+
+/// The names of one block or function body. `pending` holds every name
+/// declared directly in the scope, so uses can be checked against
+/// declarations further down.
+struct Scope {
+    declarations: HashMap<String, Declaration>,
+    pending: HashMap<String, Declaration>,
+    next_slot: usize,
+    is_function: bool,
+}
+
+impl Scope {
+    fn new(stmts: &[Stmt], params: &[(&str, Ctx)], is_function: bool) -> Self {
+        let params = params
+            .iter()
+            .map(|(param, ctx)| (*param, ctx.clone(), Kind::Parameter));
+        let names = stmts.iter().flat_map(|stmt| {
+            let names = match stmt.declaration() {
+                Stmt::Let(_, name, Mutable(true), ..) => vec![(name, Kind::LetMut)],
+                Stmt::Let(_, name, Mutable(false), ..) => vec![(name, Kind::Let)],
+                Stmt::Const(_, name, ..) => vec![(name, Kind::Const)],
+                Stmt::Function(_, name, ..) => vec![(name, Kind::Function)],
+                Stmt::Struct(_, name, ..) => vec![(name, Kind::Struct)],
+                Stmt::Import(_, _, name) => vec![(name, Kind::Import)],
+                Stmt::Use(_, _, names) => names.iter().map(|name| (name, Kind::Import)).collect(),
+                _ => vec![],
+            };
+            names
+                .into_iter()
+                .map(|(name, kind)| (name.lexeme, Ctx::from_token(&stmt.ctx().file, name), kind))
+        });
+
+        let mut pending = HashMap::new();
+        for (slot, (name, ctx, kind)) in params.chain(names).enumerate() {
+            pending
+                .entry(name.to_string())
+                .or_insert(Declaration::new(slot, ctx, kind));
+        }
+
+        Scope {
+            declarations: HashMap::new(),
+            pending,
+            next_slot: 0,
+            is_function,
+        }
+    }
+}
+
+/// A member of a global, as in `shapes.area` or `use shapes::{area}`. When
+/// the global is an imported module the member is checked against its
+/// exports before the module runs.
+#[derive(Debug, Clone)]
+pub struct MemberUse {
+    pub ctx: Ctx,
+    pub global: String,
+    pub member: String,
+}
+
+/// Resolves every variable to the scope and slot it lives in before the
+/// program runs, reporting names used before their declaration, used in
+/// their own initializer or declared twice in the same scope. Constants
+/// are evaluated here and folded into the expressions that use them.
+///
+/// Bindings that are never read, `let mut` bindings that are never
+/// reassigned, bindings that shadow an outer one and unreachable code are
+/// reported as warnings, unless the name starts with `_`.
+///
+/// The outermost scope holds the globals. They are looked up by name at
+/// runtime, so a function can use a global declared after it. Exported
+/// globals are used by other modules, and in prompt mode by later lines, so
+/// neither is reported.
+pub struct Resolver {
+    scopes: Vec<Scope>,
+    errors: Vec<UvlError>,
+    warnings: Vec<Diagnostic>,
+    members: Vec<MemberUse>,
+    depth: Depth,
+    prompt_mode: bool,
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            scopes: vec![],
+            errors: vec![],
+            warnings: vec![],
+            members: vec![],
+            depth: Depth::new(DEFAULT_MAX_DEPTH),
+            prompt_mode: false,
+        }
+    }
+
+    pub fn with_prompt_mode(mut self, prompt_mode: bool) -> Self {
+        self.prompt_mode = prompt_mode;
+        self
+    }
+
+    /// Sets how deeply statements and expressions may nest before resolving
+    /// fails with a RecursionError.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.depth = Depth::new(max_depth);
+        self
+    }
+
+    pub fn resolve(&mut self, stmts: &mut [Stmt]) -> Result<(), Vec<UvlError>> {
+        self.scopes.push(Scope::new(stmts, &[], false));
+        self.resolve_stmts(stmts);
+        if self.prompt_mode {
+            self.scopes.pop();
+        } else {
+            let globals = &mut self.scopes[0].declarations;
+            for stmt in stmts.iter() {
+                if let Stmt::Pub(_, declaration) = stmt {
+                    if let Stmt::Let(_, name, ..) = declaration.as_ref() {
+                        globals.remove(name.lexeme);
+                    }
+                }
+            }
+            self.end_scope();
+        }
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    /// Warnings found by the last call to [`Resolver::resolve`], in
+    /// source order.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.sort_by_key(|warning| warning.ctx.span.start);
+        warnings
+    }
+
+    /// The members of globals used by the last call to
+    /// [`Resolver::resolve`].
+    pub fn take_members(&mut self) -> Vec<MemberUse> {
+        std::mem::take(&mut self.members)
+    }
+
+    fn resolve_stmts(&mut self, stmts: &mut [Stmt]) {
+        let mut diverged_at: Option<Span> = None;
+        let mut reported = false;
+        for stmt in stmts {
+            if let (Some(span), false) = (diverged_at, reported) {
+                self.warnings.push(
+                    Diagnostic::warning(stmt.ctx(), "Unreachable statement".to_string())
+                        .with_label("unreachable statement".to_string())
+                        .with_secondary_label(
+                            span,
+                            "any code following this statement is unreachable".to_string(),
+                        ),
+                );
+                reported = true;
+            }
+
+            self.resolve_stmt(stmt);
+            if diverged_at.is_none() && stmt.diverges() {
+                diverged_at = Some(stmt.ctx().span);
+            }
+        }
+    }
+
+    /// Leaves the innermost scope, warning about its unused bindings.
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().expect("scopes are balanced");
+        for (name, declaration) in scope.declarations {
+            // Methods take `self` whether they use it or not
+            if name.starts_with('_') || name == "self" {
+                continue;
+            }
+
+            let help = format!(
+                "if this is intentional, prefix it with an underscore: `_{}`",
+                name
+            );
+            if !declaration.used
+                && matches!(declaration.kind, Kind::Let | Kind::LetMut | Kind::Parameter)
+            {
+                self.warnings.push(
+                    Diagnostic::warning(&declaration.ctx, format!("Unused variable '{}'", name))
+                        .with_help(help),
+                );
+            }
+            if !declaration.reassigned && declaration.kind == Kind::LetMut {
+                self.warnings.push(
+                    Diagnostic::warning(
+                        &declaration.ctx,
+                        format!("Variable '{}' does not need to be mutable", name),
+                    )
+                    .with_label("never reassigned".to_string())
+                    .with_help("remove the `mut`".to_string()),
+                );
+            }
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) {
+        if self.depth.enter(stmt.ctx(), &mut self.errors) {
+            self.resolve_nested_stmt(stmt);
+        }
+        self.depth.leave();
+    }
+
+    fn resolve_nested_stmt(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Expression(_, expr) | Stmt::PrintLn(_, expr) => self.resolve_expr(expr),
+            Stmt::Let(ctx, name, is_mutable, _, expr, _) => {
+                let ctx = Ctx::from_token(&ctx.file, name);
+                let kind = if is_mutable.0 {
+                    Kind::LetMut
+                } else {
+                    Kind::Let
+                };
+                self.declare(&ctx, name.lexeme, kind);
+                self.resolve_expr(expr);
+                self.define(name.lexeme, None);
+            }
+            Stmt::Const(ctx, name, expr, _) => {
+                let ctx = Ctx::from_token(&ctx.file, name);
+                if name.lexeme != name.lexeme.to_uppercase() {
+                    self.warnings.push(
+                        Diagnostic::warning(
+                            &ctx,
+                            format!("Constant '{}' should have an upper case name", name.lexeme),
+                        )
+                        .with_help(format!(
+                            "convert the name to upper case: `{}`",
+                            name.lexeme.to_uppercase()
+                        )),
+                    );
+                }
+
+                self.declare(&ctx, name.lexeme, Kind::Const);
+                let errors = self.errors.len();
+                self.resolve_expr(expr);
+                let value = match Resolver::evaluate(name.lexeme, expr) {
+                    Ok(value) => {
+                        **expr = Expr::Constant(expr.ctx().clone(), value.clone());
+                        Some(value)
+                    }
+                    // Names that failed to resolve were reported already
+                    Err(_) if self.errors.len() > errors => None,
+                    Err(e) => {
+                        self.errors.push(e);
+                        None
+                    }
+                };
+                self.define(name.lexeme, value);
+            }
+            Stmt::Block(_, stmts) => {
+                self.scopes.push(Scope::new(stmts, &[], false));
+                self.resolve_stmts(stmts);
+                self.end_scope();
+            }
+            Stmt::If(_, condition, then_branch, else_branch) => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_stmt(else_branch);
+                }
+            }
+            Stmt::Function(ctx, name, params, _, body, _) => {
+                self.declare(
+                    &Ctx::from_token(&ctx.file, name),
+                    name.lexeme,
+                    Kind::Function,
+                );
+                self.define(name.lexeme, None);
+                self.resolve_function(&ctx.file, params, body);
+            }
+            Stmt::Struct(ctx, name, fields, _) => {
+                let mut declared = HashMap::new();
+                for field in fields.iter() {
+                    let field_ctx = Ctx::from_token(&ctx.file, &field.name);
+                    if let Some(previous) = declared.insert(field.name.lexeme, field_ctx.span) {
+                        self.errors.push(UvlError::new(
+                            ErrorKind::Name,
+                            Diagnostic::error(
+                                &field_ctx,
+                                format!(
+                                    "Field '{}' is already declared in struct '{}'",
+                                    field.name.lexeme, name.lexeme
+                                ),
+                            )
+                            .with_label("redeclared here".to_string())
+                            .with_secondary_label(previous, "first declared here".to_string()),
+                        ));
+                    }
+                }
+                self.declare(&Ctx::from_token(&ctx.file, name), name.lexeme, Kind::Struct);
+                self.define(name.lexeme, None);
+            }
+            // Traits and impls are not bindings, only their methods' bodies
+            // need resolving
+            Stmt::Trait(ctx, _, methods, _) => {
+                for method in methods {
+                    if let Some(body) = &mut method.body {
+                        self.resolve_function(&ctx.file, &method.params, body);
+                    }
+                }
+            }
+            Stmt::Impl(_, _, _, methods) => {
+                for method in methods {
+                    if let Stmt::Function(ctx, _, params, _, body, _) = method {
+                        self.resolve_function(&ctx.file, params, body);
+                    }
+                }
+            }
+            Stmt::Return(_, expr) => {
+                if let Some(expr) = expr {
+                    self.resolve_expr(expr);
+                }
+            }
+            Stmt::Import(ctx, _, name) => {
+                self.declare(&Ctx::from_token(&ctx.file, name), name.lexeme, Kind::Import);
+                self.define(name.lexeme, None);
+            }
+            Stmt::Use(ctx, module, names) => {
+                let (binding, _) = self.lookup(
+                    &Ctx::from_token(&ctx.file, module),
+                    module.lexeme,
+                    Access::Read,
+                );
+                for name in names {
+                    if binding == Binding::Global {
+                        self.use_member(&Ctx::from_token(&ctx.file, name), module.lexeme, name);
+                    }
+                    self.declare(&Ctx::from_token(&ctx.file, name), name.lexeme, Kind::Import);
+                    self.define(name.lexeme, None);
+                }
+            }
+            Stmt::Pub(_, stmt) => self.resolve_stmt(stmt),
+        }
+    }
+
+    fn resolve_function(&mut self, file: &str, params: &[Param], body: &mut Rc<Vec<Stmt>>) {
+        let body = Rc::get_mut(body).expect("function bodies are resolved before they run");
+        let names = params
+            .iter()
+            .map(|param| (param.name.lexeme, Ctx::from_token(file, &param.name)))
+            .collect::<Vec<(&str, Ctx)>>();
+        self.scopes.push(Scope::new(body, &names, true));
+        for param in params {
+            self.declare(
+                &Ctx::from_token(file, &param.name),
+                param.name.lexeme,
+                Kind::Parameter,
+            );
+            self.define(param.name.lexeme, None);
+        }
+        self.resolve_stmts(body);
+        self.end_scope();
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        if self.depth.enter(expr.ctx(), &mut self.errors) {
+            self.resolve_nested_expr(expr);
+        }
+        self.depth.leave();
+    }
+
+    fn resolve_nested_expr(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Binary(_, left, _, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Grouping(_, expr) | Expr::Unary(_, _, expr) => self.resolve_expr(expr),
+            Expr::Literal(..) | Expr::Constant(..) => {}
+            Expr::Variable(ctx, name, binding) => {
+                let (resolved, declaration) = self.lookup(ctx, name.lexeme, Access::Read);
+                *binding = resolved;
+                if let Some(value) = declaration.and_then(|declaration| declaration.value) {
+                    let ctx = ctx.clone();
+                    *expr = Expr::Constant(ctx, value);
+                }
+            }
+            Expr::Assign(ctx, name, binding, expr) => {
+                self.resolve_expr(expr);
+                let (resolved, declaration) = self.lookup(ctx, name.lexeme, Access::Write);
+                if let Some(declaration) = declaration {
+                    self.check_mutable(ctx, name.lexeme, &declaration);
+                }
+                *binding = resolved;
+            }
+            Expr::Call(_, callee, args) => {
+                self.resolve_expr(callee);
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            // Functions exported by a module are called like methods
+            Expr::MethodCall(ctx, receiver, name, args) => {
+                self.resolve_expr(receiver);
+                if let Expr::Variable(_, global, Binding::Global) = receiver.as_ref() {
+                    self.use_member(&Ctx::from_token(&ctx.file, name), global.lexeme, name);
+                }
+                for arg in args {
+                    self.resolve_expr(arg);
+                }
+            }
+            Expr::Get(ctx, object, name) => {
+                self.resolve_expr(object);
+                if let Expr::Variable(_, global, Binding::Global) = object.as_ref() {
+                    self.use_member(&Ctx::from_token(&ctx.file, name), global.lexeme, name);
+                }
+            }
+            Expr::Index(_, target, index) => {
+                self.resolve_expr(target);
+                self.resolve_expr(index);
+            }
+        }
+    }
+
+    fn use_member(&mut self, ctx: &Ctx, global: &str, member: &Token) {
+        self.members.push(MemberUse {
+            ctx: ctx.clone(),
+            global: global.to_string(),
+            member: member.lexeme.to_string(),
+        });
+    }
+
+    fn declare(&mut self, ctx: &Ctx, name: &str, kind: Kind) {
+        let (innermost, enclosing) = self
+            .scopes
+            .split_last_mut()
+            .expect("there is always a global scope");
+        if !enclosing.is_empty() && !name.starts_with('_') {
+            if let Some(outer) = enclosing
+                .iter()
+                .rev()
+                .find_map(|scope| scope.declarations.get(name))
+            {
+                self.warnings.push(
+                    Diagnostic::warning(
+                        ctx,
+                        format!("Name '{}' shadows a binding in an outer scope", name),
+                    )
+                    .with_label("shadows the outer binding".to_string())
+                    .with_secondary_label(outer.ctx.span, "previously declared here".to_string()),
+                );
+            }
+        }
+
+        let scope = innermost;
+        let slot = scope.next_slot;
+        scope.next_slot += 1;
+
+        if let Some(previous) = scope.declarations.get(name) {
+            let error = UvlError::new(
+                ErrorKind::Name,
+                Diagnostic::error(
+                    ctx,
+                    format!("Name '{}' is already declared in this scope", name),
+                )
+                .with_label("redeclared here".to_string())
+                .with_secondary_label(previous.ctx.span, "first declared here".to_string()),
+            );
+            self.errors.push(error);
+            return;
+        }
+
+        // Uses from functions declared earlier were recorded on the
+        // pending declaration
+        let mut declaration = Declaration::new(slot, ctx.clone(), kind);
+        if let Some(pending) = scope.pending.get(name) {
+            declaration.used = pending.used;
+            declaration.reassigned = pending.reassigned;
+        }
+        scope.declarations.insert(name.to_string(), declaration);
+    }
+
+    fn define(&mut self, name: &str, value: Option<UvlValue>) {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("there is always a global scope");
+        if let Some(declaration) = scope.declarations.get_mut(name) {
+            declaration.defined = true;
+            declaration.value = value;
+        }
+    }
+
+    /// Evaluates the initializer of the constant `name`. Only literals,
+    /// operators and other constants can be evaluated at compile time.
+    fn evaluate(name: &str, expr: &Expr) -> UvlResult {
+        match expr {
+            Expr::Literal(_, token) => Ok(UvlValue::from_literal(token)),
+            Expr::Constant(_, value) => Ok(value.clone()),
+            Expr::Grouping(_, expr) => Resolver::evaluate(name, expr),
+            Expr::Unary(ctx, op, expr) => Resolver::evaluate(name, expr)?
+                .apply_operator(ctx, &op.ttype, None)
+                .map_err(|e| UvlError::new(ErrorKind::Const, e.diagnostic().clone())),
+            Expr::Binary(ctx, left, op, right) => {
+                let left = Resolver::evaluate(name, left)?;
+                let right = Resolver::evaluate(name, right)?;
+                left.apply_operator(ctx, &op.ttype, Some(&right))
+                    .map_err(|e| UvlError::new(ErrorKind::Const, e.diagnostic().clone()))
+            }
+            Expr::Variable(ctx, ..)
+            | Expr::Assign(ctx, ..)
+            | Expr::Call(ctx, ..)
+            | Expr::MethodCall(ctx, ..)
+            | Expr::Get(ctx, ..)
+            | Expr::Index(ctx, ..) => Err(UvlError::new(
+                ErrorKind::Const,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Initializer of const '{}' is not a constant expression",
+                        name
+                    ),
+                )
+                .with_label("not known at compile time".to_string())
+                .with_note(
+                    "constants can only use literals, operators and other constants".to_string(),
+                ),
+            )),
+        }
+    }
+
+    /// Rejects an assignment at `ctx` to a binding that is not `let mut`.
+    fn check_mutable(&mut self, ctx: &Ctx, name: &str, declaration: &Declaration) {
+        let (message, label) = match declaration.kind {
+            Kind::LetMut => return,
+            Kind::Let => (
+                format!("Name '{}' is immutable", name),
+                format!("first assignment to '{}'", name),
+            ),
+            Kind::Const => {
+                self.errors.push(UvlError::new(
+                    ErrorKind::Const,
+                    Diagnostic::error(ctx, format!("Cannot assign to constant '{}'", name))
+                        .with_label("cannot assign to a constant".to_string())
+                        .with_secondary_label(
+                            declaration.ctx.span,
+                            format!("'{}' is declared as a constant here", name),
+                        ),
+                ));
+                return;
+            }
+            Kind::Function => (
+                format!("Name '{}' is immutable", name),
+                format!("'{}' is declared as a function here", name),
+            ),
+            Kind::Struct => (
+                format!("Name '{}' is immutable", name),
+                format!("'{}' is declared as a struct here", name),
+            ),
+            Kind::Parameter => (
+                format!("Name '{}' is immutable", name),
+                format!("'{}' is a parameter", name),
+            ),
+            Kind::Import => (
+                format!("Name '{}' is immutable", name),
+                format!("'{}' is imported here", name),
+            ),
+        };
+
+        let mut diagnostic = Diagnostic::error(ctx, message)
+            .with_label("cannot assign to an immutable binding".to_string())
+            .with_secondary_label(declaration.ctx.span, label);
+        if declaration.kind == Kind::Let {
+            diagnostic = diagnostic.with_help(format!("consider `let mut {}`", name));
+        }
+        self.errors.push(UvlError::new(ErrorKind::Name, diagnostic));
+    }
+
+    /// Finds the binding `name` refers to at `ctx`, and its declaration if
+    /// it is declared in the source being resolved. A name declared further
+    /// down an enclosing scope can only be used from a function nested in
+    /// that scope, which cannot run before the declaration does.
+    fn lookup(&mut self, ctx: &Ctx, name: &str, access: Access) -> (Binding, Option<Declaration>) {
+        let mut later_declaration = None;
+        let mut found = None;
+        for (index, scope) in self.scopes.iter().enumerate().rev() {
+            if scope.declarations.contains_key(name) {
+                found = Some((index, false));
+                break;
+            }
+
+            if let Some(declaration) = scope.pending.get(name) {
+                if self.scopes[index + 1..]
+                    .iter()
+                    .any(|scope| scope.is_function)
+                {
+                    found = Some((index, true));
+                    break;
+                }
+                later_declaration.get_or_insert(declaration.ctx.span);
+            }
+        }
+
+        let Some((index, is_pending)) = found else {
+            if let Some(span) = later_declaration {
+                self.errors.push(UvlError::new(
+                    ErrorKind::Name,
+                    Diagnostic::error(
+                        ctx,
+                        format!("Name '{}' is used before its declaration", name),
+                    )
+                    .with_label("used here".to_string())
+                    .with_secondary_label(span, "declared here".to_string()),
+                ));
+            }
+            return (Binding::Global, None);
+        };
+
+        let innermost = self.scopes.len() - 1;
+        let scope = &mut self.scopes[index];
+        let declaration = if is_pending {
+            scope.pending.get_mut(name)
+        } else {
+            scope.declarations.get_mut(name)
+        }
+        .expect("the declaration was found above");
+        match access {
+            Access::Read => declaration.used = true,
+            Access::Write => declaration.reassigned = true,
+        }
+        let declaration = declaration.clone();
+
+        if !is_pending && !declaration.defined {
+            self.errors.push(UvlError::new(
+                ErrorKind::Name,
+                Diagnostic::error(
+                    ctx,
+                    format!("Name '{}' is used in its own initializer", name),
+                )
+                .with_label("the binding is not initialized yet".to_string())
+                .with_secondary_label(declaration.ctx.span, "declared here".to_string()),
+            ));
+        }
+
+        let binding = if index == 0 {
+            Binding::Global
+        } else {
+            Binding::Local(innermost - index, declaration.slot)
+        };
+        (binding, Some(declaration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn resolve(source: &str) -> Result<Vec<Stmt<'_>>, Vec<String>> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.scan().unwrap().clone();
+        let mut stmts = Parser::new(&tokens, "main.uvl", false).parse().unwrap();
+        match Resolver::new().resolve(&mut stmts) {
+            Ok(()) => Ok(stmts),
+            Err(errors) => Err(errors
+                .iter()
+                .map(|e| format!("{}:{} {}", e.ctx().line, e.ctx().column, e.message()))
+                .collect()),
+        }
+    }
+
+    #[test]
+    fn resolves_depth_and_slot() {
+        let stmts = resolve("let g = 1;\n{ let a = 1; let b = 2; { println b + g; } }").unwrap();
+
+        let Stmt::Block(_, outer) = &stmts[1] else {
+            panic!("expected a block")
+        };
+        let Stmt::Block(_, inner) = &outer[2] else {
+            panic!("expected a block")
+        };
+        let Stmt::PrintLn(_, expr) = &inner[0] else {
+            panic!("expected println")
+        };
+        let Expr::Binary(_, b, _, g) = expr.as_ref() else {
+            panic!("expected a binary expression")
+        };
+        assert!(matches!(**b, Expr::Variable(_, _, Binding::Local(1, 1))));
+        assert!(matches!(**g, Expr::Variable(_, _, Binding::Global)));
+    }
+
+    #[test]
+    fn deep_nesting_raises_recursion_error() {
+        let source = format!("let a = {}1{};", "-(".repeat(10), ")".repeat(10));
+        let mut lexer = Lexer::new(&source);
+        let tokens = lexer.scan().unwrap().clone();
+        let mut stmts = Parser::new(&tokens, "main.uvl", false).parse().unwrap();
+        let errors = Resolver::new()
+            .with_max_depth(8)
+            .resolve(&mut stmts)
+            .unwrap_err();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::Recursion);
+    }
+
+    #[test]
+    fn reports_scope_errors() {
+        let source =
+            "println a;\nlet a = 1;\n{ let b = b; }\nfun f(x, x) { let y = 1; let y = 2; }";
+
+        assert_eq!(
+            resolve(source).unwrap_err(),
+            vec![
+                "1:9 Name 'a' is used before its declaration",
+                "3:11 Name 'b' is used in its own initializer",
+                "4:10 Name 'x' is already declared in this scope",
+                "4:30 Name 'y' is already declared in this scope",
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_may_use_later_declarations() {
+        let source = "fun ping(n) { return pong(n); }\nfun pong(n) { return n; }\n\
+                      { let x = 1; { println x; let x = 2; } }";
+        assert!(resolve(source).is_ok());
+    }
+
+    #[test]
+    fn rejects_assignment_to_immutable_bindings() {
+        let source = "let n = 1;\nlet mut m = 1;\nm = 2;\nfun f(p) { p = 1; n = 2; }\nf = 3;";
+
+        assert_eq!(
+            resolve(source).unwrap_err(),
+            vec![
+                "4:12 Name 'p' is immutable",
+                "4:19 Name 'n' is immutable",
+                "5:1 Name 'f' is immutable",
+            ]
+        );
+    }
+
+    #[test]
+    fn constants_are_folded() {
+        let stmts = resolve("const A = 2;\nconst B = (A + 1) * -A;\nprintln B;").unwrap();
+
+        let Stmt::PrintLn(_, expr) = &stmts[2] else {
+            panic!("expected println")
+        };
+        assert!(matches!(**expr, Expr::Constant(_, UvlValue::Number(n)) if n == -6.0));
+    }
+
+    #[test]
+    fn reports_constant_errors() {
+        let source = "let x = 1;\nconst A = x + 1;\nconst B = 1;\nB = 2;\nconst C = 1 / 0;";
+
+        assert_eq!(
+            resolve(source).unwrap_err(),
+            vec![
+                "2:11 Initializer of const 'A' is not a constant expression",
+                "4:1 Cannot assign to constant 'B'",
+                "5:11 Division by zero: 1/0",
+            ]
+        );
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        warnings_in_mode(source, false)
+    }
+
+    fn warnings_in_mode(source: &str, prompt_mode: bool) -> Vec<String> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.scan().unwrap().clone();
+        let mut stmts = Parser::new(&tokens, "main.uvl", prompt_mode)
+            .parse()
+            .unwrap();
+        let mut resolver = Resolver::new().with_prompt_mode(prompt_mode);
+        resolver.resolve(&mut stmts).unwrap();
+        resolver
+            .take_warnings()
+            .iter()
+            .map(|w| format!("{}:{} {}", w.ctx.line, w.ctx.column, w.message))
+            .collect()
+    }
+
+    #[test]
+    fn lints_lower_case_constants() {
+        assert_eq!(
+            warnings("const pi = 3.14;\nconst TAU = pi * 2;"),
+            vec!["1:7 Constant 'pi' should have an upper case name"]
+        );
+    }
+
+    #[test]
+    fn warns_about_mutability_shadowing_and_unreachable_code() {
+        let source = "let x = 1;\n\
+                      fun f(a) {\n  \
+                        let mut y = 2;\n  \
+                        let mut z = 1;\n  \
+                        z = a;\n  \
+                        let x = z;\n  \
+                        return y;\n  \
+                        println x;\n\
+                      }";
+
+        assert_eq!(
+            warnings(source),
+            vec![
+                "1:5 Unused variable 'x'",
+                "3:11 Variable 'y' does not need to be mutable",
+                "6:7 Name 'x' shadows a binding in an outer scope",
+                "8:3 Unreachable statement",
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_unused_bindings() {
+        let source = "fun f(a, _b) {\n  let c = 1;\n  let _d = 2;\n  { fun g() { return e; } }\n  let e = 1;\n}";
+
+        assert_eq!(
+            warnings(source),
+            vec!["1:7 Unused variable 'a'", "2:7 Unused variable 'c'"]
+        );
+    }
+
+    #[test]
+    fn warns_about_unused_globals_that_are_not_exported() {
+        let source = "let a = 1;\nlet mut b = 2;\npub let c = 3;\npub let mut d = 4;\nlet e = 5;\nfun f() { return e; }";
+
+        assert_eq!(
+            warnings(source),
+            vec![
+                "1:5 Unused variable 'a'",
+                "2:9 Unused variable 'b'",
+                "2:9 Variable 'b' does not need to be mutable",
+            ]
+        );
+        assert!(warnings_in_mode(source, true).is_empty());
+    }
+}
@@ -5,10 +5,14 @@ pub enum TokenType<'a> {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
     Minus,
     Plus,
+    Colon,
+    ColonColon,
     Semicolon,
     Slash,
     Star,
@@ -22,6 +26,7 @@ pub enum TokenType<'a> {
     GreaterEqual,
     Less,
     LessEqual,
+    Arrow,
 
     // Literals.
     Identifier,
@@ -30,20 +35,27 @@ pub enum TokenType<'a> {
 
     // Keywords.
     And,
+    As,
     Class,
     Else,
     False,
     Fun,
     For,
     If,
+    Impl,
+    Import,
     Nil,
     Mut,
     Or,
     PrintLn,
+    Pub,
     Return,
+    Struct,
     Super,
     This,
+    Trait,
     True,
+    Use,
     Let,
     Const,
     While,
@@ -51,19 +63,54 @@ pub enum TokenType<'a> {
     Eof,
 }
 
+/// Byte range `[start, end)` in the source.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(&self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token<'a> {
     pub ttype: TokenType<'a>,
     pub lexeme: &'a str,
+    /// 1-based line of the first char of the token.
     pub line: usize,
+    /// 1-based column, counted in chars, of the first char of the token.
+    pub column: usize,
+    pub span: Span,
+    /// Lines of the `///` doc comment preceding this token. Only `let`,
+    /// `const`, `fun`, `struct` and `trait` keywords carry documentation,
+    /// also when they follow `pub`.
+    pub doc: Vec<&'a str>,
 }
 
 impl<'a> Token<'a> {
-    pub fn new(ttype: TokenType<'a>, lexeme: &'a str, line: usize) -> Self {
+    pub fn new(
+        ttype: TokenType<'a>,
+        lexeme: &'a str,
+        line: usize,
+        column: usize,
+        span: Span,
+    ) -> Self {
         Token {
             ttype,
             lexeme,
             line,
+            column,
+            span,
+            doc: vec![],
         }
     }
 }
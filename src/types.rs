@@ -0,0 +1,449 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{Ctx, Stmt, TypeExpr};
+use crate::diagnostic::Diagnostic;
+use crate::error::{ErrorKind, UvlError};
+use crate::methods;
+use crate::token::{Span, Token, TokenType};
+use crate::value::{UvlValue, TYPE_NAMES};
+
+/// A static type. Unannotated code has type `Any`, which is compatible
+/// with every type, so it is only checked when it runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Number,
+    String,
+    Bool,
+    Nil,
+    List,
+    Map,
+    Result,
+    Module,
+    Function(Vec<Type>, Box<Type>),
+    Struct(String),
+}
+
+impl Type {
+    /// The type an annotation stands for, which may name a struct in `known`.
+    pub fn from_expr(expr: &TypeExpr, known: &MethodTable) -> Result<Type, UvlError> {
+        match expr {
+            TypeExpr::Named(_, name) if known.structs.contains_key(name.lexeme) => {
+                Ok(Type::Struct(name.lexeme.to_string()))
+            }
+            TypeExpr::Named(ctx, name) => match name.lexeme {
+                "Any" => Ok(Type::Any),
+                "Number" => Ok(Type::Number),
+                "String" => Ok(Type::String),
+                "Bool" => Ok(Type::Bool),
+                "Nil" => Ok(Type::Nil),
+                "List" => Ok(Type::List),
+                "Map" => Ok(Type::Map),
+                "Result" => Ok(Type::Result),
+                "Module" => Ok(Type::Module),
+                _ => Err(UvlError::new(
+                    ErrorKind::Type,
+                    Diagnostic::error(ctx, format!("Unknown type '{}'", name.lexeme))
+                        .with_label("not a type".to_string())
+                        .with_help(
+                            "the types are Number, String, Bool, Nil, List, Map, Result, Module, Any, `fun(..) -> ..` and structs"
+                                .to_string(),
+                        ),
+                )),
+            },
+            TypeExpr::Function(_, params, return_type) => Ok(Type::Function(
+                params
+                    .iter()
+                    .map(|param| Type::from_expr(param, known))
+                    .collect::<Result<Vec<Type>, UvlError>>()?,
+                Box::new(Type::from_expr(return_type, known)?),
+            )),
+        }
+    }
+
+    /// The annotation's type, `Any` when it names an unknown type, which is
+    /// reported in `errors`.
+    pub fn annotated(
+        annotation: &TypeExpr,
+        known: &MethodTable,
+        errors: &mut Vec<UvlError>,
+    ) -> Type {
+        Type::from_expr(annotation, known).unwrap_or_else(|e| {
+            errors.push(e);
+            Type::Any
+        })
+    }
+
+    /// The annotation's type, `Any` when there is none.
+    pub fn from_annotation(annotation: Option<&TypeExpr>, known: &MethodTable) -> Type {
+        annotation
+            .and_then(|annotation| Type::from_expr(annotation, known).ok())
+            .unwrap_or(Type::Any)
+    }
+
+    /// The type of a value.
+    pub fn of(value: &UvlValue) -> Type {
+        match value {
+            UvlValue::String(_) => Type::String,
+            UvlValue::Number(_) => Type::Number,
+            UvlValue::Bool(_) => Type::Bool,
+            UvlValue::Nil(_) => Type::Nil,
+            UvlValue::List(_) => Type::List,
+            UvlValue::Map(_) => Type::Map,
+            UvlValue::Result(_) => Type::Result,
+            UvlValue::Module(_) => Type::Module,
+            UvlValue::Function(function) => Type::Function(
+                function.param_types.clone(),
+                Box::new(function.return_type.clone()),
+            ),
+            UvlValue::Native(function) => match function.arity {
+                Some(arity) => Type::Function(vec![Type::Any; arity], Box::new(Type::Any)),
+                None => Type::Any,
+            },
+            UvlValue::Struct(instance) => Type::Struct(instance.name.clone()),
+        }
+    }
+
+    /// Whether a value of type `other` may be used where `self` is expected.
+    pub fn accepts(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Function(params, ret), Type::Function(other_params, other_ret)) => {
+                params.len() == other_params.len()
+                    && params
+                        .iter()
+                        .zip(other_params)
+                        .all(|(param, other)| other.accepts(param))
+                    && ret.accepts(other_ret)
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Whether `value` may be used where `self` is expected.
+    pub fn admits(&self, value: &UvlValue) -> bool {
+        self.accepts(&Type::of(value))
+    }
+
+    /// The name impls use for the type, `None` for `Any`.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Type::Any => None,
+            Type::Number => Some("Number"),
+            Type::String => Some("String"),
+            Type::Bool => Some("Bool"),
+            Type::Nil => Some("Nil"),
+            Type::List => Some("List"),
+            Type::Map => Some("Map"),
+            Type::Result => Some("Result"),
+            Type::Module => Some("Module"),
+            Type::Function(..) => Some("Function"),
+            Type::Struct(name) => Some(name.as_str()),
+        }
+    }
+}
+
+/// What the type checkers know about a method before the call runs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MethodLookup {
+    /// A native method with these parameter and return types.
+    Native(Vec<Type>, Type),
+    /// Exports of modules and methods of impls are looked up when the call
+    /// runs, as is any method of a receiver whose type is unknown.
+    Dynamic,
+}
+
+/// The methods impls give each type and the fields of each struct,
+/// collected before the program runs so the checkers can tell overloaded
+/// operators and fields from type errors.
+#[derive(Debug, Clone, Default)]
+pub struct MethodTable {
+    traits: HashMap<String, Vec<String>>,
+    methods: HashMap<String, HashSet<String>>,
+    structs: HashMap<String, Vec<String>>,
+}
+
+impl MethodTable {
+    /// Records the structs, traits and impls declared in `stmts`, nested
+    /// ones included.
+    pub fn scan(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Struct(_, name, fields, _) => {
+                    let names = fields.iter().map(|field| field.name.lexeme.to_string());
+                    self.structs
+                        .insert(name.lexeme.to_string(), names.collect());
+                }
+                Stmt::Trait(_, name, methods, _) => {
+                    let names = methods.iter().map(|m| m.name.lexeme.to_string());
+                    self.traits.insert(name.lexeme.to_string(), names.collect());
+                }
+                Stmt::Impl(_, trait_name, type_name, methods) => {
+                    let inherited = self.traits.get(trait_name.lexeme).cloned();
+                    let provided = self
+                        .methods
+                        .entry(type_name.lexeme.to_string())
+                        .or_default();
+                    provided.extend(inherited.unwrap_or_default());
+                    for method in methods {
+                        if let Stmt::Function(_, name, ..) = method {
+                            provided.insert(name.lexeme.to_string());
+                        }
+                    }
+                }
+                Stmt::Pub(_, stmt) => self.scan(std::slice::from_ref(stmt)),
+                Stmt::Block(_, stmts) => self.scan(stmts),
+                Stmt::Function(.., body, _) => self.scan(body),
+                Stmt::If(_, _, then_branch, else_branch) => {
+                    self.scan(std::slice::from_ref(then_branch));
+                    if let Some(else_branch) = else_branch {
+                        self.scan(std::slice::from_ref(else_branch));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The fields of the struct called `name`, `None` for other types.
+    pub fn fields(&self, name: &str) -> Option<&[String]> {
+        self.structs.get(name).map(Vec::as_slice)
+    }
+
+    /// Reports an impl at `ctx` of `trait_name` for `type_name` unless it
+    /// names a built-in type or a struct. The comparisons of built-in types
+    /// other than Function never call `Eq` or `Ord`, so their impls are
+    /// reported too.
+    pub fn impl_target(
+        &self,
+        ctx: &Ctx,
+        trait_name: &Token,
+        type_name: &Token,
+    ) -> Result<(), UvlError> {
+        if self.structs.contains_key(type_name.lexeme) {
+            return Ok(());
+        }
+        if TYPE_NAMES.contains(&type_name.lexeme) {
+            if type_name.lexeme == "Function" || !["Eq", "Ord"].contains(&trait_name.lexeme) {
+                return Ok(());
+            }
+            return Err(UvlError::new(
+                ErrorKind::Type,
+                Diagnostic::error(
+                    &Ctx::from_token(&ctx.file, trait_name),
+                    format!(
+                        "Trait '{}' cannot be implemented for {}",
+                        trait_name.lexeme, type_name.lexeme
+                    ),
+                )
+                .with_label(format!(
+                    "{} is compared by the built-in operators",
+                    type_name.lexeme
+                ))
+                .with_help(format!(
+                    "consider a struct wrapping the {}",
+                    type_name.lexeme
+                )),
+            ));
+        }
+        Err(UvlError::new(
+            ErrorKind::Name,
+            Diagnostic::error(
+                &Ctx::from_token(&ctx.file, type_name),
+                format!("Unknown type '{}'", type_name.lexeme),
+            )
+            .with_label("not a type".to_string())
+            .with_note(format!(
+                "traits can be implemented for structs and for {}",
+                TYPE_NAMES.join(", ")
+            )),
+        ))
+    }
+
+    /// Whether an impl gives the type called `type_name` a method `method`.
+    pub fn has(&self, type_name: &str, method: &str) -> bool {
+        self.methods
+            .get(type_name)
+            .is_some_and(|methods| methods.contains(method))
+    }
+
+    /// Whether `op` calls an operator method of the type called
+    /// `type_name` instead of the built-in operator. An operand of unknown
+    /// type may be of any type an impl gives the method.
+    pub fn overloads(&self, op: &Token, type_name: Option<&str>, binary: bool) -> bool {
+        match (UvlValue::operator_method(&op.ttype, binary), type_name) {
+            (Some((_, method)), Some(name)) => self.has(name, method),
+            (Some((_, method)), None) => self.methods.values().any(|m| m.contains(method)),
+            _ => false,
+        }
+    }
+
+    /// The method `name` called at `ctx` with `args` arguments on a
+    /// receiver, spanning `receiver`, whose type is called `type_name`.
+    /// Methods of impls take precedence over native methods of the same
+    /// name. Calls with the wrong number of arguments and methods no type
+    /// provides are errors.
+    pub fn method(
+        &self,
+        ctx: &Ctx,
+        receiver: Span,
+        type_name: Option<&str>,
+        name: &Token,
+        args: usize,
+    ) -> Result<MethodLookup, UvlError> {
+        let type_name = match type_name {
+            Some("Module") | None => return Ok(MethodLookup::Dynamic),
+            Some(type_name) if self.has(type_name, name.lexeme) => {
+                return Ok(MethodLookup::Dynamic)
+            }
+            Some(type_name) => type_name,
+        };
+        match methods::signature(type_name, name.lexeme) {
+            Some((params, _)) if params.len() != args => Err(UvlError::new(
+                ErrorKind::Type,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Method '{}' expects {} arguments, got {}",
+                        name.lexeme,
+                        params.len(),
+                        args
+                    ),
+                ),
+            )),
+            Some((params, returns)) => Ok(MethodLookup::Native(params, returns)),
+            None => {
+                let name_ctx = Ctx::from_token(&ctx.file, name);
+                Err(self.missing(&name_ctx, receiver, type_name, name.lexeme))
+            }
+        }
+    }
+
+    /// The error for calling `method` on a type without it, suggesting
+    /// the traits that declare it.
+    fn missing(&self, ctx: &Ctx, receiver: Span, type_name: &str, method: &str) -> UvlError {
+        let mut diagnostic =
+            Diagnostic::error(ctx, format!("{} has no method '{}'", type_name, method))
+                .with_label("method not found".to_string())
+                .with_secondary_label(receiver, type_name.to_string());
+        let mut traits = self
+            .traits
+            .iter()
+            .filter(|(_, methods)| methods.iter().any(|declared| declared == method))
+            .map(|(trait_name, _)| trait_name.as_str())
+            .collect::<Vec<&str>>();
+        traits.sort();
+        for trait_name in traits {
+            diagnostic = diagnostic.with_help(format!(
+                "trait '{}' declares '{}', consider `impl {} for {}`",
+                trait_name, method, trait_name, type_name
+            ));
+        }
+        UvlError::new(ErrorKind::Type, diagnostic)
+    }
+}
+
+/// The error for an operator the built-in types do not support for its
+/// operands, given by their span and rendered type.
+pub fn unsupported_operator(ctx: &Ctx, op: &Token, operands: &[(Span, String)]) -> UvlError {
+    let types = operands
+        .iter()
+        .map(|(_, ty)| ty.as_str())
+        .collect::<Vec<&str>>();
+    let mut diagnostic = Diagnostic::error(
+        ctx,
+        format!(
+            "Operator '{}' is not supported for {}",
+            op.lexeme,
+            types.join(" and ")
+        ),
+    );
+    for (span, ty) in operands {
+        diagnostic = diagnostic.with_secondary_label(*span, ty.clone());
+    }
+    if op.ttype == TokenType::Plus {
+        diagnostic =
+            diagnostic.with_note("'+' adds two Numbers or concatenates two Strings".to_string());
+    }
+    UvlError::new(ErrorKind::Type, diagnostic)
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Number => write!(f, "Number"),
+            Type::String => write!(f, "String"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Nil => write!(f, "Nil"),
+            Type::List => write!(f, "List"),
+            Type::Map => write!(f, "Map"),
+            Type::Result => write!(f, "Result"),
+            Type::Module => write!(f, "Module"),
+            Type::Struct(name) => write!(f, "{}", name),
+            Type::Function(params, ret) => {
+                let params = params.iter().map(Type::to_string).collect::<Vec<String>>();
+                write!(f, "fun({}) -> {}", params.join(", "), ret)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn any_is_compatible_with_everything() {
+        let unary = Type::Function(vec![Type::Number], Box::new(Type::Bool));
+        let dynamic = Type::Function(vec![Type::Any], Box::new(Type::Any));
+
+        assert!(Type::Any.accepts(&Type::Number));
+        assert!(Type::String.accepts(&Type::Any));
+        assert!(!Type::String.accepts(&Type::Number));
+        assert!(unary.accepts(&dynamic));
+        assert!(!unary.accepts(&Type::Function(vec![], Box::new(Type::Bool))));
+        assert_eq!(unary.to_string(), "fun(Number) -> Bool");
+    }
+
+    #[test]
+    fn looks_up_methods_by_receiver_type() {
+        let mut lexer =
+            crate::lexer::Lexer::new("trait T { fun shout(self); }\nimpl T for Number {}");
+        let tokens = lexer.scan().unwrap().clone();
+        let stmts = crate::parser::Parser::new(&tokens, "main.uvl", false)
+            .parse()
+            .unwrap();
+        let mut table = MethodTable::default();
+        table.scan(&stmts);
+        let ctx = Ctx::default();
+        let receiver = Span::new(0, 1);
+        let method = |type_name, name: &str, args| {
+            let name = Token::new(TokenType::Identifier, name, 1, 1, Span::new(0, 0));
+            table
+                .method(&ctx, receiver, type_name, &name, args)
+                .map_err(|e| e.message().to_string())
+        };
+
+        assert_eq!(
+            method(Some("String"), "repeat", 1),
+            Ok(MethodLookup::Native(vec![Type::Number], Type::String))
+        );
+        assert_eq!(
+            method(Some("Number"), "shout", 0),
+            Ok(MethodLookup::Dynamic)
+        );
+        assert_eq!(
+            method(Some("Module"), "anything", 3),
+            Ok(MethodLookup::Dynamic)
+        );
+        assert_eq!(
+            method(Some("String"), "repeat", 0),
+            Err("Method 'repeat' expects 1 arguments, got 0".to_string())
+        );
+        assert_eq!(
+            method(Some("Bool"), "shout", 0),
+            Err("Bool has no method 'shout'".to_string())
+        );
+    }
+}
@@ -1,27 +1,20 @@
-use crate::ast::Ctx;
-use crate::common::make_error_msg;
-use crate::token::TokenType;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::ast::{Ctx, Stmt};
+use crate::diagnostic::Diagnostic;
+use crate::envr::Environment;
+use crate::error::{ErrorKind, UvlError};
+use crate::token::{Token, TokenType};
+use crate::types::Type;
 
 pub type UvlResult = Result<UvlValue, UvlError>;
 
-#[derive(Debug)]
-pub enum UvlError {
-    RuntimeError(String),
-    UnsupportedOperator(String),
-    ParserError(String),
-    NameError(String),
-}
-
-impl std::fmt::Display for UvlError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::RuntimeError(s) => write!(f, "{}", s),
-            Self::ParserError(s) => write!(f, "{}", s),
-            Self::UnsupportedOperator(s) => write!(f, "{}", s),
-            Self::NameError(s) => write!(f, "{}", s),
-        }
-    }
-}
+/// The names `UvlValue::type_str` returns for the built-in types, structs
+/// go by their own name.
+pub const TYPE_NAMES: [&str; 9] = [
+    "Number", "String", "Bool", "Nil", "List", "Map", "Result", "Module", "Function",
+];
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum UvlValue {
@@ -29,6 +22,104 @@ pub enum UvlValue {
     Number(f64),
     Bool(bool),
     Nil(()),
+    List(Rc<Vec<UvlValue>>),
+    /// String keys to values, in key order.
+    Map(Rc<BTreeMap<String, UvlValue>>),
+    /// The outcome of an operation that may fail, such as reading a file.
+    Result(Rc<Result<UvlValue, UvlValue>>),
+    Module(Rc<UvlModule>),
+    Function(Rc<UvlFunction>),
+    Native(Rc<NativeFunction>),
+    Struct(Rc<UvlStruct>),
+}
+
+/// A value of a struct type, with its fields in declaration order.
+#[derive(Debug, PartialEq)]
+pub struct UvlStruct {
+    pub name: String,
+    pub fields: Vec<(String, UvlValue)>,
+}
+
+impl UvlStruct {
+    pub fn field(&self, name: &str) -> Option<&UvlValue> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// An imported module. Its globals live in the interpreter, under `id`.
+#[derive(Debug, PartialEq)]
+pub struct UvlModule {
+    pub id: usize,
+    pub name: String,
+}
+
+/// A user defined function together with the environment it closes over.
+pub struct UvlFunction {
+    pub ctx: Ctx,
+    pub name: String,
+    pub params: Vec<String>,
+    pub param_types: Vec<Type>,
+    pub return_type: Type,
+    /// The module whose globals the body sees.
+    pub module: usize,
+    pub body: Rc<Vec<Stmt<'static>>>,
+    /// The source `body` borrows from, kept alive along with it.
+    pub source: Rc<str>,
+    pub closure: Option<Box<Environment>>,
+}
+
+impl std::fmt::Debug for UvlFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fun {}>", self.name)
+    }
+}
+
+/// Functions are equal only to themselves.
+impl PartialEq for UvlFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// The body of a native function. An `Err` is raised as a RuntimeError at
+/// the call site.
+pub type NativeFn = dyn Fn(&[UvlValue]) -> Result<UvlValue, String>;
+
+/// A function implemented in Rust.
+pub struct NativeFunction {
+    pub name: String,
+    /// The number of arguments, `None` when it takes any number.
+    pub arity: Option<usize>,
+    pub function: Box<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: Option<usize>,
+        function: impl Fn(&[UvlValue]) -> Result<UvlValue, String> + 'static,
+    ) -> Self {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fun {}>", self.name)
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
 }
 
 impl std::fmt::Display for UvlValue {
@@ -38,17 +129,238 @@ impl std::fmt::Display for UvlValue {
             Self::Number(n) => write!(f, "{}", n),
             Self::Bool(b) => write!(f, "{}", b),
             Self::Nil(_) => write!(f, "()"),
+            Self::List(items) => {
+                let items = items
+                    .iter()
+                    .map(UvlValue::to_string)
+                    .collect::<Vec<String>>();
+                write!(f, "[{}]", items.join(", "))
+            }
+            Self::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| format!("\"{}\": {}", key, value))
+                    .collect::<Vec<String>>();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Self::Result(result) => match &**result {
+                Ok(value) => write!(f, "Ok({})", value),
+                Err(error) => write!(f, "Err({})", error),
+            },
+            Self::Module(module) => write!(f, "<module {}>", module.name),
+            Self::Function(function) => write!(f, "<fun {}>", function.name),
+            Self::Native(function) => write!(f, "<native fun {}>", function.name),
+            Self::Struct(instance) => {
+                let fields = instance
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect::<Vec<String>>();
+                match fields.is_empty() {
+                    true => write!(f, "{} {{}}", instance.name),
+                    false => write!(f, "{} {{ {} }}", instance.name, fields.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+impl From<f64> for UvlValue {
+    fn from(n: f64) -> Self {
+        UvlValue::Number(n)
+    }
+}
+
+impl From<i64> for UvlValue {
+    fn from(n: i64) -> Self {
+        UvlValue::Number(n as f64)
+    }
+}
+
+impl From<bool> for UvlValue {
+    fn from(b: bool) -> Self {
+        UvlValue::Bool(b)
+    }
+}
+
+impl From<&str> for UvlValue {
+    fn from(s: &str) -> Self {
+        UvlValue::String(s.to_string())
+    }
+}
+
+impl From<String> for UvlValue {
+    fn from(s: String) -> Self {
+        UvlValue::String(s)
+    }
+}
+
+impl From<()> for UvlValue {
+    fn from(_: ()) -> Self {
+        UvlValue::Nil(())
+    }
+}
+
+/// `None` is nil.
+impl<T: Into<UvlValue>> From<Option<T>> for UvlValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(UvlValue::Nil(()), Into::into)
+    }
+}
+
+impl<T: Into<UvlValue>> From<Vec<T>> for UvlValue {
+    fn from(items: Vec<T>) -> Self {
+        UvlValue::List(Rc::new(items.into_iter().map(Into::into).collect()))
+    }
+}
+
+impl<T: Into<UvlValue>, E: Into<UvlValue>> From<Result<T, E>> for UvlValue {
+    fn from(result: Result<T, E>) -> Self {
+        UvlValue::Result(Rc::new(result.map(Into::into).map_err(Into::into)))
+    }
+}
+
+/// The message of a failed conversion from a uvl value.
+fn expected(type_name: &str, value: &UvlValue) -> String {
+    format!(
+        "Expected {}, found {} of type {}",
+        type_name,
+        value,
+        value.type_str()
+    )
+}
+
+impl TryFrom<UvlValue> for f64 {
+    type Error = String;
+
+    fn try_from(value: UvlValue) -> Result<Self, Self::Error> {
+        match value {
+            UvlValue::Number(n) => Ok(n),
+            value => Err(expected("Number", &value)),
+        }
+    }
+}
+
+/// Only whole Numbers convert.
+impl TryFrom<UvlValue> for i64 {
+    type Error = String;
+
+    fn try_from(value: UvlValue) -> Result<Self, Self::Error> {
+        match value {
+            UvlValue::Number(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(n as i64),
+            value => Err(expected("a whole Number", &value)),
+        }
+    }
+}
+
+impl TryFrom<UvlValue> for bool {
+    type Error = String;
+
+    fn try_from(value: UvlValue) -> Result<Self, Self::Error> {
+        match value {
+            UvlValue::Bool(b) => Ok(b),
+            value => Err(expected("Bool", &value)),
+        }
+    }
+}
+
+impl TryFrom<UvlValue> for String {
+    type Error = String;
+
+    fn try_from(value: UvlValue) -> Result<Self, Self::Error> {
+        match value {
+            UvlValue::String(s) => Ok(s),
+            value => Err(expected("String", &value)),
+        }
+    }
+}
+
+impl TryFrom<UvlValue> for () {
+    type Error = String;
+
+    fn try_from(value: UvlValue) -> Result<Self, Self::Error> {
+        match value {
+            UvlValue::Nil(()) => Ok(()),
+            value => Err(expected("Nil", &value)),
+        }
+    }
+}
+
+/// Nil is `None`, anything else must convert to `T`.
+impl<T: TryFrom<UvlValue, Error = String>> TryFrom<UvlValue> for Option<T> {
+    type Error = String;
+
+    fn try_from(value: UvlValue) -> Result<Self, Self::Error> {
+        match value {
+            UvlValue::Nil(()) => Ok(None),
+            value => T::try_from(value).map(Some),
+        }
+    }
+}
+
+impl<T: TryFrom<UvlValue, Error = String>> TryFrom<UvlValue> for Vec<T> {
+    type Error = String;
+
+    fn try_from(value: UvlValue) -> Result<Self, Self::Error> {
+        match value {
+            UvlValue::List(items) => items.iter().cloned().map(T::try_from).collect(),
+            value => Err(expected("List", &value)),
         }
     }
 }
 
 impl UvlValue {
-    pub fn type_str(&self) -> &'static str {
+    /// The value of a literal token.
+    pub fn from_literal(token: &Token) -> Self {
+        match token.ttype {
+            TokenType::String(s) => UvlValue::String(s.to_string()),
+            TokenType::Number(n) => UvlValue::Number(n),
+            TokenType::True => UvlValue::Bool(true),
+            TokenType::False => UvlValue::Bool(false),
+            _ => UvlValue::Nil(()),
+        }
+    }
+
+    pub fn type_str(&self) -> &str {
         match self {
             UvlValue::String(_) => "String",
             UvlValue::Number(_) => "Number",
             UvlValue::Bool(_) => "Bool",
             UvlValue::Nil(_) => "Nil",
+            UvlValue::List(_) => "List",
+            UvlValue::Map(_) => "Map",
+            UvlValue::Result(_) => "Result",
+            UvlValue::Module(_) => "Module",
+            UvlValue::Function(_) | UvlValue::Native(_) => "Function",
+            UvlValue::Struct(instance) => &instance.name,
+        }
+    }
+
+    /// Whether operators on the value are built in rather than dispatched to
+    /// the operator methods of its type.
+    pub fn is_primitive(&self) -> bool {
+        !matches!(
+            self,
+            UvlValue::Function(_) | UvlValue::Native(_) | UvlValue::Struct(_)
+        )
+    }
+
+    /// The trait and method a binary or unary operator dispatches to.
+    pub fn operator_method(op: &TokenType, binary: bool) -> Option<(&'static str, &'static str)> {
+        match (op, binary) {
+            (TokenType::Plus, true) => Some(("Add", "add")),
+            (TokenType::Minus, true) => Some(("Sub", "sub")),
+            (TokenType::Star, true) => Some(("Mul", "mul")),
+            (TokenType::Slash, true) => Some(("Div", "div")),
+            (TokenType::EqualEqual, true) => Some(("Eq", "eq")),
+            (TokenType::BangEqual, true) => Some(("Eq", "ne")),
+            (TokenType::Less, true) => Some(("Ord", "lt")),
+            (TokenType::LessEqual, true) => Some(("Ord", "le")),
+            (TokenType::Greater, true) => Some(("Ord", "gt")),
+            (TokenType::GreaterEqual, true) => Some(("Ord", "ge")),
+            (TokenType::Minus, false) => Some(("Neg", "neg")),
+            (TokenType::Bang, false) => Some(("Not", "not")),
+            _ => None,
         }
     }
 
@@ -65,10 +377,10 @@ impl UvlValue {
                 TokenType::Minus => self.minus(ctx, rhs),
                 TokenType::Star => self.multi(ctx, rhs),
                 TokenType::Slash => self.divide(ctx, rhs),
-                _ => Err(UvlError::UnsupportedOperator(make_error_msg(
-                    ctx,
-                    format!("{:?} is not supported", op),
-                ))),
+                _ => Err(UvlError::new(
+                    ErrorKind::UnsupportedOperator,
+                    Diagnostic::error(ctx, format!("{:?} is not supported", op)),
+                )),
             }
         } else {
             match op {
@@ -76,20 +388,40 @@ impl UvlValue {
                     if let UvlValue::Number(num) = self {
                         Ok(UvlValue::Number(-num))
                     } else {
-                        Err(UvlError::UnsupportedOperator(make_error_msg(
-                            ctx,
-                            format!(
-                                "Operator '-' is not supported for {} of type {}",
-                                self,
-                                self.type_str()
+                        Err(UvlError::new(
+                            ErrorKind::UnsupportedOperator,
+                            Diagnostic::error(
+                                ctx,
+                                format!(
+                                    "Operator '-' is not supported for {} of type {}",
+                                    self,
+                                    self.type_str()
+                                ),
                             ),
-                        )))
+                        ))
                     }
                 }
-                _ => Err(UvlError::UnsupportedOperator(make_error_msg(
-                    ctx,
-                    format!("Unsupported operator {:?}", op),
-                ))),
+                TokenType::Bang => {
+                    if let UvlValue::Bool(b) = self {
+                        Ok(UvlValue::Bool(!b))
+                    } else {
+                        Err(UvlError::new(
+                            ErrorKind::UnsupportedOperator,
+                            Diagnostic::error(
+                                ctx,
+                                format!(
+                                    "Operator '!' is not supported for {} of type {}",
+                                    self,
+                                    self.type_str()
+                                ),
+                            ),
+                        ))
+                    }
+                }
+                _ => Err(UvlError::new(
+                    ErrorKind::UnsupportedOperator,
+                    Diagnostic::error(ctx, format!("Unsupported operator {:?}", op)),
+                )),
             }
         }
     }
@@ -100,16 +432,20 @@ impl UvlValue {
         } else if let (UvlValue::String(lhs_str), UvlValue::String(rhs_str)) = (self, rhs) {
             Ok(UvlValue::String(format!("{}{}", lhs_str, rhs_str)))
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '+' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
-                ),
-            )))
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '+' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
+                )
+                .with_note("'+' adds two Numbers or concatenates two Strings".to_string()),
+            ))
         }
     }
 
@@ -117,16 +453,19 @@ impl UvlValue {
         if let (UvlValue::Number(lhs_num), UvlValue::Number(rhs_num)) = (self, rhs) {
             Ok(UvlValue::Number(lhs_num - rhs_num))
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '-' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '-' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
                 ),
-            )))
+            ))
         }
     }
 
@@ -134,16 +473,19 @@ impl UvlValue {
         if let (UvlValue::Number(lhs_num), UvlValue::Number(rhs_num)) = (self, rhs) {
             Ok(UvlValue::Number(lhs_num * rhs_num))
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '*' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '*' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
                 ),
-            )))
+            ))
         }
     }
 
@@ -152,22 +494,26 @@ impl UvlValue {
             if *rhs_num != 0.0 {
                 Ok(UvlValue::Number(lhs_num / rhs_num))
             } else {
-                Err(UvlError::RuntimeError(make_error_msg(
-                    ctx,
-                    format!("Division by zero: {}/{}", lhs_num, rhs_num),
-                )))
+                Err(UvlError::new(
+                    ErrorKind::Runtime,
+                    Diagnostic::error(ctx, format!("Division by zero: {}/{}", lhs_num, rhs_num))
+                        .with_label("the divisor evaluates to zero".to_string()),
+                ))
             }
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '/' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '/' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
                 ),
-            )))
+            ))
         }
     }
 
@@ -175,16 +521,19 @@ impl UvlValue {
         if let (UvlValue::Number(lhs_num), UvlValue::Number(rhs_num)) = (self, rhs) {
             Ok(UvlValue::Bool(lhs_num > rhs_num))
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '>' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '>' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
                 ),
-            )))
+            ))
         }
     }
 
@@ -192,16 +541,19 @@ impl UvlValue {
         if let (UvlValue::Number(lhs_num), UvlValue::Number(rhs_num)) = (self, rhs) {
             Ok(UvlValue::Bool(lhs_num >= rhs_num))
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '>=' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '>=' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
                 ),
-            )))
+            ))
         }
     }
 
@@ -209,16 +561,19 @@ impl UvlValue {
         if let (UvlValue::Number(lhs_num), UvlValue::Number(rhs_num)) = (self, rhs) {
             Ok(UvlValue::Bool(lhs_num < rhs_num))
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '<' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '<' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
                 ),
-            )))
+            ))
         }
     }
 
@@ -226,16 +581,19 @@ impl UvlValue {
         if let (UvlValue::Number(lhs_num), UvlValue::Number(rhs_num)) = (self, rhs) {
             Ok(UvlValue::Bool(lhs_num <= rhs_num))
         } else {
-            Err(UvlError::UnsupportedOperator(make_error_msg(
-                ctx,
-                format!(
-                    "Operator '<=' is not supported for {} of type {} and {} of type {}",
-                    self,
-                    self.type_str(),
-                    rhs,
-                    rhs.type_str()
+            Err(UvlError::new(
+                ErrorKind::UnsupportedOperator,
+                Diagnostic::error(
+                    ctx,
+                    format!(
+                        "Operator '<=' is not supported for {} of type {} and {} of type {}",
+                        self,
+                        self.type_str(),
+                        rhs,
+                        rhs.type_str()
+                    ),
                 ),
-            )))
+            ))
         }
     }
 
@@ -243,7 +601,7 @@ impl UvlValue {
         Ok(UvlValue::Bool(self == rhs))
     }
 
-    fn neq(&self, ctx: &Ctx, rhs: &UvlValue) -> Result<UvlValue, UvlError> {
-        self.eq(ctx, rhs)
+    fn neq(&self, _: &Ctx, rhs: &UvlValue) -> Result<UvlValue, UvlError> {
+        Ok(UvlValue::Bool(self != rhs))
     }
 }
@@ -0,0 +1,86 @@
+use uvl::{to_string, ErrorKind, Expr, Lexer, Parser, Stmt, UvlInterpreter, UvlValue};
+
+#[test]
+fn parses_sources_into_syntax_trees() {
+    let mut lexer = Lexer::new("let x = 1 + 2 * 3;\nprintln x;");
+    let tokens = lexer.scan().unwrap().clone();
+    let stmts = Parser::new(&tokens, "main.uvl", false).parse().unwrap();
+
+    assert_eq!(stmts.len(), 2);
+    match &stmts[0] {
+        Stmt::Let(_, _, _, _, initializer, _) => {
+            assert_eq!(to_string(initializer), "(+ 1 (* 2 3))");
+        }
+        stmt => panic!("expected a let statement, found {:?}", stmt),
+    }
+    assert!(matches!(&stmts[1], Stmt::PrintLn(_, expr) if matches!(**expr, Expr::Variable(..))));
+}
+
+#[test]
+fn runs_programs_and_reports_errors() {
+    let mut interp = UvlInterpreter::new(false);
+    interp
+        .run("main.uvl", "fun fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); }\nlet f = fib(15);")
+        .unwrap();
+    assert_eq!(interp.get_global("f"), Some(UvlValue::Number(610.0)));
+
+    let errors = interp.run("main.uvl", "let y = 1 / 0;").unwrap_err();
+    assert_eq!(errors[0].kind(), ErrorKind::Runtime);
+    assert_eq!(errors[0].message(), "Division by zero: 1/0");
+    assert_eq!(errors[0].ctx().line, 1);
+
+    let errors = interp.run("main.uvl", "let = 1;\nlet mut;").unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(errors.iter().all(|e| e.kind() == ErrorKind::Parser));
+}
+
+#[test]
+fn hosts_exchange_values_with_programs() {
+    let mut interp = UvlInterpreter::new(false);
+    interp.register_fn("sum", |args| {
+        let numbers = Vec::<f64>::try_from(args[0].clone())?;
+        Ok(UvlValue::from(numbers.iter().sum::<f64>()))
+    });
+    interp.set_global("words", "a,b,c");
+    interp
+        .run(
+            "main.uvl",
+            "let parts = words.split(\",\");\nfun total(n) { return sum(n); }",
+        )
+        .unwrap();
+
+    let parts = interp.get_global("parts").unwrap();
+    assert_eq!(
+        Vec::<String>::try_from(parts),
+        Ok(vec!["a".to_string(), "b".to_string(), "c".to_string()])
+    );
+    let total = interp
+        .call("total", vec![UvlValue::from(vec![1.5, 2.5])])
+        .unwrap();
+    assert_eq!(f64::try_from(total), Ok(4.0));
+
+    let error = interp.call("missing", vec![]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Name);
+}
+
+#[test]
+fn default_limits_fit_a_main_thread_stack() {
+    // The 8 MiB a main thread usually gets
+    let thread = std::thread::Builder::new().stack_size(8 * 1024 * 1024);
+    let kinds = thread
+        .spawn(|| {
+            let mut interp = UvlInterpreter::new(false);
+            let deep = [
+                "fun r(n) { if n == 0 { return 0; } return r(n - 1) + 1; }\nlet a = r(10000);",
+                "fun g(n) { return h(n); }\nfun h(n) { return g(n); }\nlet b = g(1);",
+            ];
+            deep.iter()
+                .map(|source| interp.run("main.uvl", source).unwrap_err()[0].kind())
+                .collect::<Vec<ErrorKind>>()
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    assert_eq!(kinds, vec![ErrorKind::Recursion, ErrorKind::Recursion]);
+}
@@ -0,0 +1,72 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the `uvl` binary on `source` with the script arguments `args`,
+/// feeding it `input`.
+fn run(source: &str, args: &[&str], input: &str) -> Output {
+    let dir = std::env::temp_dir().join(format!("uvl-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let script = dir.join(format!("{:x}.uvl", hasher.finish()));
+    std::fs::write(&script, source).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_uvl"))
+        .arg(&script)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&script).unwrap();
+    output
+}
+
+#[test]
+fn scripts_get_the_arguments_after_the_file() {
+    let output = run(
+        "io.print_line(env.args().len());\nio.print(env.args()[1]);",
+        &["--deny-warnings", "two words"],
+        "",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "2\ntwo words");
+}
+
+#[test]
+fn scripts_read_stdin_and_write_both_streams() {
+    let output = run(
+        "let first = io.read_line();\nio.eprint_line(first);\nio.print(io.read_all());\nio.print(io.read_line() == nil);",
+        &[],
+        "one\r\ntwo\nthree",
+    );
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "one\n");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "two\nthreetrue");
+}
+
+#[test]
+fn process_exit_sets_the_status() {
+    let output = run(
+        "io.print(\"bye\");\nprocess.exit(3);\nio.print(\"never\");",
+        &[],
+        "",
+    );
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "bye");
+
+    let output = run("process.exit(300);", &[], "");
+    assert_eq!(output.status.code(), Some(70));
+}